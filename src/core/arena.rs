@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use crate::core::structs::Page;
+use crate::error::Result;
+
+/// The pages of an index keyed by block number. Items refer to their child page by block number
+/// through `Item::child`, so every page is stored once and the tree is navigated by lookups.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct PageArena {
+    pages: BTreeMap<i64, Page>,
+    /// Right halves of GiST splits the parent has no downlink for, keyed by the split page.
+    #[serde(skip)]
+    right_halves: HashMap<i64, i64>,
+}
+
+impl PageArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, page: Page) {
+        self.pages.insert(page.id, page);
+    }
+
+    pub fn get(&self, page_id: i64) -> Option<&Page> {
+        self.pages.get(&page_id)
+    }
+
+    pub fn get_mut(&mut self, page_id: i64) -> Option<&mut Page> {
+        self.pages.get_mut(&page_id)
+    }
+
+    /// The pages in block number order.
+    pub fn iter(&self) -> impl Iterator<Item = &Page> {
+        self.pages.values()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Page> {
+        self.pages.values_mut()
+    }
+
+    /// The child pages of `page`, in item order, each followed by the right halves of its splits
+    /// that `page` has no downlink for.
+    pub fn children<'a>(&'a self, page: &'a Page) -> impl Iterator<Item = &'a Page> + 'a {
+        page.items.iter()
+            .filter_map(move |item| self.get(item.child?))
+            .flat_map(move |child| std::iter::successors(Some(child), move |page| self.right_half(page.id)))
+    }
+
+    /// The right sibling `walk` followed from the page `page_id`, split after its parent was
+    /// written.
+    pub fn right_half(&self, page_id: i64) -> Option<&Page> {
+        self.get(*self.right_halves.get(&page_id)?)
+    }
+
+    /// The pages reachable from `root`, grouped by their distance to it: `root` alone first, then
+    /// its children, and so on, each level in item order.
+    pub fn levels(&self, root: i64) -> Vec<Vec<&Page>> {
+        let mut levels: Vec<Vec<&Page>> = vec![];
+        let mut level: Vec<&Page> = self.get(root).into_iter().collect();
+        while !level.is_empty() {
+            let next_level = level.iter().flat_map(|page| self.children(page)).collect();
+            levels.push(level);
+            level = next_level;
+        }
+        levels
+    }
+
+    /// For access methods that don't store a level on the page (GIN, GiST, SP-GiST), derives the
+    /// level of the internal pages under `root` from their children, counting leaves as level 0
+    /// like btree does.
+    pub fn set_levels_from_children(&mut self, root: i64) {
+        let page_ids: Vec<i64> = self.levels(root).into_iter().flatten().map(|page| page.id).collect();
+        for page_id in page_ids.into_iter().rev() {
+            let page = &self.pages[&page_id];
+            if page.is_leaf {
+                continue;
+            }
+            let level = self.children(page).map(|child| child.level + 1).max().unwrap_or(0);
+            self.pages.get_mut(&page_id).unwrap().level = level;
+        }
+    }
+}
+
+/// Fetches the pages under `root` one level at a time, so a level costs a single round trip.
+/// `fetch_level` is given the block numbers of a level along with the page the downlink to each
+/// was read from, returns the pages in the same order, and marks the items to descend into by
+/// setting their `child`. A block reached a second time, through another item or a cycle of
+/// SP-GiST redirects, isn't fetched again and the item's `child` is cleared, so each page hangs
+/// off a single parent. The right sibling of a page that `split_not_in_parent` is fetched with
+/// the same parent in one more call for the level, as a GiST scan follows it, and hangs off that
+/// parent too. The walk stops at the first level `fetch_level` fails on.
+pub fn walk<F: FnMut(&[i64], &[Option<&Page>]) -> Result<Vec<Page>>>(root: i64, fetch_level: F) -> Result<PageArena> {
+    walk_roots(&[root], fetch_level)
+}
+
+/// Like `walk`, for indexes made of several trees (SP-GiST keeps NULL keys apart), which are
+/// fetched side by side into one arena.
+pub fn walk_roots<F: FnMut(&[i64], &[Option<&Page>]) -> Result<Vec<Page>>>(roots: &[i64], mut fetch_level: F) -> Result<PageArena> {
+    let mut arena = PageArena::new();
+    let mut seen: HashSet<i64> = roots.iter().copied().collect();
+    let mut level: Vec<(i64, Option<i64>)> = roots.iter().map(|root| (*root, None)).collect();
+    while !level.is_empty() {
+        let mut next_level = vec![];
+        let mut pending = level;
+        while !pending.is_empty() {
+            let page_ids: Vec<i64> = pending.iter().map(|(page_id, _)| *page_id).collect();
+            let parents: Vec<Option<&Page>> = pending.iter().map(|(_, parent_id)| parent_id.and_then(|parent_id| arena.get(parent_id))).collect();
+            let pages = fetch_level(&page_ids, &parents)?;
+            let mut right_halves = vec![];
+            for (mut page, (_, parent_id)) in pages.into_iter().zip(pending.iter()) {
+                for item in page.items.iter_mut() {
+                    if let Some(child_id) = item.child {
+                        if seen.insert(child_id) {
+                            next_level.push((child_id, Some(page.id)));
+                        } else {
+                            item.child = None;
+                        }
+                    }
+                }
+                if let Some(right_id) = page.next_page_id.filter(|right_id| page.split_not_in_parent() && seen.insert(*right_id)) {
+                    right_halves.push((right_id, *parent_id));
+                    arena.right_halves.insert(page.id, right_id);
+                }
+                arena.insert(page);
+            }
+            pending = right_halves;
+        }
+        level = next_level;
+    }
+    Ok(arena)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::structs::{Item, Page};
+    use crate::db::SPLIT_NOT_IN_PARENT;
+    use super::{walk, walk_roots};
+
+    fn page(page_id: i64, children: &[i64]) -> Page {
+        let mut page = Page::new(page_id, 0, children.is_empty(), page_id == 1, 0, 0);
+        for child_id in children {
+            page.items.push(Item::new(String::new(), Some(*child_id), Some(*child_id), None));
+        }
+        page
+    }
+
+    #[test]
+    pub fn test_walk() {
+        // 4 is linked twice and 5 links back to the root
+        let mut fetched = vec![];
+        let mut arena = walk(1, |page_ids, parents| {
+            fetched.push(page_ids.iter().zip(parents).map(|(page_id, parent)| (*page_id, parent.map(|parent| parent.id))).collect::<Vec<(i64, Option<i64>)>>());
+            Ok(page_ids.iter().map(|page_id| match page_id {
+                1 => page(1, &[2, 3]),
+                2 => page(2, &[4]),
+                3 => page(3, &[4, 5]),
+                5 => page(5, &[1]),
+                _ => page(*page_id, &[]),
+            }).collect())
+        }).unwrap();
+        // one call per level
+        assert_eq!(vec![vec![(1, None)], vec![(2, Some(1)), (3, Some(1))], vec![(4, Some(2)), (5, Some(3))]], fetched);
+        assert_eq!(vec![1, 2, 3, 4, 5], arena.iter().map(|page| page.id).collect::<Vec<i64>>());
+        assert_eq!(vec![None, Some(5)], arena.get(3).unwrap().items.iter().map(|item| item.child).collect::<Vec<Option<i64>>>());
+        assert_eq!(None, arena.get(5).unwrap().items[0].child);
+
+        let levels: Vec<Vec<i64>> = arena.levels(1).iter()
+            .map(|level| level.iter().map(|page| page.id).collect())
+            .collect();
+        assert_eq!(vec![vec![1], vec![2, 3], vec![4, 5]], levels);
+
+        arena.set_levels_from_children(1);
+        assert_eq!(vec![2, 1, 1, 0, 0], arena.iter().map(|page| page.level).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    pub fn test_walk_roots() {
+        let mut fetched = vec![];
+        let arena = walk_roots(&[1, 2], |page_ids, _| {
+            fetched.push(page_ids.to_vec());
+            Ok(page_ids.iter().map(|page_id| match page_id {
+                1 => page(1, &[3]),
+                2 => page(2, &[4, 3]),
+                _ => page(*page_id, &[]),
+            }).collect())
+        }).unwrap();
+        assert_eq!(vec![vec![1, 2], vec![3, 4]], fetched);
+        assert_eq!(vec![vec![1], vec![3]], arena.levels(1).iter().map(|level| level.iter().map(|page| page.id).collect()).collect::<Vec<Vec<i64>>>());
+        assert_eq!(vec![vec![2], vec![4]], arena.levels(2).iter().map(|level| level.iter().map(|page| page.id).collect()).collect::<Vec<Vec<i64>>>());
+    }
+
+    #[test]
+    pub fn test_walk_follows_splits_not_in_parent() {
+        // 2 was split into 2 and 4 after the root was written, and 4 again into 4 and 6
+        let mut fetched = vec![];
+        let arena = walk(1, |page_ids, parents| {
+            fetched.push(page_ids.iter().zip(parents).map(|(page_id, parent)| (*page_id, parent.map(|parent| parent.id))).collect::<Vec<(i64, Option<i64>)>>());
+            Ok(page_ids.iter().map(|page_id| {
+                let mut page = match page_id {
+                    1 => page(1, &[2, 3]),
+                    _ => page(*page_id, &[]),
+                };
+                if *page_id == 2 || *page_id == 4 {
+                    page.next_page_id = Some(page_id + 2);
+                    page.flags.push(SPLIT_NOT_IN_PARENT.to_string());
+                }
+                page
+            }).collect())
+        }).unwrap();
+        // the right halves are fetched in extra calls for the level, with the root as parent
+        assert_eq!(vec![vec![(1, None)], vec![(2, Some(1)), (3, Some(1))], vec![(4, Some(1))], vec![(6, Some(1))]], fetched);
+        let levels: Vec<Vec<i64>> = arena.levels(1).iter()
+            .map(|level| level.iter().map(|page| page.id).collect())
+            .collect();
+        assert_eq!(vec![vec![1], vec![2, 4, 6, 3]], levels);
+    }
+}
@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use log::debug;
+use crate::core::datum::{is_numeric_type, Attribute};
+use crate::core::structs::{BrinIndex, BrinRange};
+use crate::db::{get_brin_heap_blocks, get_brin_metadata_page, get_brin_ranges, Client, IndexInfo};
+use crate::error::Result;
+
+pub fn generate_brin(client: Arc<Mutex<Client>>, index_name: String, index_info: Arc<IndexInfo>) -> Result<BrinIndex> {
+    let metadata_page = get_brin_metadata_page(Arc::clone(&client), index_name.clone())?;
+    debug!("BRIN metadata page: {:?}", metadata_page);
+    let heap_blocks = get_brin_heap_blocks(Arc::clone(&client), index_name.clone())?;
+    let mut ranges = get_brin_ranges(Arc::clone(&client), &metadata_page, heap_blocks, index_name.clone(), index_info.clone())?;
+    count_overlapping_ranges(&mut ranges, &index_info.attributes);
+    Ok(BrinIndex::new(metadata_page, ranges, heap_blocks, index_name, index_info))
+}
+
+/// Splits a minmax summary such as `{1 .. 2512}` into its bounds. Summaries of other opclasses
+/// (inclusion, bloom, minmax-multi) don't have this shape and yield `None`.
+pub fn parse_min_max(value: &str) -> Option<(String, String)> {
+    let inner = value.strip_prefix('{')?.strip_suffix('}')?;
+    if inner.contains(['{', '}']) {
+        return None;
+    }
+    let (min, max) = inner.split_once(" .. ")?;
+    if max.contains(" .. ") {
+        return None;
+    }
+    Some((min.to_string(), max.to_string()))
+}
+
+/// Summaries are only available as text, so bounds of numeric columns are compared as numbers
+/// and the others as strings, which orders ISO dates and timestamps correctly too. Strings are
+/// compared byte by byte, as the "C" collation does; other collations may order a few text
+/// bounds differently than the index did.
+pub fn compare_bounds(a: &str, b: &str, numeric: bool) -> Ordering {
+    if !numeric {
+        return a.cmp(b);
+    }
+    // bounds that aren't numbers, which shouldn't happen, sort after the ones that are to keep
+    // the order total
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Number of bounds in `sorted` that are `<= bound` (or `< bound` when `inclusive` is false).
+fn count_bounds(sorted: &[&str], bound: &str, inclusive: bool, numeric: bool) -> usize {
+    sorted.partition_point(|candidate| match compare_bounds(candidate, bound, numeric) {
+        Ordering::Less => true,
+        Ordering::Equal => inclusive,
+        Ordering::Greater => false,
+    })
+}
+
+/// For every column of every summarized range, counts the other ranges whose min/max interval
+/// intersects it. A scan for any value in such an interval has to read all of those ranges.
+/// `attributes` are the index's, whose types tell how the bounds of each column compare.
+pub fn count_overlapping_ranges(ranges: &mut [BrinRange], attributes: &[Attribute]) {
+    let numeric = |attnum: i32| attributes.get((attnum - 1) as usize)
+        .is_some_and(|attribute| is_numeric_type(attribute.type_oid));
+    let mut bounds: HashMap<i32, (Vec<String>, Vec<String>)> = HashMap::new();
+    for range in ranges.iter() {
+        for summary in range.summaries.iter() {
+            if let (Some(min), Some(max)) = (&summary.min, &summary.max) {
+                let (mins, maxes) = bounds.entry(summary.attnum).or_default();
+                mins.push(min.clone());
+                maxes.push(max.clone());
+            }
+        }
+    }
+    let sorted_bounds: HashMap<i32, (Vec<&str>, Vec<&str>)> = bounds.iter()
+        .map(|(attnum, (mins, maxes))| {
+            let numeric = numeric(*attnum);
+            let mut mins: Vec<&str> = mins.iter().map(|min| min.as_str()).collect();
+            let mut maxes: Vec<&str> = maxes.iter().map(|max| max.as_str()).collect();
+            mins.sort_by(|a, b| compare_bounds(a, b, numeric));
+            maxes.sort_by(|a, b| compare_bounds(a, b, numeric));
+            (*attnum, (mins, maxes))
+        })
+        .collect();
+
+    for range in ranges.iter_mut() {
+        for summary in range.summaries.iter_mut() {
+            let (min, max) = match (&summary.min, &summary.max) {
+                (Some(min), Some(max)) => (min, max),
+                _ => continue,
+            };
+            let (mins, maxes) = &sorted_bounds[&summary.attnum];
+            let numeric = numeric(summary.attnum);
+            // intervals starting at or before our max, minus those ending before our min, minus ourselves
+            let starting_before_max = count_bounds(mins, max, true, numeric);
+            let ending_before_min = count_bounds(maxes, min, false, numeric);
+            summary.overlapping_ranges = starting_before_max.saturating_sub(ending_before_min + 1) as i64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::datum::Attribute;
+    use crate::core::structs::{BrinRange, BrinSummary};
+    use super::{count_overlapping_ranges, parse_min_max};
+
+    fn attribute(type_oid: u32, type_name: &str) -> Attribute {
+        Attribute { name: "value".to_string(), type_oid, type_name: type_name.to_string(), len: -1, align: 'i' }
+    }
+
+    fn range(first_block: i64, min: &str, max: &str) -> BrinRange {
+        BrinRange {
+            first_block,
+            last_block: first_block + 15,
+            summary_tid: None,
+            placeholder: false,
+            summaries: vec![BrinSummary {
+                attnum: 1,
+                column: None,
+                all_nulls: false,
+                has_nulls: false,
+                value: Some(format!("{{{} .. {}}}", min, max)),
+                min: Some(min.to_string()),
+                max: Some(max.to_string()),
+                overlapping_ranges: 0,
+            }],
+        }
+    }
+
+    #[test]
+    pub fn test_parse_min_max() {
+        assert_eq!(Some(("1".to_string(), "2512".to_string())), parse_min_max("{1 .. 2512}"));
+        assert_eq!(
+            Some(("2024-01-01 00:01:00+00".to_string(), "2024-01-02 17:52:00+00".to_string())),
+            parse_min_max("{2024-01-01 00:01:00+00 .. 2024-01-02 17:52:00+00}")
+        );
+        assert_eq!(None, parse_min_max("{192.168.0.0/16 .. f .. f}"));
+        assert_eq!(None, parse_min_max("{mode: hashed  nhashes: 3}"));
+    }
+
+    #[test]
+    pub fn test_count_overlapping_ranges() {
+        let mut ranges = vec![
+            range(0, "1", "100"),
+            range(16, "101", "200"),
+            range(32, "9", "150"),
+            range(48, "1000", "2000"),
+        ];
+        count_overlapping_ranges(&mut ranges, &[attribute(23, "integer")]);
+        let overlaps: Vec<i64> = ranges.iter().map(|range| range.summaries[0].overlapping_ranges).collect();
+        assert_eq!(vec![1, 1, 2, 0], overlaps);
+    }
+
+    #[test]
+    pub fn test_count_overlapping_text_ranges() {
+        // "10" < "5" < "7" < "9" as text, so the second range lies within the first
+        let mut ranges = vec![
+            range(0, "10", "9"),
+            range(16, "5", "7"),
+            range(32, "a", "b"),
+        ];
+        count_overlapping_ranges(&mut ranges, &[attribute(25, "text")]);
+        let overlaps: Vec<i64> = ranges.iter().map(|range| range.summaries[0].overlapping_ranges).collect();
+        assert_eq!(vec![1, 1, 0], overlaps);
+    }
+}
@@ -1,16 +1,449 @@
-use std::cell::RefCell;
-use std::rc::Rc;
 use std::sync::Arc;
-use postgres::Client;
-use crate::db::{get_metadata_page, get_page, IndexInfo};
+use log::{debug, info};
+use crate::core::arena::walk;
+use crate::core::datum::{decode_key, Attribute};
+use crate::core::raw_page::{hex_string, max_align, read_item_pointer, read_u16, read_u32, IndexTuple, RawPage, ITEM_POINTER_SIZE};
+use crate::core::structs::{Deduplication, Item, MetadataPage, Page, Tid};
+use crate::db::{get_file_metadata_page, get_file_page, get_metadata_page, get_pages, IndexInfo, Pool, RelationFile};
+use crate::error::{Error, Result};
 pub use crate::core::structs::Tree;
 
-pub fn generate_btree(client: Arc<RefCell<Client>>, index_name: String, index_info: Rc<IndexInfo>) -> Tree {
-    let metadata_page = get_metadata_page(Arc::clone(&client), index_name.clone());
-    println!("Metadata page: {:?}", metadata_page);
+/// Block number of the metapage.
+pub const BTREE_METAPAGE: i64 = 0;
+/// `BTREE_MAGIC`, stored at the start of the metapage contents.
+const BTREE_MAGIC: u32 = 0x053162;
+/// Offset of the high key on pages that have a right sibling.
+const P_HIKEY: u16 = 1;
+const P_NONE: u32 = 0;
+/// First btree version with `btm_allequalimage`, left false by PostgreSQL 12.
+const BTREE_NOVAC_VERSION: i32 = 4;
+/// Offset of `btm_allequalimage` in the metapage contents, after the cleanup statistics.
+const BTM_ALLEQUALIMAGE_OFFSET: usize = 40;
+
+/// `MAXALIGN(sizeof(BTPageOpaqueData))`, the size of the special space of every btree page.
+const BTREE_OPAQUE_SIZE: usize = 16;
+
+const BTP_LEAF: u16 = 1 << 0;
+const BTP_ROOT: u16 = 1 << 1;
+const BTP_DELETED: u16 = 1 << 2;
+const BTP_META: u16 = 1 << 3;
+const BTP_HALF_DEAD: u16 = 1 << 4;
+const BTP_SPLIT_END: u16 = 1 << 5;
+const BTP_HAS_GARBAGE: u16 = 1 << 6;
+const BTP_INCOMPLETE_SPLIT: u16 = 1 << 7;
+const BTP_HAS_FULLXID: u16 = 1 << 8;
+
+/// `INDEX_AM_RESERVED_BIT` of `t_info`, set on pivot and posting list tuples, whose `t_tid`
+/// then holds the number of attributes or TIDs and the status bits below instead of a heap TID.
+const INDEX_ALT_TID_MASK: u16 = 0x2000;
+const BT_OFFSET_MASK: u16 = 0x0FFF;
+const BT_PIVOT_HEAP_TID_ATTR: u16 = 0x1000;
+const BT_IS_POSTING: u16 = 0x2000;
+
+pub fn generate_btree(pool: &Pool, index_name: String, index_info: Arc<IndexInfo>) -> Result<Tree> {
+    let metadata_page = get_metadata_page(pool.client(), index_name.clone())?;
+    debug!("Metadata page: {:?}", metadata_page);
+    if metadata_page.root == 0 {
+        return Err(Error::EmptyIndex(index_name));
+    }
+    let root = metadata_page.root;
+    let pages = walk(root, |page_ids, _| {
+        info!("getting {} pages", page_ids.len());
+        pool.fetch(page_ids, |client, page_ids| get_pages(client, page_ids, &index_name, index_info.clone()))
+    })?;
+    Ok(Tree::new(metadata_page, root, pages, index_name, index_info))
+}
+
+/// Builds the tree from the relation files alone, for clusters that can't be started. Fails when
+/// block 0 isn't a btree metapage or a block can't be read or decoded, e.g. from a truncated
+/// segment or a zeroed block.
+pub fn generate_btree_from_file(relation: &RelationFile, index_name: String) -> Result<Tree> {
+    let metadata_page = get_file_metadata_page(relation)?;
+    debug!("Metadata page: {:?}", metadata_page);
     if metadata_page.root == 0 {
-        panic!("Root page is not set");
+        return Err(Error::EmptyIndex(index_name));
+    }
+    let root = metadata_page.root;
+    let pages = walk(root, |page_ids, _| page_ids.iter().map(|page_id| get_file_page(relation, *page_id)).collect())?;
+    let index_info = IndexInfo {
+        index_type: "btree".to_string(),
+        columns: vec![],
+        key_columns: 0,
+        attributes: vec![],
+        table_name: String::new(),
+        table_oid: 0,
+        primary_indexed_attributes: vec![],
+    };
+    Ok(Tree::new(metadata_page, root, pages, index_name, Arc::new(index_info)))
+}
+
+/// The `BTPageOpaqueData` kept in the special space of every btree page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BtreePageOpaque {
+    pub prev: u32,
+    pub next: u32,
+    pub level: u32,
+    pub flags: u16,
+    pub cycle_id: u16,
+}
+
+impl BtreePageOpaque {
+    pub fn new(special: &[u8]) -> Option<Self> {
+        Some(Self {
+            prev: read_u32(special, 0)?,
+            next: read_u32(special, 4)?,
+            level: read_u32(special, 8)?,
+            flags: read_u16(special, 12)?,
+            cycle_id: read_u16(special, 14)?,
+        })
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.flags & BTP_LEAF != 0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.flags & BTP_ROOT != 0
+    }
+
+    pub fn is_rightmost(&self) -> bool {
+        self.next == P_NONE
+    }
+
+    pub fn flag_names(&self) -> Vec<String> {
+        [
+            (BTP_LEAF, "leaf"),
+            (BTP_ROOT, "root"),
+            (BTP_DELETED, "deleted"),
+            (BTP_META, "meta"),
+            (BTP_HALF_DEAD, "half_dead"),
+            (BTP_SPLIT_END, "split_end"),
+            (BTP_HAS_GARBAGE, "has_garbage"),
+            (BTP_INCOMPLETE_SPLIT, "incomplete_split"),
+            (BTP_HAS_FULLXID, "has_fullxid"),
+        ].iter()
+            .filter(|(flag, _)| self.flags & flag != 0)
+            .map(|(_, name)| name.to_string())
+            .collect()
+    }
+}
+
+/// Decodes the `BTMetaPageData` stored in block 0, the equivalent of `bt_metap`.
+pub fn decode_metadata_page(bytes: &[u8]) -> Option<MetadataPage> {
+    let raw_page = RawPage::new(bytes)?;
+    let meta = raw_page.contents();
+    if read_u32(meta, 0)? != BTREE_MAGIC {
+        return None;
+    }
+    let mut metadata_page = MetadataPage::new(
+        read_u32(meta, 4)? as i32,
+        read_u32(meta, 8)? as i64,
+        read_u32(meta, 12)? as i64,
+        read_u32(meta, 16)? as i64,
+        read_u32(meta, 20)? as i64,
+    );
+    if metadata_page.version >= BTREE_NOVAC_VERSION {
+        metadata_page.allequalimage = meta.get(BTM_ALLEQUALIMAGE_OFFSET).map(|&byte| byte != 0);
+    }
+    Some(metadata_page)
+}
+
+/// `BTreeTupleIsPosting`: a deduplicated leaf tuple, carrying a list of heap TIDs after its key.
+/// Only indexes built or reindexed on PostgreSQL 13 or later have them.
+pub fn is_posting(tuple: &IndexTuple) -> bool {
+    tuple.info & INDEX_ALT_TID_MASK != 0 && tuple.tid.offset_number & BT_IS_POSTING != 0
+}
+
+/// `BTreeTupleIsPivot`: a high key or internal item. Pivots of indexes created before
+/// PostgreSQL 12 (btree version 3) only have the flag when their `INCLUDE` columns were cut off.
+pub fn is_pivot(tuple: &IndexTuple) -> bool {
+    tuple.info & INDEX_ALT_TID_MASK != 0 && tuple.tid.offset_number & BT_IS_POSTING == 0
+}
+
+/// The heap TIDs of a posting list tuple, in order.
+pub fn posting_list(tuple: &IndexTuple) -> Option<Vec<Tid>> {
+    if !is_posting(tuple) {
+        return None;
+    }
+    let start = tuple.tid.block_number as usize;
+    let count = (tuple.tid.offset_number & BT_OFFSET_MASK) as usize;
+    Some((0..count).filter_map(|i| read_item_pointer(tuple.bytes(), start + i * ITEM_POINTER_SIZE)).collect())
+}
+
+/// `BTreeTupleGetHeapTID`, except that, like `bt_page_items`, tuples in pivot positions that
+/// don't have the pivot flag (pre PostgreSQL 12 indexes) have none, rather than a bogus one.
+pub fn heap_tid(tuple: &IndexTuple, in_pivot_position: bool) -> Option<Tid> {
+    if is_posting(tuple) {
+        return posting_list(tuple)?.into_iter().next();
+    }
+    if is_pivot(tuple) {
+        if tuple.tid.offset_number & BT_PIVOT_HEAP_TID_ATTR == 0 {
+            return None;
+        }
+        return read_item_pointer(tuple.bytes(), tuple.size().checked_sub(ITEM_POINTER_SIZE)?);
+    }
+    if in_pivot_position {
+        return None;
+    }
+    Some(tuple.tid.clone())
+}
+
+/// `BTreeTupleGetNAtts` of a pivot: the key attributes suffix truncation left it. `None` for
+/// tuples without the pivot flag, which have all of them.
+pub fn key_attributes(tuple: &IndexTuple) -> Option<i64> {
+    if !is_pivot(tuple) {
+        return None;
+    }
+    Some((tuple.tid.offset_number & BT_OFFSET_MASK) as i64)
+}
+
+/// The key attributes of a tuple, without the posting list or pivot heap TID stored after them,
+/// as `bt_page_items` shows its `data`.
+pub fn key_bytes<'a>(tuple: &IndexTuple<'a>) -> &'a [u8] {
+    let mut end = tuple.size();
+    if is_posting(tuple) {
+        end = (tuple.tid.block_number as usize).min(end);
+    } else if is_pivot(tuple) && tuple.tid.offset_number & BT_PIVOT_HEAP_TID_ATTR != 0 {
+        end = end.saturating_sub(max_align(ITEM_POINTER_SIZE));
+    }
+    &tuple.bytes()[tuple.data_offset().min(end)..end]
+}
+
+/// Decodes a btree page the way `bt_page_stats` and `bt_page_items` see it. Item values are the
+/// key bytes in hex and `child` isn't set: on internal pages each item's `pointer` is the
+/// downlink, on leaf pages `obj_id` is the heap TID. The high key, if any, is split off into
+/// `high_key`. Leaf items get `dead`, all items `htid` and `key_size`, pivots `key_attributes`
+/// and posting list tuples their `tids`, which leaf pages sum up in `deduplication`. Given the
+/// index's `attributes`, every item also gets its `key` decoded, which then is its `value` too.
+pub fn decode_btree_page(page_id: i64, bytes: &[u8], attributes: &[Attribute]) -> Option<Page> {
+    let raw_page = RawPage::new(bytes)?;
+    // new and zeroed pages have no special space, pages of other access methods a different one
+    if raw_page.special().len() != BTREE_OPAQUE_SIZE {
+        return None;
+    }
+    let opaque = BtreePageOpaque::new(raw_page.special())?;
+    // the metapage holds BTMetaPageData where other pages have line pointers
+    if opaque.flags & BTP_META != 0 {
+        return None;
+    }
+    let mut page = Page::new(page_id, opaque.level as i64, opaque.is_leaf(), opaque.is_root(), opaque.next as i64, opaque.prev as i64);
+    if opaque.prev == P_NONE {
+        page.prev_page_id = None;
+    }
+    if opaque.is_rightmost() {
+        page.next_page_id = None;
+    }
+    page.flags = opaque.flag_names();
+
+    let tuples = raw_page.index_tuples();
+    page.nb_items = Some(tuples.len() as i32);
+    for (offset, tuple) in tuples {
+        let key = key_bytes(&tuple);
+        let value = hex_string(key);
+        if offset == P_HIKEY && !opaque.is_rightmost() {
+            page.high_key = Some(value);
+            continue;
+        }
+        let htid = heap_tid(&tuple, !opaque.is_leaf());
+        let mut item = if opaque.is_leaf() {
+            let mut item = Item::new(value, None, htid.as_ref().map(|htid| htid.block_number as i64), htid.clone());
+            item.dead = Some(tuple.dead);
+            item.tids = posting_list(&tuple);
+            item
+        } else {
+            let mut item = Item::new(value, None, Some(tuple.tid.block_number as i64), None);
+            item.key_attributes = key_attributes(&tuple);
+            item
+        };
+        item.htid = htid;
+        item.key_size = Some(key.len() as i64);
+        if !attributes.is_empty() {
+            let kept = item.key_attributes.map_or(attributes.len(), |kept| kept as usize);
+            let values = decode_key(&tuple, key, &attributes[..kept.min(attributes.len())]);
+            if !values.is_empty() {
+                item.value = values.iter().map(|value| value.value.as_deref().unwrap_or("NULL")).collect::<Vec<&str>>().join(", ");
+            }
+            item.key = Some(values);
+        }
+        page.items.push(item);
+    }
+    if opaque.is_leaf() {
+        page.deduplication = Some(Deduplication::of_items(&page.items));
+    }
+    Some(page)
+}
+
+/// Names the key columns suffix truncation cut off the pivots of an internal page.
+pub fn set_truncated_columns(page: &mut Page, index_info: &IndexInfo) {
+    for item in page.items.iter_mut() {
+        let kept = item.key_attributes.map(|key_attributes| key_attributes as usize);
+        if let Some(columns) = kept.and_then(|kept| index_info.columns.get(kept..index_info.key_columns)) {
+            item.truncated = Some(columns.to_vec());
+        }
+    }
+}
+
+/// Marks the downlinks of an internal page as the children to walk into.
+pub fn link_children(page: &mut Page) {
+    if !page.is_leaf {
+        for item in page.items.iter_mut() {
+            item.child = item.pointer;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Tid;
+    use crate::core::raw_page::IndexTuple;
+    use crate::core::structs::Deduplication;
+    use crate::core::structs::{Item, SuffixTruncation};
+    use crate::core::test_support::{sample_btree, sample_index_info};
+    use super::{decode_btree_page, decode_metadata_page, heap_tid, key_attributes, key_bytes, posting_list, set_truncated_columns, BTREE_MAGIC, BTP_LEAF, BTP_META, BTP_ROOT};
+    use super::{BT_IS_POSTING, BT_PIVOT_HEAP_TID_ATTR, INDEX_ALT_TID_MASK};
+
+    fn page_with_tuples(tuples: &[(u32, u16, &[u8])], prev: u32, next: u32, level: u32, flags: u16) -> Vec<u8> {
+        let mut page = vec![0u8; 8192];
+        let special = 8192 - 16;
+        let mut upper = special;
+        for (i, (block, offset, data)) in tuples.iter().enumerate() {
+            let size = 8 + data.len();
+            upper -= (size + 7) & !7;
+            page[upper..upper + 2].copy_from_slice(&((block >> 16) as u16).to_le_bytes());
+            page[upper + 2..upper + 4].copy_from_slice(&(*block as u16).to_le_bytes());
+            page[upper + 4..upper + 6].copy_from_slice(&offset.to_le_bytes());
+            page[upper + 6..upper + 8].copy_from_slice(&(size as u16).to_le_bytes());
+            page[upper + 8..upper + size].copy_from_slice(data);
+            let lp: u32 = upper as u32 | (1 << 15) | ((size as u32) << 17);
+            page[24 + i * 4..28 + i * 4].copy_from_slice(&lp.to_le_bytes());
+        }
+        page[12..14].copy_from_slice(&((24 + tuples.len() * 4) as u16).to_le_bytes());
+        page[14..16].copy_from_slice(&(upper as u16).to_le_bytes());
+        page[16..18].copy_from_slice(&(special as u16).to_le_bytes());
+        page[18..20].copy_from_slice(&(8192u16 | 4).to_le_bytes());
+        page[special..special + 4].copy_from_slice(&prev.to_le_bytes());
+        page[special + 4..special + 8].copy_from_slice(&next.to_le_bytes());
+        page[special + 8..special + 12].copy_from_slice(&level.to_le_bytes());
+        page[special + 12..special + 14].copy_from_slice(&flags.to_le_bytes());
+        page
+    }
+
+    #[test]
+    pub fn test_decode_metadata_page() {
+        let mut page = vec![0u8; 8192];
+        page[16..18].copy_from_slice(&8176u16.to_le_bytes());
+        for (i, value) in [BTREE_MAGIC, 4, 3, 1, 3, 1].iter().enumerate() {
+            page[24 + i * 4..28 + i * 4].copy_from_slice(&value.to_le_bytes());
+        }
+        page[24 + 40] = 1;
+        page[8176 + 12..8176 + 14].copy_from_slice(&BTP_META.to_le_bytes());
+        let metadata_page = decode_metadata_page(&page).unwrap();
+        assert_eq!((4, 3, 1, 3, 1), (metadata_page.version, metadata_page.root, metadata_page.level, metadata_page.fast_root, metadata_page.fast_level));
+        assert_eq!(Some(true), metadata_page.allequalimage);
+        assert_eq!(None, decode_metadata_page(&vec![0u8; 8192]));
+        assert_eq!(None, decode_btree_page(0, &page, &[]));
+    }
+
+    #[test]
+    pub fn test_decode_leaf_page_with_high_key() {
+        let bytes = page_with_tuples(&[
+            (0, 0, &[0x2a, 0, 0, 0, 0, 0, 0, 0]),
+            (3, 1, &[0x01, 0, 0, 0, 0, 0, 0, 0]),
+            (3, 2, &[0x02, 0, 0, 0, 0, 0, 0, 0]),
+        ], 0, 4, 0, BTP_LEAF);
+        let page = decode_btree_page(2, &bytes, &[]).unwrap();
+        assert!(page.is_leaf);
+        assert!(!page.is_root);
+        assert_eq!(None, decode_btree_page(2, &vec![0u8; 8192], &[]));
+        assert_eq!(None, page.prev_page_id);
+        assert_eq!(Some(4), page.next_page_id);
+        assert_eq!(Some("2a 00 00 00 00 00 00 00".to_string()), page.high_key);
+        assert_eq!(2, page.items.len());
+        assert_eq!(Some(Tid { block_number: 3, offset_number: 2 }), page.items[1].obj_id);
+        assert_eq!("02 00 00 00 00 00 00 00", page.items[1].value);
+        assert_eq!(Some(Deduplication::new(2, 0, 2)), page.deduplication);
+    }
+
+    #[test]
+    pub fn test_decode_root_page() {
+        // the first downlink of an internal page has its key truncated away ("minus infinity")
+        let bytes = page_with_tuples(&[(1, 0, &[]), (2, 1, &[0x2a, 0, 0, 0, 0, 0, 0, 0])], 0, 0, 1, BTP_ROOT);
+        let page = decode_btree_page(3, &bytes, &[]).unwrap();
+        assert!(page.is_root);
+        assert!(!page.is_leaf);
+        assert_eq!(1, page.level);
+        assert_eq!(None, page.high_key);
+        assert_eq!(vec![Some(1), Some(2)], page.items.iter().map(|item| item.pointer).collect::<Vec<Option<i64>>>());
+        assert_eq!("", page.items[0].value);
+        assert_eq!(None, page.deduplication);
+    }
+
+    fn tuple_bytes(block: u32, offset: u16, info: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 8];
+        bytes[0..2].copy_from_slice(&((block >> 16) as u16).to_le_bytes());
+        bytes[2..4].copy_from_slice(&(block as u16).to_le_bytes());
+        bytes[4..6].copy_from_slice(&offset.to_le_bytes());
+        bytes[6..8].copy_from_slice(&((8 + data.len()) as u16 | info).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    pub fn test_decode_posting_and_pivot_tuples() {
+        let tid = |block_number: u32, offset_number: u16| Tid { block_number, offset_number };
+        // key 7, then a posting list of (3,1), (3,2) and (9,4) starting at byte 16
+        let posting = tuple_bytes(16, BT_IS_POSTING | 3, INDEX_ALT_TID_MASK, &[
+            7, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 3, 0, 1, 0, 0, 0, 3, 0, 2, 0, 0, 0, 9, 0, 4, 0,
+        ]);
+        let posting = IndexTuple::new(&posting).unwrap();
+        assert_eq!(Some(vec![tid(3, 1), tid(3, 2), tid(9, 4)]), posting_list(&posting));
+        assert_eq!(Some(tid(3, 1)), heap_tid(&posting, false));
+        assert_eq!(&[7, 0, 0, 0, 0, 0, 0, 0], key_bytes(&posting));
+
+        // downlink to block 5 with one key attribute and the heap TID (2,8) kept as tiebreaker
+        let pivot = tuple_bytes(5, BT_PIVOT_HEAP_TID_ATTR | 1, INDEX_ALT_TID_MASK, &[
+            7, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 2, 0, 8, 0,
+        ]);
+        let pivot = IndexTuple::new(&pivot).unwrap();
+        assert_eq!(None, posting_list(&pivot));
+        assert_eq!(Some(tid(2, 8)), heap_tid(&pivot, true));
+        assert_eq!(Some(1), key_attributes(&pivot));
+        assert_eq!(None, key_attributes(&posting));
+        assert_eq!(&[7, 0, 0, 0, 0, 0, 0, 0], key_bytes(&pivot));
+
+        // a pivot of a version 3 index has no flag and no heap TID
+        let old_pivot = tuple_bytes(5, 1, 0, &[7, 0, 0, 0, 0, 0, 0, 0]);
+        let old_pivot = IndexTuple::new(&old_pivot).unwrap();
+        assert_eq!(None, heap_tid(&old_pivot, true));
+        assert_eq!(Some(tid(5, 1)), heap_tid(&old_pivot, false));
+    }
+
+    #[test]
+    pub fn test_suffix_truncation() {
+        let index_info = sample_index_info(&["a", "b", "c"], 2);
+        let mut tree = sample_btree(index_info.clone());
+        for (leaf, key_size) in [(1, 16), (2, 24)] {
+            let mut item = Item::new(String::new(), None, None, None);
+            item.key_size = Some(key_size);
+            tree.pages.get_mut(leaf).unwrap().items.push(item);
+        }
+        let root = tree.pages.get_mut(3).unwrap();
+        let mut minus_infinity = Item::new(String::new(), Some(1), Some(1), None);
+        minus_infinity.key_size = Some(0);
+        minus_infinity.key_attributes = Some(0);
+        // (a, b) truncated to a, which still needs the heap TID to tell the pages apart
+        let mut pivot = Item::new("2a 00 00 00 00 00 00 00".to_string(), Some(2), Some(2), None);
+        pivot.key_size = Some(8);
+        pivot.key_attributes = Some(1);
+        pivot.htid = Some(Tid { block_number: 7, offset_number: 3 });
+        root.items = vec![minus_infinity, pivot];
+        set_truncated_columns(root, &index_info);
+        assert_eq!(Some(vec!["a".to_string(), "b".to_string()]), root.items[0].truncated);
+        assert_eq!(Some(vec!["b".to_string()]), root.items[1].truncated);
+
+        let expected = SuffixTruncation { level: 1, pivots: 1, truncated_attributes: 1, heap_tids: 1, key_bytes: 16, bytes_saved: 16 };
+        assert_eq!(vec![expected], tree.suffix_truncation());
     }
-    let root = get_page(client.clone(), metadata_page.root, index_name.clone(), index_info.clone());
-    Tree::new(metadata_page, root, index_name, index_info)
 }
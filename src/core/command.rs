@@ -1,12 +1,24 @@
-use std::cell::RefCell;
+use std::io;
 use std::path::Path;
-use std::rc::Rc;
 use std::sync::Arc;
-use crate::core::btree::generate_btree;
-use crate::core::render;
+use crate::core::brin::generate_brin;
+use crate::core::btree::{generate_btree, generate_btree_from_file};
+use crate::core::gin::generate_gin;
+use crate::core::gist::generate_gist;
+use crate::core::hash::generate_hash;
+use crate::core::heap::{hash_with_heap_pages, with_heap_pages, with_heap_rows};
+use crate::core::spgist::generate_spgist;
+use crate::core::{render, render_brin, render_dot, render_hash, render_json, render_svg, render_text, JsonExport};
+use crate::core::text::STDOUT_PATH;
 use crate::db;
-use clap::Parser;
-use log::{error, info};
+use crate::core::structs::{BrinIndex, HashIndex};
+use crate::core::server::{bind, serve};
+use crate::core::tui::{run_tui, Browser};
+use crate::core::Tree;
+use crate::db::{IndexInfo, RelationFile};
+use crate::error::{Error, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use log::info;
 
 /// Postgres CLI args
 #[derive(Parser, Debug)]
@@ -32,28 +44,255 @@ struct Args {
     #[arg(short = 'x', long, default_value = "")]
     password: String,
 
-    /// Postgres index
+    /// Postgres index, only used as a title when reading relation files
+    #[arg(short, long, required_unless_present_any = ["relfilenode", "file"])]
+    index: Option<String>,
+
+    /// Output file path, output.<format> by default
     #[arg(short, long)]
-    index: String,
+    output: Option<String>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Html)]
+    format: Format,
+
+    /// List every item under its page in text output
+    #[arg(long)]
+    items: bool,
+
+    /// Maximum number of heap pages referenced by leaf items to render, none by default
+    #[arg(long, default_value_t = 0, conflicts_with_all = ["file", "relfilenode"])]
+    heap_pages: usize,
+
+    /// Look up the table rows btree leaf items point to, instead of only decoding their keys
+    #[arg(long, conflicts_with_all = ["file", "relfilenode"])]
+    resolve_heap: bool,
+
+    /// Number of rows looked up per query with --resolve-heap
+    #[arg(long, default_value_t = 1000, requires = "resolve_heap")]
+    heap_batch_size: usize,
+
+    /// Maximum number of rows looked up with --resolve-heap
+    #[arg(long, default_value_t = 10000, requires = "resolve_heap")]
+    heap_row_limit: usize,
+
+    /// Run CREATE EXTENSION pageinspect when the database doesn't have it yet
+    #[arg(long, conflicts_with_all = ["file", "relfilenode"])]
+    create_extension: bool,
+
+    /// Number of connections the pages of a tree level are fetched over concurrently
+    #[arg(short, long, default_value_t = 1, conflicts_with_all = ["file", "relfilenode"])]
+    jobs: usize,
+
+    /// Data directory of a stopped or copied cluster to read the btree index from, without connecting
+    #[arg(long, requires = "relfilenode")]
+    data_dir: Option<String>,
+
+    /// Relfilenode of the index in --data-dir, as in pg_class.relfilenode
+    #[arg(long, requires = "data_dir")]
+    relfilenode: Option<u32>,
+
+    /// Path to the first segment file of a btree index, read without connecting
+    #[arg(long, conflicts_with = "data_dir")]
+    file: Option<String>,
+
+    /// Browse the pages of a btree index in the terminal instead of writing an output file
+    #[arg(long, conflicts_with_all = ["output", "format"])]
+    tui: bool,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Serve a btree index over HTTP, the browser fetching pages as they are expanded
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// Interactive HTML page
+    Html,
+    /// JSON document following docs/json_schema.md
+    Json,
+    /// Graphviz description of the page graph
+    Dot,
+    /// The page graph laid out by graphviz, which has to be installed
+    Svg,
+    /// Indented page tree, written to stdout unless --output is given
+    Text,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Json => "json",
+            Format::Dot => "dot",
+            Format::Svg => "svg",
+            Format::Text => "txt",
+        }
+    }
+}
 
-    /// Output file path
-    #[arg(short, long, default_value = "output.html")]
-    output: String,
+impl Args {
+    fn output_path(&self) -> String {
+        if self.format == Format::Text && self.output.is_none() {
+            return STDOUT_PATH.to_string();
+        }
+        self.output.clone().unwrap_or_else(|| format!("output.{}", self.format.extension()))
+    }
 }
 
-pub fn handle_command_call() {
+/// Says on stderr how much of the heap was read, the library itself only logging.
+fn report_heap(tree: &Tree) {
+    if !tree.heap_pages.is_empty() {
+        eprintln!("Rendering {} heap pages", tree.heap_pages.len());
+    }
+    let resolved: Vec<bool> = tree.pages.iter().flat_map(|page| page.items.iter()).filter_map(|item| item.row_visible).collect();
+    if !resolved.is_empty() {
+        eprintln!("Resolved {} heap rows, {} of them not visible", resolved.len(), resolved.iter().filter(|visible| !**visible).count());
+    }
+}
+
+fn output_tree(tree: Tree, format: Format, show_items: bool, output_path: &Path) -> Result<()> {
+    report_heap(&tree);
+    match format {
+        Format::Html => render(tree, output_path),
+        Format::Json => render_json(JsonExport::try_from(&tree)?, output_path),
+        Format::Dot => render_dot(tree, output_path),
+        Format::Svg => render_svg(tree, output_path),
+        Format::Text => render_text(tree, show_items, output_path),
+    }
+}
+
+fn output_brin(index: BrinIndex, format: Format, output_path: &Path) -> Result<()> {
+    match format {
+        Format::Html => render_brin(index, output_path),
+        Format::Json => render_json(JsonExport::try_from(&index)?, output_path),
+        Format::Dot | Format::Svg | Format::Text => Err(unsupported(&index.index_name, "brin", "which has no page tree to draw, use --format html or json")),
+    }
+}
+
+fn output_hash(index: HashIndex, format: Format, output_path: &Path) -> Result<()> {
+    if !index.heap_pages.is_empty() {
+        eprintln!("Rendering {} heap pages", index.heap_pages.len());
+    }
+    match format {
+        Format::Html => render_hash(index, output_path),
+        Format::Json => render_json(JsonExport::try_from(&index)?, output_path),
+        Format::Dot | Format::Svg | Format::Text => Err(unsupported(&index.index_name, "hash", "which has no page tree to draw, use --format html or json")),
+    }
+}
+
+fn unsupported(index: &str, access_method: &str, reason: &'static str) -> Error {
+    Error::UnsupportedAccessMethod { index: index.to_string(), access_method: access_method.to_string(), reason }
+}
+
+/// Fails unless `index` is a btree index, with `reason` saying what only works on those.
+fn require_btree(index: &str, index_info: &IndexInfo, reason: &'static str) -> Result<()> {
+    if index_info.index_type != "btree" {
+        return Err(unsupported(index, &index_info.index_type, reason));
+    }
+    Ok(())
+}
+
+pub fn handle_command_call() -> Result<()> {
     let args = Args::parse();
+    if args.relfilenode.is_some() || args.file.is_some() {
+        return handle_file_call(args);
+    }
+    let output = args.output_path();
 
     // Connect to the database
-    let client_ref = Arc::new(db::init_client(args.host, args.port, args.db,
-                                              args.user, args.password));
-    let index_information = db::get_index_info(Arc::clone(&client_ref), args.index.clone());
-    if index_information.index_type == "btree" {
-        let output_path = Path::new(args.output.as_str());
-        let tree = generate_btree(Arc::clone(&client_ref), args.index, Rc::new(index_information));
-        render(tree, output_path);
-        info!("Output file generated at: {}", args.output);
-    } else {
-        error!("Index type is not btree");
-    }
-}
\ No newline at end of file
+    let pool = db::Pool::connect(args.host, args.port, args.db, args.user, args.password, args.jobs)?;
+    let client_ref = pool.client();
+    let index = args.index.unwrap();
+    let index_information = Arc::new(db::get_index_info(Arc::clone(&client_ref), index.clone())?);
+    let reads_heap = args.command.is_none() && !args.tui && args.heap_pages > 0;
+    let functions = db::pageinspect_functions(&index_information.index_type, reads_heap);
+    db::check_pageinspect(Arc::clone(&client_ref), &functions, args.create_extension)?;
+    if let Some(Commands::Serve { listen }) = &args.command {
+        require_btree(&index, &index_information, "only btree indexes can be served")?;
+        let server = bind(listen)?;
+        eprintln!("Serving {} on http://{}", index, listen);
+        return serve(server, Arc::clone(&client_ref), index, index_information);
+    }
+    if args.tui {
+        require_btree(&index, &index_information, "only btree indexes can be browsed")?;
+        let root = db::get_metadata_page(Arc::clone(&client_ref), index.clone())?.root;
+        let browser = Browser::new(index.clone(), root, |page_id| {
+            db::get_page(Arc::clone(&client_ref), page_id, &index, index_information.clone())
+        });
+        return Ok(run_tui(browser)?);
+    }
+    if args.resolve_heap {
+        require_btree(&index, &index_information, "only the rows of btree leaf items can be resolved")?;
+    }
+    let output_path = Path::new(output.as_str());
+    let heap_pages = |tree| with_heap_pages(Arc::clone(&client_ref), tree, &index_information, args.heap_pages);
+    match index_information.index_type.as_str() {
+        "btree" => {
+            let mut tree = heap_pages(generate_btree(&pool, index, index_information.clone())?)?;
+            if args.resolve_heap {
+                tree = with_heap_rows(Arc::clone(&client_ref), tree, index_information.clone(), args.heap_batch_size, args.heap_row_limit)?;
+            }
+            output_tree(tree, args.format, args.items, output_path)?
+        }
+        "gin" => output_tree(heap_pages(generate_gin(&pool, index, index_information.clone())?)?, args.format, args.items, output_path)?,
+        "gist" => output_tree(heap_pages(generate_gist(&pool, index, index_information.clone())?)?, args.format, args.items, output_path)?,
+        "spgist" => output_tree(heap_pages(generate_spgist(&pool, index, index_information.clone())?)?, args.format, args.items, output_path)?,
+        "brin" => output_brin(generate_brin(Arc::clone(&client_ref), index, index_information.clone())?, args.format, output_path)?,
+        "hash" => output_hash(hash_with_heap_pages(Arc::clone(&client_ref), generate_hash(Arc::clone(&client_ref), index, index_information.clone())?, &index_information, args.heap_pages)?, args.format, output_path)?,
+        index_type => return Err(unsupported(&index, index_type, "only btree, gin, gist, spgist, brin and hash indexes are supported")),
+    };
+    info!("Output file generated at: {}", output);
+    Ok(())
+}
+
+/// Renders a btree index straight from its relation files, for clusters that can't be started.
+fn handle_file_call(args: Args) -> Result<()> {
+    let output = args.output_path();
+    let relation = match (&args.file, &args.data_dir, args.relfilenode) {
+        (Some(file), _, _) => RelationFile::open(Path::new(file)),
+        (None, Some(data_dir), Some(relfilenode)) => RelationFile::find(Path::new(data_dir), relfilenode),
+        _ => unreachable!("clap requires --data-dir and --relfilenode together"),
+    };
+    let relation = relation.map_err(|e| io::Error::new(e.kind(), format!("Could not open the index relation: {}", e)))?;
+    let index = args.index.unwrap_or_else(|| relation.path().display().to_string());
+    if args.command.is_some() {
+        return Err(unsupported(&index, "btree", "serve fetches pages from the database and can't read relation files"));
+    }
+    if args.tui {
+        let root = db::get_file_metadata_page(&relation)?.root;
+        let browser = Browser::new(index, root, |page_id| db::get_file_page(&relation, page_id));
+        return Ok(run_tui(browser)?);
+    }
+    let tree = generate_btree_from_file(&relation, index)?;
+    output_tree(tree, args.format, args.items, Path::new(output.as_str()))?;
+    info!("Output file generated at: {}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::error::ErrorKind;
+    use clap::Parser;
+    use super::Args;
+
+    #[test]
+    pub fn test_file_mode_rejects_database_options() {
+        for option in [&["--resolve-heap"][..], &["--heap-pages", "5"], &["--jobs", "4"], &["--create-extension"]] {
+            let args = [&["inspector", "--file", "16384"][..], option].concat();
+            assert_eq!(ErrorKind::ArgumentConflict, Args::try_parse_from(&args).unwrap_err().kind());
+            let args = [&["inspector", "--data-dir", "data", "--relfilenode", "16384"][..], option].concat();
+            assert_eq!(ErrorKind::ArgumentConflict, Args::try_parse_from(&args).unwrap_err().kind());
+        }
+        assert!(Args::try_parse_from(["inspector", "--file", "16384", "--format", "json"]).is_ok());
+    }
+}
@@ -0,0 +1,386 @@
+use std::fmt;
+use postgres_types::Oid;
+use crate::core::raw_page::{hex_string, read_u16, read_u32, IndexTuple};
+use crate::core::structs::KeyValue;
+
+const BOOL_OID: Oid = 16;
+const NAME_OID: Oid = 19;
+const INT8_OID: Oid = 20;
+const INT2_OID: Oid = 21;
+const INT4_OID: Oid = 23;
+const TEXT_OID: Oid = 25;
+const OID_OID: Oid = 26;
+const FLOAT4_OID: Oid = 700;
+const FLOAT8_OID: Oid = 701;
+const BPCHAR_OID: Oid = 1042;
+const VARCHAR_OID: Oid = 1043;
+const DATE_OID: Oid = 1082;
+const TIMESTAMP_OID: Oid = 1114;
+const TIMESTAMPTZ_OID: Oid = 1184;
+const NUMERIC_OID: Oid = 1700;
+const UUID_OID: Oid = 2950;
+
+/// Days from 1970-01-01 to 2000-01-01, the epoch of dates and timestamps.
+const POSTGRES_EPOCH_DAYS: i64 = 10957;
+const USECS_PER_DAY: i64 = 86_400_000_000;
+
+/// Decimal exponents from which `float4out` and `float8out` switch to exponent form.
+const FLT_DIG: i32 = 6;
+const DBL_DIG: i32 = 15;
+
+const NUMERIC_SIGN_MASK: u16 = 0xC000;
+const NUMERIC_NEG: u16 = 0x4000;
+const NUMERIC_SHORT: u16 = 0x8000;
+const NUMERIC_SPECIAL: u16 = 0xC000;
+const NUMERIC_NAN: u16 = 0xC000;
+const NUMERIC_PINF: u16 = 0xD000;
+const NUMERIC_NINF: u16 = 0xF000;
+const NUMERIC_DSCALE_MASK: u16 = 0x3FFF;
+const NUMERIC_SHORT_SIGN_MASK: u16 = 0x2000;
+const NUMERIC_SHORT_DSCALE_MASK: u16 = 0x1F80;
+const NUMERIC_SHORT_DSCALE_SHIFT: u16 = 7;
+const NUMERIC_SHORT_WEIGHT_SIGN_MASK: u16 = 0x0040;
+const NUMERIC_SHORT_WEIGHT_MASK: u16 = 0x003F;
+
+/// An attribute of the index relation, as `pg_attribute` and `pg_type` describe it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub type_oid: Oid,
+    /// `format_type` of the attribute's type.
+    pub type_name: String,
+    /// `attlen`: the size of fixed length types, -1 for varlena and -2 for C strings.
+    pub len: i16,
+    /// `attalign`: `c`, `s`, `i` or `d`.
+    pub align: char,
+}
+
+/// Whether values of the type are numbers, whose text output orders numerically rather than as
+/// strings.
+pub fn is_numeric_type(type_oid: Oid) -> bool {
+    matches!(type_oid, INT2_OID | INT4_OID | INT8_OID | OID_OID | FLOAT4_OID | FLOAT8_OID | NUMERIC_OID)
+}
+
+/// Decodes the first `attributes` of an index tuple, `key` being its attribute data as
+/// `key_bytes` returns it. Attributes that can't be located, because the bytes don't match
+/// their type, end the key early.
+pub fn decode_key(tuple: &IndexTuple, key: &[u8], attributes: &[Attribute]) -> Vec<KeyValue> {
+    let mut values = vec![];
+    // the data starts MAXALIGNed, so alignment relative to it is alignment within the page
+    let mut offset = 0;
+    for (i, attribute) in attributes.iter().enumerate() {
+        let value = if tuple.has_nulls() && tuple.is_null(i) {
+            None
+        } else {
+            offset = align(key, offset, attribute);
+            let len = match datum_len(key, offset, attribute) {
+                Some(len) => len,
+                None => break,
+            };
+            let datum = &key[offset..offset + len];
+            offset += len;
+            Some(format_value(datum, attribute))
+        };
+        values.push(KeyValue {
+            column: attribute.name.clone(),
+            type_name: attribute.type_name.clone(),
+            value,
+        });
+    }
+    values
+}
+
+/// `att_align_pointer`: varlenas with a 1 byte header are not aligned, and a nonzero byte can
+/// only be the start of one, never padding.
+pub(crate) fn align(bytes: &[u8], offset: usize, attribute: &Attribute) -> usize {
+    if attribute.len == -1 && bytes.get(offset).is_some_and(|byte| *byte != 0) {
+        return offset;
+    }
+    let alignment = match attribute.align {
+        's' => 2,
+        'i' => 4,
+        'd' => 8,
+        _ => 1,
+    };
+    (offset + alignment - 1) & !(alignment - 1)
+}
+
+fn datum_len(bytes: &[u8], offset: usize, attribute: &Attribute) -> Option<usize> {
+    let len = match attribute.len {
+        len if len > 0 => len as usize,
+        -1 => varlena_len(&bytes[offset.min(bytes.len())..])?,
+        -2 => bytes.get(offset..)?.iter().position(|byte| *byte == 0)? + 1,
+        _ => return None,
+    };
+    if offset + len > bytes.len() {
+        return None;
+    }
+    Some(len)
+}
+
+/// Total size of a varlena, header included. External TOAST pointers never show up in index keys.
+fn varlena_len(bytes: &[u8]) -> Option<usize> {
+    let first = *bytes.first()?;
+    if first == 0x01 {
+        None
+    } else if first & 0x01 == 0x01 {
+        Some((first >> 1) as usize)
+    } else {
+        Some((read_u32(bytes, 0)? >> 2) as usize)
+    }
+}
+
+/// The data of a varlena, `None` when it is compressed.
+fn varlena_data(datum: &[u8]) -> Option<&[u8]> {
+    let first = *datum.first()?;
+    if first & 0x01 == 0x01 {
+        datum.get(1..)
+    } else if first & 0x03 == 0x00 {
+        datum.get(4..)
+    } else {
+        None
+    }
+}
+
+/// Renders a datum the way its type's output function would, with timestamps in UTC. Types
+/// without a decoder, and values that don't decode, are shown as hex bytes.
+fn format_value(datum: &[u8], attribute: &Attribute) -> String {
+    let value = match attribute.type_oid {
+        BOOL_OID => datum.first().map(|byte| if *byte != 0 { "true" } else { "false" }.to_string()),
+        INT2_OID => datum.try_into().ok().map(|b| i16::from_le_bytes(b).to_string()),
+        INT4_OID => datum.try_into().ok().map(|b| i32::from_le_bytes(b).to_string()),
+        INT8_OID => datum.try_into().ok().map(|b| i64::from_le_bytes(b).to_string()),
+        OID_OID => datum.try_into().ok().map(|b| u32::from_le_bytes(b).to_string()),
+        FLOAT4_OID => datum.try_into().ok().map(|b| format_float(f32::from_le_bytes(b), FLT_DIG)),
+        FLOAT8_OID => datum.try_into().ok().map(|b| format_float(f64::from_le_bytes(b), DBL_DIG)),
+        NAME_OID => {
+            let end = datum.iter().position(|byte| *byte == 0).unwrap_or(datum.len());
+            String::from_utf8(datum[..end].to_vec()).ok()
+        }
+        TEXT_OID | VARCHAR_OID | BPCHAR_OID => varlena_data(datum).and_then(|data| String::from_utf8(data.to_vec()).ok()),
+        UUID_OID => format_uuid(datum),
+        DATE_OID => datum.try_into().ok().map(|b| format_date(i32::from_le_bytes(b))),
+        TIMESTAMP_OID => datum.try_into().ok().map(|b| format_timestamp(i64::from_le_bytes(b), "")),
+        TIMESTAMPTZ_OID => datum.try_into().ok().map(|b| format_timestamp(i64::from_le_bytes(b), "+00")),
+        NUMERIC_OID => varlena_data(datum).and_then(format_numeric),
+        _ => None,
+    };
+    value.unwrap_or_else(|| hex_string(datum))
+}
+
+/// `float4out`/`float8out` with the default `extra_float_digits`: the shortest digits that
+/// round-trip, in exponent form when the decimal exponent is below -4 or reaches `digits`.
+fn format_float<F: fmt::Display + fmt::LowerExp>(value: F, digits: i32) -> String {
+    let fixed = value.to_string();
+    match fixed.as_str() {
+        "NaN" => return fixed,
+        "inf" => return "Infinity".to_string(),
+        "-inf" => return "-Infinity".to_string(),
+        _ => {}
+    }
+    let scientific = format!("{:e}", value);
+    let (mantissa, exponent) = match scientific.split_once('e').and_then(|(m, e)| Some((m, e.parse::<i32>().ok()?))) {
+        Some(parts) => parts,
+        None => return fixed,
+    };
+    if (-4..digits).contains(&exponent) {
+        fixed
+    } else {
+        format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    }
+}
+
+fn format_uuid(datum: &[u8]) -> Option<String> {
+    if datum.len() != 16 {
+        return None;
+    }
+    let hex: String = datum.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Some(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+}
+
+/// Year, month and day of a day number relative to 1970-01-01, in the proleptic Gregorian
+/// calendar, year 0 being 1 BC.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn format_day(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days + POSTGRES_EPOCH_DAYS);
+    if year <= 0 {
+        format!("{:04}-{:02}-{:02} BC", 1 - year, month, day)
+    } else {
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+fn format_date(days: i32) -> String {
+    match days {
+        i32::MAX => "infinity".to_string(),
+        i32::MIN => "-infinity".to_string(),
+        days => format_day(days as i64),
+    }
+}
+
+fn format_timestamp(usecs: i64, zone: &str) -> String {
+    match usecs {
+        i64::MAX => return "infinity".to_string(),
+        i64::MIN => return "-infinity".to_string(),
+        _ => {}
+    }
+    let day = format_day(usecs.div_euclid(USECS_PER_DAY));
+    let time = usecs.rem_euclid(USECS_PER_DAY);
+    let (secs, fraction) = (time / 1_000_000, time % 1_000_000);
+    let mut text = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    if fraction != 0 {
+        text.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+    }
+    // the era goes after the time, like PostgreSQL prints it
+    match day.strip_suffix(" BC") {
+        Some(day) => format!("{} {}{} BC", day, text, zone),
+        None => format!("{} {}{}", day, text, zone),
+    }
+}
+
+/// `numeric_out` of the data of a numeric varlena: a header in short or long format, then
+/// base 10000 digits.
+fn format_numeric(data: &[u8]) -> Option<String> {
+    let header = read_u16(data, 0)?;
+    let (negative, dscale, weight, digits_offset) = if header & NUMERIC_SIGN_MASK == NUMERIC_SPECIAL {
+        return match header {
+            NUMERIC_NAN => Some("NaN".to_string()),
+            NUMERIC_PINF => Some("Infinity".to_string()),
+            NUMERIC_NINF => Some("-Infinity".to_string()),
+            _ => None,
+        };
+    } else if header & NUMERIC_SIGN_MASK == NUMERIC_SHORT {
+        let mut weight = (header & NUMERIC_SHORT_WEIGHT_MASK) as i16;
+        if header & NUMERIC_SHORT_WEIGHT_SIGN_MASK != 0 {
+            weight |= !(NUMERIC_SHORT_WEIGHT_MASK as i16);
+        }
+        let dscale = (header & NUMERIC_SHORT_DSCALE_MASK) >> NUMERIC_SHORT_DSCALE_SHIFT;
+        (header & NUMERIC_SHORT_SIGN_MASK != 0, dscale, weight, 2)
+    } else {
+        let weight = read_u16(data, 2)? as i16;
+        (header & NUMERIC_SIGN_MASK == NUMERIC_NEG, header & NUMERIC_DSCALE_MASK, weight, 4)
+    };
+    let digits: Vec<i16> = data.get(digits_offset..)?.chunks_exact(2)
+        .map(|chunk| i16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    // digits[i] is worth 10000^(weight - i)
+    let digit = |power: i16| usize::try_from(weight - power).ok().and_then(|i| digits.get(i)).copied().unwrap_or(0);
+
+    let mut text = if negative && !digits.is_empty() { "-".to_string() } else { String::new() };
+    if weight < 0 {
+        text.push('0');
+    } else {
+        text.push_str(&digit(weight).to_string());
+        for power in (0..weight).rev() {
+            text.push_str(&format!("{:04}", digit(power)));
+        }
+    }
+    if dscale > 0 {
+        let mut fraction = String::new();
+        let mut power = -1;
+        while fraction.len() < dscale as usize {
+            fraction.push_str(&format!("{:04}", digit(power)));
+            power -= 1;
+        }
+        text.push('.');
+        text.push_str(&fraction[..dscale as usize]);
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::raw_page::IndexTuple;
+    use super::{decode_key, format_float, format_numeric, format_timestamp, Attribute, DBL_DIG, FLT_DIG};
+
+    fn attribute(name: &str, type_oid: u32, type_name: &str, len: i16, align: char) -> Attribute {
+        Attribute { name: name.to_string(), type_oid, type_name: type_name.to_string(), len, align }
+    }
+
+    #[test]
+    pub fn test_decode_key() {
+        let attributes = [
+            attribute("id", 23, "integer", 4, 'i'),
+            attribute("name", 25, "text", -1, 'i'),
+            attribute("created", 1114, "timestamp without time zone", 8, 'd'),
+            attribute("ref", 2950, "uuid", 16, 'c'),
+        ];
+        // t_info with INDEX_NULL_MASK and 48 bytes, then a bitmap with name left out
+        let mut bytes = vec![0, 0, 1, 0, 1, 0, 48, 0x80, 0b1101, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&42i32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        // 2024-02-29 13:45:06.5
+        bytes.extend_from_slice(&(762_529_506_500_000i64).to_le_bytes());
+        bytes.extend_from_slice(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]);
+        let tuple = IndexTuple::new(&bytes).unwrap();
+        let key = decode_key(&tuple, &bytes[16..], &attributes);
+        let values: Vec<Option<&str>> = key.iter().map(|value| value.value.as_deref()).collect();
+        assert_eq!(vec![Some("42"), None, Some("2024-02-29 13:45:06.5"), Some("12345678-9abc-def0-1234-56789abcdef0")], values);
+        assert_eq!("name", key[1].column);
+
+        // a short varlena right after an int2, where a 4 byte header would have been aligned
+        let attributes = [attribute("n", 21, "smallint", 2, 's'), attribute("s", 25, "text", -1, 'i')];
+        let bytes = [0, 0, 1, 0, 1, 0, 16, 0, 7, 0, 0x09, b'a', b'b', b'c', 0, 0];
+        let tuple = IndexTuple::new(&bytes).unwrap();
+        let key = decode_key(&tuple, &bytes[8..], &attributes);
+        assert_eq!(vec![Some("7"), Some("abc")], key.iter().map(|value| value.value.as_deref()).collect::<Vec<Option<&str>>>());
+    }
+
+    #[test]
+    pub fn test_format_numeric() {
+        let numeric = |header: u16, rest: &[i16]| {
+            let mut data = header.to_le_bytes().to_vec();
+            for value in rest {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            format_numeric(&data).unwrap()
+        };
+        // short format: 12345.670, weight 1, dscale 3
+        assert_eq!("12345.670", numeric(0x8000 | (3 << 7) | 1, &[1, 2345, 6700]));
+        // short format: -0.0012, weight -1, dscale 4
+        assert_eq!("-0.0012", numeric(0x8000 | 0x2000 | (4 << 7) | 0x7F, &[12]));
+        assert_eq!("0", numeric(0x8000, &[]));
+        // long format: -100000000, weight 2
+        assert_eq!("-100000000", numeric(0x4000, &[2, 1]));
+        assert_eq!("NaN", numeric(0xC000, &[]));
+        assert_eq!("-Infinity", numeric(0xF000, &[]));
+    }
+
+    #[test]
+    pub fn test_format_float() {
+        assert_eq!("Infinity", format_float(f64::INFINITY, DBL_DIG));
+        assert_eq!("-Infinity", format_float(f32::NEG_INFINITY, FLT_DIG));
+        assert_eq!("NaN", format_float(f64::NAN, DBL_DIG));
+        assert_eq!("-0", format_float(-0.0f64, DBL_DIG));
+        assert_eq!("1e+20", format_float(1e20f64, DBL_DIG));
+        assert_eq!("100000000000000", format_float(1e14f64, DBL_DIG));
+        assert_eq!("1.2345e+15", format_float(1.2345e15f64, DBL_DIG));
+        assert_eq!("0.0001", format_float(0.0001f64, DBL_DIG));
+        assert_eq!("1.5e-05", format_float(1.5e-5f64, DBL_DIG));
+        assert_eq!("1e-300", format_float(1e-300f64, DBL_DIG));
+        assert_eq!("0.1", format_float(0.1f32, FLT_DIG));
+        assert_eq!("100000", format_float(1e5f32, FLT_DIG));
+        assert_eq!("1e+06", format_float(1e6f32, FLT_DIG));
+        assert_eq!("3.4028235e+38", format_float(f32::MAX, FLT_DIG));
+    }
+
+    #[test]
+    pub fn test_format_timestamp() {
+        assert_eq!("2000-01-01 00:00:00", format_timestamp(0, ""));
+        assert_eq!("1999-12-31 23:59:59.999999+00", format_timestamp(-1, "+00"));
+        assert_eq!("0001-12-31 00:00:00 BC", format_timestamp(-730_120 * 86_400_000_000, ""));
+        assert_eq!("infinity", format_timestamp(i64::MAX, ""));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use crate::core::renderer::write_output;
+use crate::core::structs::{Page, Tree};
+use crate::error::Result;
+
+/// Items without a child shown per page, the rest are summarized so leaves stay readable.
+const DOT_MAX_ITEMS: usize = 8;
+/// Keys are cut after this many characters.
+const DOT_MAX_VALUE_LENGTH: usize = 24;
+
+/// Graphviz description of the tree: a record node per page listing its items, an edge from every
+/// item to the child page it points to, and dashed edges between siblings of a level.
+pub fn generate_dot(tree: &Tree) -> String {
+    let pages: BTreeMap<i64, &Page> = tree.pages.iter()
+        .chain(tree.pending_list.iter())
+        .map(|page| (page.id, page))
+        .collect();
+
+    let mut dot = format!("digraph \"{}\" {{\n", escape_string(&tree.index_name));
+    dot.push_str("    rankdir=TB;\n");
+    dot.push_str("    node [shape=record, fontname=\"monospace\", fontsize=10];\n");
+    dot.push_str("    edge [arrowsize=0.6];\n");
+
+    for page in pages.values() {
+        dot.push_str(&format!("    page{} [label=\"{{{}|{{{}}}}}\"];\n", page.id, page_title(page), page_items(page)));
+    }
+
+    let mut levels: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+    for page in pages.values() {
+        levels.entry(page.level).or_default().push(page.id);
+    }
+    for page_ids in levels.values() {
+        let nodes: Vec<String> = page_ids.iter().map(|page_id| format!("page{}", page_id)).collect();
+        dot.push_str(&format!("    {{ rank=same; {}; }}\n", nodes.join("; ")));
+    }
+
+    for page in pages.values() {
+        for (i, item) in page.items.iter().enumerate() {
+            if let Some(child_id) = item.child {
+                dot.push_str(&format!("    page{}:i{} -> page{};\n", page.id, i, child_id));
+            }
+        }
+    }
+
+    for page in pages.values() {
+        if let Some(next) = sibling(page, page.next_page_id, &pages) {
+            // a single edge with both arrows when the right page links back
+            let dir = if next.prev_page_id == Some(page.id) { ", dir=both" } else { "" };
+            dot.push_str(&format!("    page{} -> page{} [style=dashed, constraint=false{}];\n", page.id, next.id, dir));
+        }
+        if let Some(prev) = sibling(page, page.prev_page_id, &pages) {
+            if prev.next_page_id != Some(page.id) {
+                dot.push_str(&format!("    page{} -> page{} [style=dashed, constraint=false];\n", page.id, prev.id));
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// The sibling `page_id` points to, if it was inspected and sits on the same level. Access
+/// methods without sibling links leave 0 there, which may well be another page.
+fn sibling<'a>(page: &Page, page_id: Option<i64>, pages: &BTreeMap<i64, &'a Page>) -> Option<&'a Page> {
+    let sibling = *pages.get(&page_id?)?;
+    if sibling.id == page.id || sibling.level != page.level {
+        return None;
+    }
+    Some(sibling)
+}
+
+fn page_title(page: &Page) -> String {
+    let mut title = format!("Page {} (level {}", page.id, page.level);
+    if page.is_root {
+        title.push_str(", root");
+    }
+    if page.is_leaf {
+        title.push_str(", leaf");
+    }
+    title.push(')');
+    if let Some(high_key) = &page.high_key {
+        title.push_str(&format!("\\nhigh key: {}", escape_record(&truncate(high_key))));
+    }
+    title
+}
+
+/// The record fields of the items. Items with a child always get a field, so the downlink has a
+/// port to start from.
+fn page_items(page: &Page) -> String {
+    let mut fields = vec![];
+    let mut shown = 0;
+    for (i, item) in page.items.iter().enumerate() {
+        if item.child.is_none() {
+            if shown == DOT_MAX_ITEMS {
+                continue;
+            }
+            shown += 1;
+        }
+        let value = match &item.state {
+            Some(state) => state.clone(),
+            None => truncate(&item.value),
+        };
+        fields.push(format!("<i{}> {}", i, escape_record(&value)));
+    }
+    let hidden = page.items.len() - fields.len();
+    if hidden > 0 {
+        fields.push(format!("... {} more items", hidden));
+    }
+    if fields.is_empty() {
+        fields.push("no items".to_string());
+    }
+    fields.join("|")
+}
+
+fn truncate(value: &str) -> String {
+    if value.chars().count() <= DOT_MAX_VALUE_LENGTH {
+        return value.to_string();
+    }
+    format!("{}...", value.chars().take(DOT_MAX_VALUE_LENGTH).collect::<String>())
+}
+
+fn escape_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escapes the characters that structure record labels, on top of those of a quoted string.
+fn escape_record(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '{' | '}' | '|' | '<' | '>' | '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn render_dot(tree: Tree, output_path: &Path) -> Result<()> {
+    write_output(generate_dot(&tree), output_path)
+}
+
+/// Lays the graph out with graphviz's `dot`, which has to be on the PATH.
+pub fn render_svg(tree: Tree, output_path: &Path) -> Result<()> {
+    let mut dot = Command::new("dot")
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("Unable to run graphviz dot, is it installed? {}", e)))?;
+    dot.stdin.take().unwrap().write_all(generate_dot(&tree).as_bytes())?;
+    let output = dot.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("graphviz dot failed: {}", String::from_utf8_lossy(&output.stderr))).into());
+    }
+    write_output(String::from_utf8_lossy(&output.stdout).to_string(), output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::structs::Item;
+    use crate::core::test_support::{sample_btree, sample_index_info};
+    use super::{escape_record, generate_dot};
+
+    #[test]
+    pub fn test_generate_dot() {
+        let mut tree = sample_btree(sample_index_info(&["id"], 1));
+        let leaf_a = tree.pages.get_mut(1).unwrap();
+        for i in 0..10 {
+            leaf_a.items.push(Item::new(format!("{{{}}}", i), None, Some(0), None));
+        }
+
+        let dot = generate_dot(&tree);
+        assert!(dot.starts_with("digraph \"t_id\" {"));
+        assert!(dot.contains("page3:i0 -> page1;"));
+        assert!(dot.contains("page3:i1 -> page2;"));
+        assert!(dot.contains("page1 -> page2 [style=dashed, constraint=false, dir=both];"));
+        assert!(!dot.contains("page2 -> page1"));
+        assert!(dot.contains("{ rank=same; page1; page2; }"));
+        assert!(dot.contains("<i7> \\{7\\}|... 2 more items"));
+    }
+
+    #[test]
+    pub fn test_escape_record() {
+        assert_eq!("\\{a\\|b\\}\\<c\\>", escape_record("{a|b}<c>"));
+    }
+}
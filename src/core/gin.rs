@@ -0,0 +1,263 @@
+use std::sync::Arc;
+use log::debug;
+use crate::core::arena::walk;
+use crate::core::datum::{align, Attribute};
+use crate::core::raw_page::{format_datum, read_block_id, read_item_pointer, read_u16, IndexTuple, ITEM_POINTER_SIZE, INVALID_BLOCK_NUMBER};
+use crate::core::structs::{Tid, Tree};
+use crate::db::{get_gin_metadata_page, get_gin_pages, get_gin_pending_list, IndexInfo, Pool};
+use crate::error::Result;
+
+/// Block number of the entry tree root, GIN keeps it fixed right after the metapage.
+pub const GIN_ROOT_BLKNO: i64 = 1;
+/// Offset number marking an entry tuple whose postings live in a separate posting tree.
+const GIN_TREE_POSTING: u16 = 0xFFFF;
+const GIN_ITUP_COMPRESSED: u32 = 1 << 31;
+const MAX_HEAP_TUPLES_PER_PAGE_BITS: u32 = 11;
+/// Size of the `GinPostingList` header: the first item pointer plus `nbytes`.
+const GIN_POSTING_LIST_HEADER_SIZE: usize = 8;
+/// Size of a `PostingItem` on a posting tree internal page: child block id plus key item pointer.
+const POSTING_ITEM_SIZE: usize = 10;
+
+pub fn generate_gin(pool: &Pool, index_name: String, index_info: Arc<IndexInfo>) -> Result<Tree> {
+    let metadata_page = get_gin_metadata_page(pool.client(), index_name.clone())?;
+    debug!("GIN metadata page: {:?}", metadata_page);
+    let mut pages = walk(GIN_ROOT_BLKNO, |page_ids, _| {
+        pool.fetch(page_ids, |client, page_ids| get_gin_pages(client, page_ids, &index_name, index_info.clone()))
+    })?;
+    pages.set_levels_from_children(GIN_ROOT_BLKNO);
+    let pending_list = if metadata_page.pending_head == INVALID_BLOCK_NUMBER {
+        vec![]
+    } else {
+        get_gin_pending_list(pool.client(), metadata_page.pending_head, index_name.clone(), index_info.clone())?
+    };
+    Ok(Tree::new_gin(metadata_page, GIN_ROOT_BLKNO, pages, pending_list, index_name, index_info))
+}
+
+/// Where an entry tuple on a leaf page keeps the heap TIDs for its key.
+#[derive(Debug, PartialEq)]
+pub enum GinPostings {
+    List(Vec<Tid>),
+    Tree(u32),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GinEntry {
+    pub key: String,
+    pub downlink: Option<u32>,
+    pub postings: Option<GinPostings>,
+}
+
+fn item_pointer_to_u64(tid: &Tid) -> u64 {
+    ((tid.block_number as u64) << MAX_HEAP_TUPLES_PER_PAGE_BITS) | tid.offset_number as u64
+}
+
+fn u64_to_item_pointer(value: u64) -> Tid {
+    Tid {
+        block_number: (value >> MAX_HEAP_TUPLES_PER_PAGE_BITS) as u32,
+        offset_number: (value & ((1 << MAX_HEAP_TUPLES_PER_PAGE_BITS) - 1)) as u16,
+    }
+}
+
+fn decode_varbyte(bytes: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*position)?;
+        *position += 1;
+        if shift == 42 {
+            // the seventh byte carries no continuation bit
+            return Some(value | (byte as u64) << shift);
+        }
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Decodes consecutive compressed `GinPostingList` segments, stopping after `limit` TIDs when given.
+pub fn decode_posting_lists(bytes: &[u8], limit: Option<usize>) -> Vec<Tid> {
+    let mut tids = vec![];
+    let mut offset = 0;
+    while limit.map_or(true, |limit| tids.len() < limit) {
+        let first = match read_item_pointer(bytes, offset) {
+            Some(first) if first.block_number != 0 || first.offset_number != 0 => first,
+            _ => break,
+        };
+        let nbytes = match read_u16(bytes, offset + ITEM_POINTER_SIZE) {
+            Some(nbytes) => nbytes as usize,
+            None => break,
+        };
+        let start = offset + GIN_POSTING_LIST_HEADER_SIZE;
+        let end = (start + nbytes).min(bytes.len());
+        let mut value = item_pointer_to_u64(&first);
+        tids.push(first);
+        let mut position = start;
+        while position < end {
+            match decode_varbyte(&bytes[..end], &mut position) {
+                Some(delta) => {
+                    value += delta;
+                    tids.push(u64_to_item_pointer(value));
+                }
+                None => break,
+            }
+        }
+        // segments are SHORTALIGNed
+        offset = start + nbytes + (nbytes & 1);
+    }
+    if let Some(limit) = limit {
+        tids.truncate(limit);
+    }
+    tids
+}
+
+/// Decodes an array of uncompressed `ItemPointerData`, as written before PostgreSQL 9.4.
+pub fn decode_item_pointers(bytes: &[u8], count: usize) -> Vec<Tid> {
+    (0..count)
+        .filter_map(|i| read_item_pointer(bytes, i * ITEM_POINTER_SIZE))
+        .collect()
+}
+
+/// Decodes the `PostingItem` array of a posting tree internal page into `(child block, key)` pairs.
+/// `contents` starts right after the page header, where the page's right bound is stored.
+pub fn decode_posting_items(contents: &[u8], maxoff: usize) -> Vec<(u32, Tid)> {
+    let data = crate::core::raw_page::max_align(ITEM_POINTER_SIZE);
+    (0..maxoff)
+        .filter_map(|i| {
+            let offset = data + i * POSTING_ITEM_SIZE;
+            Some((read_block_id(contents, offset)?, read_item_pointer(contents, offset + 4)?))
+        })
+        .collect()
+}
+
+/// Where the key datum starts: multi-column indexes prefix every key with its int2 attribute
+/// number, after which the key is aligned to the `typalign` of that attribute.
+fn key_offset(tuple: &IndexTuple, attributes: &[Attribute]) -> usize {
+    let offset = tuple.data_offset();
+    if attributes.len() <= 1 {
+        return offset;
+    }
+    let attnum = read_u16(tuple.bytes(), offset).unwrap_or(0) as usize;
+    match attnum.checked_sub(1).and_then(|i| attributes.get(i)) {
+        Some(attribute) => align(tuple.bytes(), offset + 2, attribute),
+        None => offset + 2,
+    }
+}
+
+fn null_category(tuple: &IndexTuple, attributes: &[Attribute]) -> String {
+    let offset = tuple.data_offset() + if attributes.len() <= 1 { 0 } else { 2 };
+    match tuple.bytes().get(offset) {
+        Some(1) => "NULL key".to_string(),
+        Some(2) => "empty item".to_string(),
+        Some(3) => "NULL item".to_string(),
+        _ => "NULL".to_string(),
+    }
+}
+
+fn format_key(tuple: &IndexTuple, key_end: usize, attributes: &[Attribute]) -> String {
+    if tuple.has_nulls() {
+        return null_category(tuple, attributes);
+    }
+    let start = key_offset(tuple, attributes);
+    match tuple.bytes().get(start..key_end) {
+        Some(key) => format_datum(key),
+        None => "".to_string(),
+    }
+}
+
+/// Decodes a tuple from an entry tree page of an index with `attributes`. On internal pages
+/// `t_tid` holds the downlink, on leaves it describes either an inline posting list or the root
+/// of a posting tree.
+pub fn decode_entry_tuple(tuple: &IndexTuple, is_leaf: bool, attributes: &[Attribute]) -> GinEntry {
+    if !is_leaf {
+        return GinEntry {
+            key: format_key(tuple, tuple.size(), attributes),
+            downlink: Some(tuple.tid.block_number),
+            postings: None,
+        };
+    }
+    if tuple.tid.offset_number == GIN_TREE_POSTING {
+        return GinEntry {
+            key: format_key(tuple, tuple.size(), attributes),
+            downlink: None,
+            postings: Some(GinPostings::Tree(tuple.tid.block_number)),
+        };
+    }
+    let n_posting = tuple.tid.offset_number as usize;
+    let posting_offset = (tuple.tid.block_number & !GIN_ITUP_COMPRESSED) as usize;
+    let posting_bytes = tuple.bytes().get(posting_offset..).unwrap_or(&[]);
+    let tids = if tuple.tid.block_number & GIN_ITUP_COMPRESSED != 0 {
+        decode_posting_lists(posting_bytes, Some(n_posting))
+    } else {
+        decode_item_pointers(posting_bytes, n_posting)
+    };
+    GinEntry {
+        key: format_key(tuple, posting_offset.min(tuple.size()), attributes),
+        downlink: None,
+        postings: Some(GinPostings::List(tids)),
+    }
+}
+
+/// Decodes a pending list tuple, which pairs a key with the heap TID in `t_tid`.
+pub fn decode_pending_tuple(tuple: &IndexTuple, attributes: &[Attribute]) -> (String, Tid) {
+    (format_key(tuple, tuple.size(), attributes), tuple.tid.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Tid;
+    use crate::core::datum::Attribute;
+    use crate::core::raw_page::{hex_string, IndexTuple};
+    use super::{decode_entry_tuple, decode_posting_items, decode_posting_lists, GinPostings};
+
+    fn tid(block_number: u32, offset_number: u16) -> Tid {
+        Tid { block_number, offset_number }
+    }
+
+    #[test]
+    pub fn test_decode_posting_list() {
+        // first (0,1), then deltas of 1 and (1 << 11) - 1 + 2, i.e. (0,2) and (1,3)
+        let mut bytes = vec![0, 0, 0, 0, 1, 0, 3, 0, 0x01, 0x81, 0x10];
+        bytes.push(0);
+        let tids = decode_posting_lists(&bytes, None);
+        assert_eq!(vec![tid(0, 1), tid(0, 2), tid(1, 3)], tids);
+        assert_eq!(vec![tid(0, 1), tid(0, 2)], decode_posting_lists(&bytes, Some(2)));
+    }
+
+    #[test]
+    pub fn test_decode_entry_tuple_with_posting_tree() {
+        // t_tid = (5, 0xffff), t_info = 12, short varlena 'ab'
+        let bytes = vec![0, 0, 5, 0, 0xff, 0xff, 12, 0, 0x07, b'a', b'b', 0];
+        let tuple = IndexTuple::new(&bytes).unwrap();
+        let entry = decode_entry_tuple(&tuple, true, &[]);
+        assert_eq!("ab", entry.key);
+        assert_eq!(Some(GinPostings::Tree(5)), entry.postings);
+    }
+
+    #[test]
+    pub fn test_decode_entry_tuple_with_double_aligned_key() {
+        let attribute = |name: &str, type_oid: u32, len: i16, align: char| Attribute {
+            name: name.to_string(),
+            type_oid,
+            type_name: name.to_string(),
+            len,
+            align,
+        };
+        let attributes = [attribute("t", 25, -1, 'i'), attribute("n", 20, 8, 'd')];
+        // downlink 3, t_info = 24, attnum 2 padded up to the int8 at offset 16
+        let mut bytes = vec![0, 0, 3, 0, 1, 0, 24, 0, 2, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&256i64.to_le_bytes());
+        let tuple = IndexTuple::new(&bytes).unwrap();
+        let entry = decode_entry_tuple(&tuple, false, &attributes);
+        assert_eq!(Some(3), entry.downlink);
+        assert_eq!(hex_string(&256i64.to_le_bytes()), entry.key);
+    }
+
+    #[test]
+    pub fn test_decode_posting_items() {
+        let mut contents = vec![0u8; 8];
+        contents.extend_from_slice(&[0, 0, 3, 0, 0, 0, 9, 0, 4, 0]);
+        assert_eq!(vec![(3, tid(9, 4))], decode_posting_items(&contents, 1));
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+use crate::core::arena::walk;
+use crate::core::structs::{BoundingBox, Tree};
+use crate::db::{get_gist_pages, IndexInfo, Pool};
+use crate::error::Result;
+
+/// Block number of the GiST root, which never moves.
+pub const GIST_ROOT_BLKNO: i64 = 0;
+
+pub fn generate_gist(pool: &Pool, index_name: String, index_info: Arc<IndexInfo>) -> Result<Tree> {
+    let mut pages = walk(GIST_ROOT_BLKNO, |page_ids, parents| {
+        let requests: Vec<(i64, Option<u64>)> = page_ids.iter().zip(parents)
+            .map(|(page_id, parent)| (*page_id, parent.and_then(|parent| parse_lsn(parent.lsn.as_deref()?))))
+            .collect();
+        pool.fetch(&requests, |client, requests| {
+            let (page_ids, parent_lsns): (Vec<i64>, Vec<Option<u64>>) = requests.iter().cloned().unzip();
+            get_gist_pages(client, &page_ids, &index_name, &parent_lsns)
+        })
+    })?;
+    pages.set_levels_from_children(GIST_ROOT_BLKNO);
+    Ok(Tree::new_gist(GIST_ROOT_BLKNO, pages, index_name, index_info))
+}
+
+/// Parses a `pg_lsn` text representation (`16/B374D848`) into its 64 bit value.
+pub fn parse_lsn(lsn: &str) -> Option<u64> {
+    let (hi, lo) = lsn.split_once('/')?;
+    let hi = u64::from_str_radix(hi, 16).ok()?;
+    let lo = u64::from_str_radix(lo, 16).ok()?;
+    Some((hi << 32) | lo)
+}
+
+fn parse_numbers(text: &str) -> Vec<f64> {
+    let mut numbers = vec![];
+    let mut current = String::new();
+    // digits inside identifiers such as BOX2DF are not numbers
+    let mut in_word = false;
+    for c in text.chars() {
+        let continues_number = if current.is_empty() {
+            !in_word && (c.is_ascii_digit() || c == '.' || c == '-' || c == '+')
+        } else {
+            c.is_ascii_digit() || c == '.'
+                || ((c == 'e' || c == 'E') && !current.contains(['e', 'E']))
+                || ((c == '-' || c == '+') && current.ends_with(['e', 'E']))
+        };
+        if continues_number {
+            current.push(c);
+            continue;
+        }
+        if let Ok(number) = current.parse::<f64>() {
+            numbers.push(number);
+        }
+        current.clear();
+        in_word = c.is_alphanumeric() || (in_word && c == '_');
+    }
+    if let Ok(number) = current.parse::<f64>() {
+        numbers.push(number);
+    }
+    numbers
+}
+
+/// Extracts a bounding box from the `keys` column of `gist_page_items`, e.g.
+/// `(b)=("(102,3),(42,0)")` for box and point opclasses or `(geom)=("BOX2DF(1 2, 3 4)")` for
+/// PostGIS. Keys of other opclasses (ranges, tsvector signatures, ...) yield `None`.
+pub fn parse_bounding_box(keys: &str) -> Option<BoundingBox> {
+    let value = match keys.split_once(")=(") {
+        Some((_, value)) => value,
+        None => keys,
+    };
+    let numbers = parse_numbers(value);
+    let (x1, y1, x2, y2) = match numbers.as_slice() {
+        [x, y] => (*x, *y, *x, *y),
+        [x1, y1, x2, y2] => (*x1, *y1, *x2, *y2),
+        _ => return None,
+    };
+    Some(BoundingBox {
+        x_min: x1.min(x2),
+        y_min: y1.min(y2),
+        x_max: x1.max(x2),
+        y_max: y1.max(y2),
+    })
+}
+
+fn overlaps(a: &BoundingBox, b: &BoundingBox) -> bool {
+    a.x_min < b.x_max && b.x_min < a.x_max && a.y_min < b.y_max && b.y_min < a.y_max
+}
+
+/// Counts the pairs of keys on a page whose boxes share some area. On internal pages every such
+/// pair is a region a search may have to descend into twice.
+pub fn count_overlapping_pairs(boxes: &[BoundingBox]) -> i64 {
+    let mut pairs = 0;
+    for (i, a) in boxes.iter().enumerate() {
+        for b in boxes.iter().skip(i + 1) {
+            if overlaps(a, b) {
+                pairs += 1;
+            }
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::structs::BoundingBox;
+    use super::{count_overlapping_pairs, parse_bounding_box, parse_lsn};
+
+    fn bounding_box(x_min: f64, y_min: f64, x_max: f64, y_max: f64) -> BoundingBox {
+        BoundingBox { x_min, y_min, x_max, y_max }
+    }
+
+    #[test]
+    pub fn test_parse_bounding_box() {
+        assert_eq!(Some(bounding_box(42.0, 0.0, 102.0, 3.0)), parse_bounding_box("(b)=(\"(102,3),(42,0)\")"));
+        assert_eq!(Some(bounding_box(1.5, -2.0, 1.5, -2.0)), parse_bounding_box("(p)=(\"(1.5,-2)\")"));
+        assert_eq!(Some(bounding_box(-1e-3, 2.0, 3.0, 4.0)), parse_bounding_box("(geom)=(\"BOX2DF(-1e-3 2, 3 4)\")"));
+        assert_eq!(None, parse_bounding_box("(r)=(\"[1,10)\", 3)"));
+    }
+
+    #[test]
+    pub fn test_count_overlapping_pairs() {
+        let boxes = vec![
+            bounding_box(0.0, 0.0, 2.0, 2.0),
+            bounding_box(1.0, 1.0, 3.0, 3.0),
+            bounding_box(2.0, 2.0, 4.0, 4.0),
+            bounding_box(10.0, 10.0, 11.0, 11.0),
+        ];
+        assert_eq!(2, count_overlapping_pairs(&boxes));
+    }
+
+    #[test]
+    pub fn test_parse_lsn() {
+        assert_eq!(Some(0x1_0000_00FF), parse_lsn("1/FF"));
+        assert_eq!(None, parse_lsn("garbage"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex};
+use log::debug;
+use crate::core::structs::{HashBucket, HashIndex, HashMetadataPage};
+use crate::db::{get_hash_bucket, get_hash_metadata_page, Client, IndexInfo};
+use crate::error::Result;
+
+const HASH_SPLITPOINT_PHASE_BITS: u32 = 2;
+const HASH_SPLITPOINT_PHASE_MASK: u32 = (1 << HASH_SPLITPOINT_PHASE_BITS) - 1;
+const HASH_SPLITPOINT_GROUPS_WITH_ONE_PHASE: u32 = 10;
+/// Buckets holding more than this many times the average number of items are highlighted.
+const SKEWED_BUCKET_THRESHOLD: f64 = 2.0;
+
+pub fn generate_hash(client: Arc<Mutex<Client>>, index_name: String, index_info: Arc<IndexInfo>) -> Result<HashIndex> {
+    let metadata_page = get_hash_metadata_page(Arc::clone(&client), index_name.clone())?;
+    debug!("Hash metadata page: {:?}", metadata_page);
+    let mut buckets: Vec<HashBucket> = (0..=metadata_page.maxbucket)
+        .map(|bucket| get_hash_bucket(Arc::clone(&client), bucket, bucket_to_blkno(&metadata_page, bucket), index_name.clone()))
+        .collect::<Result<_>>()?;
+    set_skew(&mut buckets);
+    Ok(HashIndex::new(metadata_page, buckets, index_name, index_info))
+}
+
+fn ceil_log2(num: u32) -> u32 {
+    if num <= 1 {
+        0
+    } else {
+        32 - (num - 1).leading_zeros()
+    }
+}
+
+/// Port of `_hash_spareindex`: the splitpoint phase at which `num_bucket` buckets exist.
+pub fn spare_index(num_bucket: u32) -> u32 {
+    let splitpoint_group = ceil_log2(num_bucket);
+    if splitpoint_group < HASH_SPLITPOINT_GROUPS_WITH_ONE_PHASE {
+        return splitpoint_group;
+    }
+    let mut splitpoint_phases = HASH_SPLITPOINT_GROUPS_WITH_ONE_PHASE;
+    splitpoint_phases += (splitpoint_group - HASH_SPLITPOINT_GROUPS_WITH_ONE_PHASE) << HASH_SPLITPOINT_PHASE_BITS;
+    splitpoint_phases += ((num_bucket - 1) >> (splitpoint_group - (HASH_SPLITPOINT_PHASE_BITS + 1))) & HASH_SPLITPOINT_PHASE_MASK;
+    splitpoint_phases
+}
+
+/// Port of `BUCKET_TO_BLKNO`: primary bucket pages are interleaved with the overflow pages
+/// allocated before each splitpoint, which `hashm_spares` counts.
+pub fn bucket_to_blkno(metadata_page: &HashMetadataPage, bucket: i64) -> i64 {
+    let spares = if bucket == 0 {
+        0
+    } else {
+        let index = spare_index(bucket as u32 + 1) as usize - 1;
+        metadata_page.spares.get(index).cloned().unwrap_or(0)
+    };
+    bucket + spares + 1
+}
+
+pub fn set_skew(buckets: &mut [HashBucket]) {
+    let total: i64 = buckets.iter().map(|bucket| bucket.live_items).sum();
+    if buckets.is_empty() || total == 0 {
+        return;
+    }
+    let average = total as f64 / buckets.len() as f64;
+    for bucket in buckets.iter_mut() {
+        bucket.skew = bucket.live_items as f64 / average;
+        bucket.is_skewed = bucket.skew > SKEWED_BUCKET_THRESHOLD;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::structs::{HashBucket, HashMetadataPage};
+    use super::{bucket_to_blkno, set_skew, spare_index};
+
+    fn metadata_page(spares: Vec<i64>) -> HashMetadataPage {
+        HashMetadataPage {
+            magic: 105121344,
+            version: 4,
+            ntuples: 0.0,
+            ffactor: 307,
+            bsize: 8152,
+            bmsize: 4096,
+            bmshift: 15,
+            maxbucket: 65,
+            highmask: 127,
+            lowmask: 63,
+            ovflpoint: 7,
+            firstfree: 1,
+            nmaps: 1,
+            spares,
+            mapp: vec![9],
+        }
+    }
+
+    fn bucket(live_items: i64) -> HashBucket {
+        HashBucket {
+            bucket: 0,
+            pages: vec![],
+            live_items,
+            dead_items: 0,
+            overflow_pages: 0,
+            overflow_bitmap: vec![],
+            skew: 0.0,
+            is_skewed: false,
+        }
+    }
+
+    #[test]
+    pub fn test_spare_index() {
+        assert_eq!(0, spare_index(1));
+        assert_eq!(4, spare_index(9));
+        assert_eq!(9, spare_index(512));
+        // past 512 buckets every splitpoint group is allocated in four phases
+        assert_eq!(10, spare_index(513));
+        assert_eq!(11, spare_index(641));
+        assert_eq!(14, spare_index(1025));
+    }
+
+    #[test]
+    pub fn test_bucket_to_blkno() {
+        let metadata_page = metadata_page(vec![0, 0, 0, 4, 13, 25, 49, 49]);
+        assert_eq!(1, bucket_to_blkno(&metadata_page, 0));
+        assert_eq!(8, bucket_to_blkno(&metadata_page, 7));
+        assert_eq!(13, bucket_to_blkno(&metadata_page, 8));
+        assert_eq!(58, bucket_to_blkno(&metadata_page, 32));
+        assert_eq!(115, bucket_to_blkno(&metadata_page, 65));
+    }
+
+    #[test]
+    pub fn test_set_skew() {
+        let mut buckets = vec![bucket(10), bucket(10), bucket(40)];
+        set_skew(&mut buckets);
+        assert_eq!(vec![0.5, 0.5, 2.0], buckets.iter().map(|bucket| bucket.skew).collect::<Vec<f64>>());
+        assert!(buckets.iter().all(|bucket| !bucket.is_skewed));
+        let mut buckets = vec![bucket(1), bucket(1), bucket(1), bucket(9)];
+        set_skew(&mut buckets);
+        assert_eq!(vec![false, false, false, true], buckets.iter().map(|bucket| bucket.is_skewed).collect::<Vec<bool>>());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use log::info;
+use crate::core::structs::{HashIndex, HeapPage, Page, Tid, Tree};
+use crate::db::{get_heap_blocks, get_heap_page, get_row, Client, IndexInfo};
+use crate::error::Result;
+
+/// Collects the heap TIDs the items of a leaf `page` point to. Downlinks of internal pages and
+/// SP-GiST redirects point into the index and are skipped.
+pub fn collect_heap_tids(page: &Page, tids: &mut BTreeSet<Tid>) {
+    if !page.is_leaf {
+        return;
+    }
+    for item in page.items.iter().filter(|item| item.state.is_none()) {
+        tids.extend(item.obj_id.iter().cloned());
+        tids.extend(item.tids.iter().flatten().cloned());
+    }
+}
+
+/// Attaches the heap pages leaf items of the tree, and of its GIN pending list, point into.
+pub fn with_heap_pages(client: Arc<Mutex<Client>>, mut tree: Tree, index_info: &IndexInfo, max_pages: usize) -> Result<Tree> {
+    let mut tids = BTreeSet::new();
+    for page in tree.pages.iter().chain(tree.pending_list.iter()) {
+        collect_heap_tids(page, &mut tids);
+    }
+    tree.heap_pages = generate_heap_pages(client, index_info, &tids, max_pages)?;
+    Ok(tree)
+}
+
+/// Looks up the heap rows btree leaf items point to, `batch_size` TIDs per query and at most
+/// `max_rows` of them in TID order, posting list tuples by their first heap TID. Items whose row
+/// was looked up but not found get `row_visible` false, the others keep `row` and `row_visible`
+/// unset.
+pub fn with_heap_rows(client: Arc<Mutex<Client>>, mut tree: Tree, index_info: Arc<IndexInfo>, batch_size: usize, max_rows: usize) -> Result<Tree> {
+    let tids: BTreeSet<Tid> = tree.pages.iter()
+        .filter(|page| page.is_leaf)
+        .flat_map(|page| page.items.iter())
+        .filter_map(|item| item.obj_id.clone())
+        .collect();
+    info!("Resolving {} of {} heap rows", tids.len().min(max_rows), tids.len());
+    let tids: Vec<Tid> = tids.into_iter().take(max_rows).collect();
+    let mut rows = HashMap::new();
+    for batch in tids.chunks(batch_size.max(1)) {
+        rows.extend(get_row(Arc::clone(&client), batch.to_vec(), Arc::clone(&index_info))?);
+    }
+    let looked_up: BTreeSet<&Tid> = tids.iter().collect();
+    for item in tree.pages.iter_mut().filter(|page| page.is_leaf).flat_map(|page| page.items.iter_mut()) {
+        if let Some(tid) = item.obj_id.as_ref().filter(|tid| looked_up.contains(tid)) {
+            item.row = rows.get(tid).cloned();
+            item.row_visible = Some(item.row.is_some());
+        }
+    }
+    Ok(tree)
+}
+
+pub fn hash_with_heap_pages(client: Arc<Mutex<Client>>, mut index: HashIndex, index_info: &IndexInfo, max_pages: usize) -> Result<HashIndex> {
+    let mut tids = BTreeSet::new();
+    for page in index.buckets.iter().flat_map(|bucket| bucket.pages.iter()) {
+        collect_heap_tids(page, &mut tids);
+    }
+    index.heap_pages = generate_heap_pages(client, index_info, &tids, max_pages)?;
+    Ok(index)
+}
+
+/// Fetches the heap pages `tids` point into, in block order and at most `max_pages` of them.
+/// TIDs past the end of the table, left behind by a truncating vacuum, are ignored.
+pub fn generate_heap_pages(client: Arc<Mutex<Client>>, index_info: &IndexInfo, tids: &BTreeSet<Tid>, max_pages: usize) -> Result<Vec<HeapPage>> {
+    if tids.is_empty() || max_pages == 0 {
+        return Ok(vec![]);
+    }
+    let heap_blocks = get_heap_blocks(Arc::clone(&client), index_info)?;
+    let blocks: BTreeSet<i64> = tids.iter()
+        .map(|tid| tid.block_number as i64)
+        .filter(|block_number| *block_number < heap_blocks)
+        .collect();
+    info!("Rendering {} of {} referenced heap pages", blocks.len().min(max_pages), blocks.len());
+    blocks.into_iter()
+        .take(max_pages)
+        .map(|block_number| get_heap_page(Arc::clone(&client), block_number, index_info, tids))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use crate::core::{Page, Tid};
+    use crate::core::structs::Item;
+    use super::collect_heap_tids;
+
+    #[test]
+    pub fn test_collect_heap_tids() {
+        let tid = |block_number: u32, offset_number: u16| Tid { block_number, offset_number };
+        let mut leaf = Page::new(2, 0, true, false, 0, 0);
+        leaf.items.push(Item::new("a".to_string(), None, Some(5), Some(tid(5, 1))));
+        let mut posting = Item::new("b".to_string(), None, Some(5), Some(tid(5, 2)));
+        posting.tids = Some(vec![tid(5, 2), tid(7, 3)]);
+        leaf.items.push(posting);
+        let mut redirect = Item::new(String::new(), None, Some(4), Some(tid(4, 1)));
+        redirect.state = Some("redirect".to_string());
+        leaf.items.push(redirect);
+        let mut root = Page::new(1, 1, false, true, 0, 0);
+        root.items.push(Item::new(String::new(), Some(2), Some(2), Some(tid(2, 1))));
+
+        let mut tids = BTreeSet::new();
+        collect_heap_tids(&root, &mut tids);
+        collect_heap_tids(&leaf, &mut tids);
+        assert_eq!(vec![tid(5, 1), tid(5, 2), tid(7, 3)], tids.into_iter().collect::<Vec<Tid>>());
+    }
+}
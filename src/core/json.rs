@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::core::renderer::write_output;
+use crate::core::structs::{BoundingBox, BrinIndex, BrinRange, Deduplication, HashBitmapBit, HashIndex, HeapPage, KeyValue, Page, RowData, SuffixTruncation, Tid, Tree};
+use crate::error::{Error, Result};
+
+/// Version of the JSON export schema, see `docs/json_schema.md`. Bumped on any change that
+/// renames, removes or changes the meaning of a field; new fields may be added within a version.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// An inspected index as written by `--format json`. Pages are flattened into a map keyed by
+/// block number and items refer to their child page by block number instead of nesting it.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct JsonExport {
+    pub schema_version: u32,
+    pub index: JsonIndexInfo,
+    /// The access method's metapage as is, its fields differ between index types.
+    pub metapage: Option<Value>,
+    pub root: Option<i64>,
+    /// Root of the SP-GiST tree holding NULL keys.
+    pub nulls_root: Option<i64>,
+    /// GIN fast-update pending list, in list order.
+    pub pending_list: Vec<i64>,
+    pub pages: BTreeMap<i64, JsonPage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<Vec<JsonBucket>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ranges: Option<Vec<BrinRange>>,
+    /// Over all btree leaf pages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deduplication: Option<Deduplication>,
+    /// Btree pivots per level, from the root down.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suffix_truncation: Option<Vec<SuffixTruncation>>,
+    pub heap_pages: Vec<HeapPage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonIndexInfo {
+    pub name: String,
+    pub index_type: String,
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct JsonPage {
+    pub block_number: i64,
+    pub level: i64,
+    pub is_leaf: bool,
+    pub is_root: bool,
+    pub prev_page_id: Option<i64>,
+    pub next_page_id: Option<i64>,
+    pub high_key: Option<String>,
+    pub flags: Vec<String>,
+    pub lsn: Option<String>,
+    pub nsn: Option<String>,
+    pub deduplication: Option<Deduplication>,
+    pub items: Vec<JsonItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct JsonItem {
+    pub value: String,
+    /// Block number of the child page, when it was inspected.
+    pub child: Option<i64>,
+    pub pointer: Option<i64>,
+    pub tid: Option<Tid>,
+    pub tids: Option<Vec<Tid>>,
+    /// Whether a btree leaf item was killed, `None` for other items.
+    pub dead: Option<bool>,
+    /// Heap TID of btree items, the tiebreaker key attribute of pivots.
+    pub htid: Option<Tid>,
+    pub key_size: Option<i64>,
+    /// Key attributes a btree pivot kept after suffix truncation.
+    pub key_attributes: Option<i64>,
+    /// Key columns suffix truncation cut off a pivot.
+    pub truncated: Option<Vec<String>>,
+    /// The key decoded attribute by attribute, btree only.
+    pub key: Option<Vec<KeyValue>>,
+    /// Heap row of a btree leaf item, with `--resolve-heap`.
+    pub row: Option<RowData>,
+    pub row_visible: Option<bool>,
+    pub bounding_box: Option<BoundingBox>,
+    pub state: Option<String>,
+    /// Next leaf tuple of an SP-GiST chain, as an offset on the same page.
+    pub next_offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct JsonBucket {
+    pub bucket: i64,
+    /// The primary page followed by the overflow chain.
+    pub pages: Vec<i64>,
+    pub live_items: i64,
+    pub dead_items: i64,
+    pub overflow_bitmap: Vec<HashBitmapBit>,
+    pub skew: f64,
+    pub is_skewed: bool,
+}
+
+impl JsonExport {
+    pub(crate) fn new(index: JsonIndexInfo, metapage: Option<Value>, heap_pages: &[HeapPage]) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            index,
+            metapage,
+            root: None,
+            nulls_root: None,
+            pending_list: vec![],
+            pages: BTreeMap::new(),
+            buckets: None,
+            ranges: None,
+            deduplication: None,
+            suffix_truncation: None,
+            heap_pages: heap_pages.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<&Tree> for JsonExport {
+    type Error = Error;
+
+    fn try_from(tree: &Tree) -> Result<Self> {
+        let metapage = match (&tree.metadata_page, &tree.gin_metadata_page) {
+            (Some(metadata_page), _) => Some(serde_json::to_value(metadata_page)?),
+            (None, Some(gin_metadata_page)) => Some(serde_json::to_value(gin_metadata_page)?),
+            (None, None) => None,
+        };
+        let index = JsonIndexInfo {
+            name: tree.index_name.clone(),
+            index_type: tree.index_type.clone().unwrap_or_default(),
+            table: tree.table_name.clone(),
+            columns: tree.columns.clone(),
+        };
+        let mut export = JsonExport::new(index, metapage, &tree.heap_pages);
+        export.root = Some(tree.root);
+        export.nulls_root = tree.nulls_root;
+        export.deduplication = tree.deduplication();
+        export.suffix_truncation = Some(tree.suffix_truncation()).filter(|levels| !levels.is_empty());
+        for page in tree.pages.iter() {
+            export.pages.insert(page.id, JsonPage::from(page));
+        }
+        for page in tree.pending_list.iter() {
+            export.pending_list.push(page.id);
+            export.pages.insert(page.id, JsonPage::from(page));
+        }
+        Ok(export)
+    }
+}
+
+impl TryFrom<&HashIndex> for JsonExport {
+    type Error = Error;
+
+    fn try_from(index: &HashIndex) -> Result<Self> {
+        let info = JsonIndexInfo {
+            name: index.index_name.clone(),
+            index_type: index.index_type.clone(),
+            table: index.table_name.clone(),
+            columns: index.columns.clone(),
+        };
+        let mut export = JsonExport::new(info, Some(serde_json::to_value(&index.metadata_page)?), &index.heap_pages);
+        let mut buckets = vec![];
+        for bucket in index.buckets.iter() {
+            for page in bucket.pages.iter() {
+                export.pages.insert(page.id, JsonPage::from(page));
+            }
+            buckets.push(JsonBucket {
+                bucket: bucket.bucket,
+                pages: bucket.pages.iter().map(|page| page.id).collect(),
+                live_items: bucket.live_items,
+                dead_items: bucket.dead_items,
+                overflow_bitmap: bucket.overflow_bitmap.clone(),
+                skew: bucket.skew,
+                is_skewed: bucket.is_skewed,
+            });
+        }
+        export.buckets = Some(buckets);
+        Ok(export)
+    }
+}
+
+impl TryFrom<&BrinIndex> for JsonExport {
+    type Error = Error;
+
+    fn try_from(index: &BrinIndex) -> Result<Self> {
+        let info = JsonIndexInfo {
+            name: index.index_name.clone(),
+            index_type: index.index_type.clone(),
+            table: index.table_name.clone(),
+            columns: index.columns.clone(),
+        };
+        let mut export = JsonExport::new(info, Some(serde_json::to_value(&index.metadata_page)?), &[]);
+        export.ranges = Some(index.ranges.clone());
+        Ok(export)
+    }
+}
+
+impl From<&Page> for JsonPage {
+    fn from(page: &Page) -> Self {
+        let items = page.items.iter().map(|item| JsonItem {
+            value: item.value.clone(),
+            child: item.child,
+            pointer: item.pointer,
+            tid: item.obj_id.clone(),
+            tids: item.tids.clone(),
+            dead: item.dead,
+            htid: item.htid.clone(),
+            key_size: item.key_size,
+            key_attributes: item.key_attributes,
+            truncated: item.truncated.clone(),
+            key: item.key.clone(),
+            row: item.row.clone(),
+            row_visible: item.row_visible,
+            bounding_box: item.bounding_box,
+            state: item.state.clone(),
+            next_offset: item.next_offset,
+        }).collect();
+        JsonPage {
+            block_number: page.id,
+            level: page.level,
+            is_leaf: page.is_leaf,
+            is_root: page.is_root,
+            prev_page_id: page.prev_page_id,
+            next_page_id: page.next_page_id,
+            high_key: page.high_key.clone(),
+            flags: page.flags.clone(),
+            lsn: page.lsn.clone(),
+            nsn: page.nsn.clone(),
+            deduplication: page.deduplication,
+            items,
+        }
+    }
+}
+
+pub fn render_json(export: JsonExport, output_path: &Path) -> Result<()> {
+    write_output(serde_json::to_string_pretty(&export)?, output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::structs::{Item, Tid};
+    use crate::core::test_support::{sample_btree, sample_index_info};
+    use super::{JsonExport, JSON_SCHEMA_VERSION};
+
+    #[test]
+    pub fn test_tree_to_json() {
+        let mut tree = sample_btree(sample_index_info(&["id"], 1));
+        let leaf = tree.pages.get_mut(1).unwrap();
+        leaf.items.push(Item::new("01 00 00 00".to_string(), None, Some(0), Some(Tid { block_number: 0, offset_number: 1 })));
+
+        let export = JsonExport::try_from(&tree).unwrap();
+        let json = serde_json::to_value(&export).unwrap();
+        assert_eq!(JSON_SCHEMA_VERSION, json["schema_version"]);
+        assert_eq!("btree", json["index"]["index_type"]);
+        assert_eq!(3, json["metapage"]["root"]);
+        assert_eq!(3, json["root"]);
+        // pages are keyed by block number, children are referenced rather than nested
+        assert_eq!(vec!["1", "2", "3"], json["pages"].as_object().unwrap().keys().collect::<Vec<&String>>());
+        assert_eq!(2, json["pages"]["3"]["items"][1]["child"]);
+        assert_eq!(2, json["pages"]["1"]["next_page_id"]);
+        assert_eq!(1, json["pages"]["1"]["items"][0]["tid"]["offset_number"]);
+        assert!(json.get("buckets").is_none());
+
+        let parsed: JsonExport = serde_json::from_str(&serde_json::to_string(&export).unwrap()).unwrap();
+        assert_eq!(export, parsed);
+    }
+}
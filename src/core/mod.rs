@@ -1,10 +1,28 @@
 mod command;
+pub(crate) mod arena;
+pub(crate) mod brin;
+pub(crate) mod dot;
 pub(crate) mod btree;
+pub(crate) mod datum;
+pub(crate) mod gin;
+pub(crate) mod gist;
+pub(crate) mod hash;
+pub(crate) mod heap;
+pub(crate) mod json;
+pub(crate) mod raw_page;
+pub(crate) mod server;
+pub(crate) mod spgist;
 pub(crate) mod structs;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub(crate) mod text;
+pub(crate) mod tui;
 pub(crate) mod renderer;
 
 pub use command::handle_command_call;
-pub use structs::{MetadataPage, Page, Tid};
+pub use structs::{Page, Tid};
 pub use btree::Tree;
-pub use renderer::render;
-
+pub use renderer::{render, render_brin, render_hash};
+pub use json::{render_json, JsonExport};
+pub use dot::{render_dot, render_svg};
+pub use text::render_text;
@@ -0,0 +1,301 @@
+use crate::core::structs::Tid;
+
+/// Size of `PageHeaderData` on disk.
+pub const PAGE_HEADER_SIZE: usize = 24;
+/// Size of a single `ItemIdData` line pointer.
+pub const LINE_POINTER_SIZE: usize = 4;
+/// Size of an on-disk `ItemPointerData`.
+pub const ITEM_POINTER_SIZE: usize = 6;
+/// `InvalidBlockNumber` as returned by pageinspect (an unsigned 32 bit value widened to bigint).
+pub const INVALID_BLOCK_NUMBER: i64 = 0xFFFF_FFFF;
+
+const INDEX_SIZE_MASK: u16 = 0x1FFF;
+const INDEX_NULL_MASK: u16 = 0x8000;
+const INDEX_TUPLE_HEADER_SIZE: usize = 8;
+/// Size of the null bitmap that follows the header when `INDEX_NULL_MASK` is set (INDEX_MAX_KEYS / 8).
+const INDEX_NULL_BITMAP_SIZE: usize = 4;
+
+pub const LP_NORMAL: u8 = 1;
+/// Set by index scans on tuples no transaction can see anymore, which keep their storage until
+/// the page is cleaned up.
+pub const LP_DEAD: u8 = 3;
+
+pub fn max_align(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+pub fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let b = bytes.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([b[0], b[1]]))
+}
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Reads a `BlockIdData`, which is stored as two 16 bit halves.
+pub fn read_block_id(bytes: &[u8], offset: usize) -> Option<u32> {
+    let hi = read_u16(bytes, offset)? as u32;
+    let lo = read_u16(bytes, offset + 2)? as u32;
+    Some((hi << 16) | lo)
+}
+
+pub fn read_item_pointer(bytes: &[u8], offset: usize) -> Option<Tid> {
+    Some(Tid {
+        block_number: read_block_id(bytes, offset)?,
+        offset_number: read_u16(bytes, offset + 4)?,
+    })
+}
+
+/// Formats bytes the same way `bt_page_items` renders its `data` column.
+pub fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageHeader {
+    pub lsn: u64,
+    pub checksum: u16,
+    pub flags: u16,
+    pub lower: u16,
+    pub upper: u16,
+    pub special: u16,
+    pub page_size: u16,
+    pub version: u8,
+    pub prune_xid: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinePointer {
+    pub offset: u16,
+    pub flags: u8,
+    pub length: u16,
+}
+
+/// A page image as returned by `get_raw_page`.
+pub struct RawPage<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RawPage<'a> {
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < PAGE_HEADER_SIZE {
+            return None;
+        }
+        Some(Self { bytes })
+    }
+
+    pub fn header(&self) -> PageHeader {
+        let b = self.bytes;
+        let lsn_hi = read_u32(b, 0).unwrap() as u64;
+        let lsn_lo = read_u32(b, 4).unwrap() as u64;
+        let size_version = read_u16(b, 18).unwrap();
+        PageHeader {
+            lsn: (lsn_hi << 32) | lsn_lo,
+            checksum: read_u16(b, 8).unwrap(),
+            flags: read_u16(b, 10).unwrap(),
+            lower: read_u16(b, 12).unwrap(),
+            upper: read_u16(b, 14).unwrap(),
+            special: read_u16(b, 16).unwrap(),
+            page_size: size_version & 0xFF00,
+            version: (size_version & 0x00FF) as u8,
+            prune_xid: read_u32(b, 20).unwrap(),
+        }
+    }
+
+    /// Bytes between the page header and the special space, i.e. where pages without
+    /// line pointers (GIN data pages) keep their contents.
+    pub fn contents(&self) -> &'a [u8] {
+        let end = (self.header().special as usize).min(self.bytes.len());
+        self.bytes.get(PAGE_HEADER_SIZE..end).unwrap_or(&[])
+    }
+
+    /// The access method specific area at the end of the page.
+    pub fn special(&self) -> &'a [u8] {
+        self.bytes.get(self.header().special as usize..).unwrap_or(&[])
+    }
+
+    pub fn line_pointers(&self) -> Vec<LinePointer> {
+        let lower = self.header().lower as usize;
+        let mut pointers = vec![];
+        let mut offset = PAGE_HEADER_SIZE;
+        while offset + LINE_POINTER_SIZE <= lower {
+            let raw = match read_u32(self.bytes, offset) {
+                Some(raw) => raw,
+                None => break,
+            };
+            pointers.push(LinePointer {
+                offset: (raw & 0x7FFF) as u16,
+                flags: ((raw >> 15) & 0x03) as u8,
+                length: (raw >> 17) as u16,
+            });
+            offset += LINE_POINTER_SIZE;
+        }
+        pointers
+    }
+
+    /// The tuple a normal or dead line pointer points to. Unused and redirect line pointers have
+    /// no storage.
+    pub fn item(&self, pointer: &LinePointer) -> Option<&'a [u8]> {
+        if pointer.flags != LP_NORMAL && (pointer.flags != LP_DEAD || pointer.length == 0) {
+            return None;
+        }
+        let start = pointer.offset as usize;
+        self.bytes.get(start..start + pointer.length as usize)
+    }
+
+    /// All index tuples on the page, dead ones included, in line pointer order, paired with
+    /// their offset number.
+    pub fn index_tuples(&self) -> Vec<(u16, IndexTuple<'a>)> {
+        self.line_pointers().iter()
+            .enumerate()
+            .filter_map(|(i, lp)| {
+                let mut tuple = IndexTuple::new(self.item(lp)?)?;
+                tuple.dead = lp.flags == LP_DEAD;
+                Some((i as u16 + 1, tuple))
+            })
+            .collect()
+    }
+}
+
+/// An `IndexTupleData` followed by its attribute data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexTuple<'a> {
+    pub tid: Tid,
+    pub info: u16,
+    /// Whether its line pointer is `LP_DEAD`.
+    pub dead: bool,
+    bytes: &'a [u8],
+}
+
+impl<'a> IndexTuple<'a> {
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        Some(Self {
+            tid: read_item_pointer(bytes, 0)?,
+            info: read_u16(bytes, ITEM_POINTER_SIZE)?,
+            dead: false,
+            bytes,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        ((self.info & INDEX_SIZE_MASK) as usize).min(self.bytes.len())
+    }
+
+    pub fn has_nulls(&self) -> bool {
+        self.info & INDEX_NULL_MASK != 0
+    }
+
+    /// Whether the attribute numbered `attribute` from 0 is NULL. Only tuples that `has_nulls`
+    /// have the bitmap this reads, where a set bit means not NULL.
+    pub fn is_null(&self, attribute: usize) -> bool {
+        self.bytes.get(INDEX_TUPLE_HEADER_SIZE + attribute / 8).is_some_and(|byte| byte & (1 << (attribute % 8)) == 0)
+    }
+
+    /// Offset of the first attribute, mirroring `IndexInfoFindDataOffset`.
+    pub fn data_offset(&self) -> usize {
+        if self.has_nulls() {
+            max_align(INDEX_TUPLE_HEADER_SIZE + INDEX_NULL_BITMAP_SIZE)
+        } else {
+            max_align(INDEX_TUPLE_HEADER_SIZE)
+        }
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        &self.bytes[..self.size()]
+    }
+}
+
+/// Renders a key datum as text when it looks like a varlena holding valid UTF-8,
+/// and as a hex dump otherwise.
+pub fn format_datum(bytes: &[u8]) -> String {
+    match varlena_payload(bytes) {
+        Some(payload) => match std::str::from_utf8(payload) {
+            Ok(text) if !text.chars().any(|c| c.is_control()) => text.to_string(),
+            _ => hex_string(bytes),
+        },
+        None => hex_string(bytes),
+    }
+}
+
+/// The data of a varlena with a 1 or 4 byte header, ignoring trailing alignment padding.
+pub fn varlena_payload(bytes: &[u8]) -> Option<&[u8]> {
+    let first = *bytes.first()?;
+    let (header, total) = if first & 0x01 == 0x01 {
+        // 0x01 alone marks an external TOAST pointer, which never shows up in index keys.
+        if first == 0x01 {
+            return None;
+        }
+        (1, (first >> 1) as usize)
+    } else if first & 0x03 == 0x00 {
+        (4, (read_u32(bytes, 0)? >> 2) as usize)
+    } else {
+        return None;
+    };
+    if total < header || total > bytes.len() || bytes[total..].iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(&bytes[header..total])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Tid;
+    use super::{format_datum, IndexTuple, RawPage, LP_NORMAL};
+
+    fn sample_page() -> Vec<u8> {
+        let mut page = vec![0u8; 8192];
+        // pd_lower: header plus one line pointer, one tuple at pd_upper, 8 bytes of special space
+        page[12..14].copy_from_slice(&28u16.to_le_bytes());
+        page[14..16].copy_from_slice(&8160u16.to_le_bytes());
+        page[16..18].copy_from_slice(&8184u16.to_le_bytes());
+        page[18..20].copy_from_slice(&(8192u16 | 4).to_le_bytes());
+        let lp: u32 = 8160 | (1 << 15) | (16 << 17);
+        page[24..28].copy_from_slice(&lp.to_le_bytes());
+        // t_tid = (1, 7), t_info = 16, then a short varlena 'abc'
+        page[8160..8162].copy_from_slice(&0u16.to_le_bytes());
+        page[8162..8164].copy_from_slice(&1u16.to_le_bytes());
+        page[8164..8166].copy_from_slice(&7u16.to_le_bytes());
+        page[8166..8168].copy_from_slice(&16u16.to_le_bytes());
+        page[8168..8172].copy_from_slice(&[0x09, b'a', b'b', b'c']);
+        page
+    }
+
+    #[test]
+    pub fn test_read_index_tuples() {
+        let bytes = sample_page();
+        let page = RawPage::new(&bytes).unwrap();
+        let header = page.header();
+        assert_eq!(8192, header.page_size);
+        assert_eq!(4, header.version);
+        let pointers = page.line_pointers();
+        assert_eq!(1, pointers.len());
+        assert_eq!(LP_NORMAL, pointers[0].flags);
+        let tuples = page.index_tuples();
+        assert_eq!(1, tuples.len());
+        let (offset, tuple) = &tuples[0];
+        assert_eq!(1, *offset);
+        assert_eq!(Tid { block_number: 1, offset_number: 7 }, tuple.tid);
+        assert_eq!(16, tuple.size());
+        assert_eq!("abc", format_datum(&tuple.bytes()[tuple.data_offset()..]));
+        assert!(!tuple.dead);
+
+        // killed tuples keep their storage
+        let mut bytes = sample_page();
+        let lp: u32 = 8160 | (3 << 15) | (16 << 17);
+        bytes[24..28].copy_from_slice(&lp.to_le_bytes());
+        let tuples = RawPage::new(&bytes).unwrap().index_tuples();
+        assert_eq!(1, tuples.len());
+        assert!(tuples[0].1.dead);
+    }
+
+    #[test]
+    pub fn test_format_datum_falls_back_to_hex() {
+        assert_eq!("2a 00 00 00", format_datum(&[0x2a, 0, 0, 0]));
+        assert!(IndexTuple::new(&[0, 1]).is_none());
+    }
+}
@@ -1,78 +1,69 @@
-use std::collections::HashMap;
 use crate::core::{Page, Tid, Tree};
+use crate::core::structs::{BoundingBox, BrinIndex, HashIndex, HeapPage};
+use crate::error::Result;
 use std::path::{Path};
-use std::env;
 use std::fs::{File};
 use std::io::Write;
 use handlebars::*;
 use handlebars::Handlebars;
-use serde_json::json;
-
-struct IsArrayHelper;
 
 struct TidRenderHelper;
 
-struct HasChildrenHelper;
+struct ContainsHelper;
 
-struct SampleLookupHelper;
+/// Links a heap TID to its tuple when its heap page is rendered, listed in the `heap_blocks` of
+/// the root context, and writes it as plain text otherwise.
+struct HeapLinkHelper;
 
-impl HelperDef for SampleLookupHelper {
-    fn call_inner<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper<'rc>,
-        _: &'reg Handlebars<'reg>,
-        _: &'rc Context,
-        _: &mut RenderContext<'reg, 'rc>,
-    ) -> Result<ScopedJson<'rc>, RenderError> {
-        let map = h.param(0).unwrap();
-        let key = h.param(1).unwrap();
-        let mp: HashMap<String, Vec<Page>> = serde_json::from_value(map.value().clone()).unwrap();
-        let page: Page = serde_json::from_value(key.value().clone()).unwrap();
-        let k = format!("{}", page.id);
-        if page.is_leaf {
-            return Ok(json!([]).into());
-        }
-        let val = match mp.get(&k) {
-            Some(val) => val,
-            None => {
-                return Ok(json!([]).into());
-            }
-        };
-        let val_json = json!(val);
-        Ok(val_json.clone().into())
-    }
+/// Deserializes parameter `index` of the `helper` call, a missing or mistyped one failing the
+/// render rather than the process.
+fn param<T: serde::de::DeserializeOwned>(h: &Helper, helper: &'static str, index: usize) -> std::result::Result<T, RenderError> {
+    let param = h.param(index).ok_or(RenderErrorReason::ParamNotFoundForIndex(helper, index))?;
+    serde_json::from_value(param.value().clone()).map_err(|e| RenderErrorReason::SerdeError(e).into())
 }
 
-impl HelperDef for HasChildrenHelper {
+impl HelperDef for HeapLinkHelper {
     fn call<'reg: 'rc, 'rc>(
         &self,
         h: &Helper,
         _: &Handlebars,
-        _: &Context,
+        ctx: &Context,
         _: &mut RenderContext,
         out: &mut dyn Output,
     ) -> HelperResult {
-        let param = h.param(0).unwrap();
-        if param.is_value_missing() {
-            // TODO: recheck why is this happening?
-            out.write("false").unwrap();
-            return Ok(());
-        }
-        let pages: Vec<Page> = serde_json::from_value(param.value().clone()).unwrap();
-        if pages.len() == 0 {
-            out.write("false").unwrap();
-            return Ok(());
-        }
-        if pages[0].is_leaf {
-            out.write("false").unwrap();
+        let tid: Tid = param(h, "renderHeapLink", 0)?;
+        let rendered = match ctx.data().get("heap_blocks") {
+            Some(JsonValue::Array(blocks)) => blocks.contains(&JsonValue::from(tid.block_number)),
+            _ => false,
+        };
+        if rendered {
+            out.write(&format!("<a class=\"heap-link\" href=\"#heap-{}-{}\">{}</a>", tid.block_number, tid.offset_number, tid))?;
         } else {
-            out.write("true").unwrap();
+            out.write(&tid.to_string())?;
         }
         Ok(())
     }
 }
 
-impl HelperDef for IsArrayHelper {
+impl HelperDef for ContainsHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> std::result::Result<ScopedJson<'rc>, RenderError> {
+        let list = h.param(0).ok_or(RenderErrorReason::ParamNotFoundForIndex("contains", 0))?;
+        let needle = h.param(1).ok_or(RenderErrorReason::ParamNotFoundForIndex("contains", 1))?;
+        let contains = match list.value() {
+            JsonValue::Array(values) => values.contains(needle.value()),
+            _ => false,
+        };
+        Ok(JsonValue::Bool(contains).into())
+    }
+}
+
+impl HelperDef for TidRenderHelper {
     fn call<'reg: 'rc, 'rc>(
         &self,
         h: &Helper,
@@ -81,25 +72,17 @@ impl HelperDef for IsArrayHelper {
         _: &mut RenderContext,
         out: &mut dyn Output,
     ) -> HelperResult {
-        let param = h.param(0).unwrap();
-
-        // Check if the parameter is an array
-        let is_array = match param.value() {
-            JsonValue::Array(_) => true,
-            _ => false,
-        };
-
-        if is_array {
-            out.write("true").unwrap()
-        } else {
-            out.write("false").unwrap()
-        }
-
+        let tid: Tid = param(h, "renderTid", 0)?;
+        let val = format!("({}, {})", tid.block_number, tid.offset_number);
+        out.write(val.as_str())?;
         Ok(())
     }
 }
 
-impl HelperDef for TidRenderHelper {
+/// Writes a ratio with two decimals, like the text output does.
+struct RatioHelper;
+
+impl HelperDef for RatioHelper {
     fn call<'reg: 'rc, 'rc>(
         &self,
         h: &Helper,
@@ -108,18 +91,17 @@ impl HelperDef for TidRenderHelper {
         _: &mut RenderContext,
         out: &mut dyn Output,
     ) -> HelperResult {
-        let param = h.param(0).unwrap();
-        let tid: Tid = serde_json::from_value(param.value().clone()).unwrap();
-        let val = format!("({}, {})", tid.block_number, tid.offset_number);
-        out.write(val.as_str()).unwrap();
+        let ratio: f64 = param(h, "formatRatio", 0)?;
+        out.write(&format!("{:.2}", ratio))?;
         Ok(())
     }
 }
 
-// Implement your IsString helper
-struct IsStringHelper;
+struct BoundingBoxSvgHelper;
 
-impl HelperDef for IsStringHelper {
+const SVG_SIZE: f64 = 280.0;
+
+impl HelperDef for BoundingBoxSvgHelper {
     fn call<'reg: 'rc, 'rc>(
         &self,
         h: &Helper,
@@ -128,83 +110,140 @@ impl HelperDef for IsStringHelper {
         _: &mut RenderContext,
         out: &mut dyn Output,
     ) -> HelperResult {
-        let param = h.param(0).unwrap();
+        let page: Page = param(h, "renderBoundingBoxes", 0)?;
+        out.write(bounding_box_svg(&page).as_str())?;
+        Ok(())
+    }
+}
 
-        // Check if the parameter is a string
-        let is_string = match param.value() {
-            JsonValue::String(_) => true,
-            _ => false,
-        };
+/// Draws the keys of a GiST page as an SVG overlay, scaled to the union of all keys on the page.
+fn bounding_box_svg(page: &Page) -> String {
+    let boxes: Vec<(&String, BoundingBox)> = page.items.iter()
+        .filter_map(|item| item.bounding_box.map(|bounding_box| (&item.value, bounding_box)))
+        .collect();
+    if boxes.is_empty() {
+        return "".to_string();
+    }
+    let x_min = boxes.iter().map(|(_, b)| b.x_min).fold(f64::INFINITY, f64::min);
+    let y_min = boxes.iter().map(|(_, b)| b.y_min).fold(f64::INFINITY, f64::min);
+    let x_max = boxes.iter().map(|(_, b)| b.x_max).fold(f64::NEG_INFINITY, f64::max);
+    let y_max = boxes.iter().map(|(_, b)| b.y_max).fold(f64::NEG_INFINITY, f64::max);
+    let scale = SVG_SIZE / (x_max - x_min).max(y_max - y_min).max(f64::EPSILON);
 
-        if is_string {
-            out.write("true").unwrap();
+    let mut svg = format!("<svg class=\"bounding-boxes\" width=\"{0}\" height=\"{0}\">", SVG_SIZE);
+    for (value, b) in boxes {
+        let x = (b.x_min - x_min) * scale;
+        // SVG's y axis points down
+        let y = (y_max - b.y_max) * scale;
+        let width = (b.x_max - b.x_min) * scale;
+        let height = (b.y_max - b.y_min) * scale;
+        let title = format!("<title>{}</title>", html_escape(value));
+        if width == 0.0 && height == 0.0 {
+            svg.push_str(&format!("<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"2\">{}</circle>", x, y, title));
         } else {
-            out.write("false").unwrap();
+            svg.push_str(&format!("<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\">{}</rect>", x, y, width, height, title));
         }
+    }
+    svg.push_str("</svg>");
+    svg
+}
 
-        Ok(())
+/// Compiled into the binary so rendering doesn't depend on the working directory.
+const TEMPLATES: [(&str, &str); 10] = [
+    ("render_tree", include_str!("../templates/render_tree.hbs")),
+    ("render_page", include_str!("../templates/render_page.hbs")),
+    ("render_level", include_str!("../templates/render_level.hbs")),
+    ("render_brin", include_str!("../templates/render_brin.hbs")),
+    ("render_hash", include_str!("../templates/render_hash.hbs")),
+    ("render_bucket", include_str!("../templates/render_bucket.hbs")),
+    ("render_head", include_str!("../templates/render_head.hbs")),
+    ("render_heap", include_str!("../templates/render_heap.hbs")),
+    ("render_style", include_str!("../templates/render_style.hbs")),
+    ("render_serve", include_str!("../templates/render_serve.hbs")),
+];
+
+pub(crate) fn get_handlebars<'reg>() -> Handlebars<'reg> {
+    let mut handlebars = Handlebars::new();
+    for (name, template) in TEMPLATES {
+        handlebars.register_template_string(name, template).unwrap();
     }
+    handlebars.register_helper("renderTid", Box::new(TidRenderHelper));
+    handlebars.register_helper("contains", Box::new(ContainsHelper));
+    handlebars.register_helper("renderHeapLink", Box::new(HeapLinkHelper));
+    handlebars.register_helper("renderBoundingBoxes", Box::new(BoundingBoxSvgHelper));
+    handlebars.register_helper("formatRatio", Box::new(RatioHelper));
+    handlebars
 }
 
-fn get_parent_child_mapping(pages: Vec<Page>) -> HashMap<String, Vec<Page>> {
-    let mut parent_child_map: HashMap<String, Vec<Page>> = HashMap::new();
-    for page in pages {
-        let mut child_pages: Vec<Page> = vec![];
-        if page.is_leaf {
-            // if leaf page, don't add.
-            return parent_child_map;
-        }
-        for item in page.items.clone() {
-            if let Some(child) = item.child {
-                let mut child_hash_map = get_parent_child_mapping(vec![*child.clone()]);
-                for (page, child) in child_hash_map {
-                    if parent_child_map.contains_key(&page) {
-                        let mut existing_child = parent_child_map.get_mut(&page).unwrap();
-                        existing_child.append(&mut child.clone());
-                    } else {
-                        parent_child_map.insert(page, child.clone());
-                    }
-                }
-                child_pages.push(*child);
-            }
-        }
-        parent_child_map.insert(format!("{}", page.id), child_pages);
+pub(crate) fn write_output(rendered: String, output_path: &Path) -> Result<()> {
+    let mut file = File::create(output_path)?;
+    file.write_all(rendered.as_bytes())?;
+    Ok(())
+}
+
+/// Block numbers of the rendered heap pages, the only ones `renderHeapLink` links into.
+fn heap_blocks(heap_pages: &[HeapPage]) -> JsonValue {
+    heap_pages.iter().map(|heap_page| heap_page.block_number).collect()
+}
+
+/// The tree without its pages, the root page, and the pages below the root grouped by their
+/// distance to it, each group rendered as a column. The SP-GiST tree of NULL keys, if any, is
+/// rendered apart like the GIN pending list, its pages from its root down.
+fn tree_context(tree: &Tree) -> Result<serde_json::Map<String, JsonValue>> {
+    let mut tree_value = serde_json::to_value(tree)?;
+    if let Some(tree_value) = tree_value.as_object_mut() {
+        tree_value.remove("pages");
     }
-    parent_child_map
+    let levels: Vec<Vec<&Page>> = tree.pages.levels(tree.root).into_iter().skip(1).collect();
+    let nulls_tree: Vec<&Page> = tree.nulls_root.map(|nulls_root| tree.pages.levels(nulls_root).into_iter().flatten().collect()).unwrap_or_default();
+
+    let mut map = serde_json::Map::new();
+    map.insert("tree".to_string(), tree_value);
+    map.insert("index_type".to_string(), serde_json::to_value(&tree.index_type)?);
+    map.insert("root".to_string(), serde_json::to_value(tree.root())?);
+    map.insert("levels".to_string(), serde_json::to_value(&levels)?);
+    map.insert("nulls_tree".to_string(), serde_json::to_value(&nulls_tree)?);
+    map.insert("deduplication".to_string(), serde_json::to_value(tree.deduplication())?);
+    map.insert("suffix_truncation".to_string(), serde_json::to_value(tree.suffix_truncation())?);
+    map.insert("heap_blocks".to_string(), heap_blocks(&tree.heap_pages));
+    Ok(map)
 }
 
-pub fn render(tree: Tree, output_path: &Path) {
-    let mut templates_dir = env::current_dir().unwrap();
-    templates_dir = templates_dir.join("src").join("templates");
-    let mut handlebars = Handlebars::new();
+pub fn render(tree: Tree, output_path: &Path) -> Result<()> {
+    write_output(render_tree_html(&tree)?, output_path)
+}
+
+pub(crate) fn render_tree_html(tree: &Tree) -> Result<String> {
+    Ok(get_handlebars().render("render_tree", &tree_context(tree)?)?)
+}
 
+pub fn render_brin(index: BrinIndex, output_path: &Path) -> Result<()> {
+    let handlebars = get_handlebars();
 
-    let render_tree_file = templates_dir.join("render_tree.hbs");
-    let render_page_file = templates_dir.join("render_page.hbs");
-    let render_level_file = templates_dir.join("render_level.hbs");
-    handlebars.register_template_file("render_tree", render_tree_file).unwrap();
-    handlebars.register_template_file("render_page", render_page_file).unwrap();
-    handlebars.register_template_file("render_level", render_level_file).unwrap();
-    handlebars.register_helper("isArray", Box::new(IsArrayHelper));
-    handlebars.register_helper("isString", Box::new(IsStringHelper));
-    handlebars.register_helper("renderTid", Box::new(TidRenderHelper));
-    handlebars.register_helper("hasChildren", Box::new(HasChildrenHelper));
-    handlebars.register_helper("sample-lookup", Box::new(SampleLookupHelper));
+    let mut map = serde_json::Map::new();
+    map.insert("index".to_string(), serde_json::to_value(&index)?);
+    map.insert("index_type".to_string(), serde_json::to_value(&index.index_type)?);
+    let rendered = handlebars.render("render_brin", &map)?;
+    write_output(rendered, output_path)
+}
+
+pub fn render_hash(index: HashIndex, output_path: &Path) -> Result<()> {
+    let handlebars = get_handlebars();
 
     let mut map = serde_json::Map::new();
-    map.insert("tree".to_string(), serde_json::to_value(&tree).unwrap());
-    map.insert("index_type".to_string(), serde_json::to_value(&tree.index_type.unwrap()).unwrap());
-    map.insert("parent_child_map".to_string(), serde_json::to_value(&get_parent_child_mapping(vec![tree.root.clone()])).unwrap());
-    let rendered = handlebars.render("render_tree", &map).unwrap();
-    let mut file = File::create(output_path).expect("Unable to create file");
-    file.write_all(rendered.as_bytes()).expect("Unable to write data to file");
+    map.insert("index".to_string(), serde_json::to_value(&index)?);
+    map.insert("index_type".to_string(), serde_json::to_value(&index.index_type)?);
+    map.insert("heap_blocks".to_string(), heap_blocks(&index.heap_pages));
+    let rendered = handlebars.render("render_hash", &map)?;
+    write_output(rendered, output_path)
 }
 
 #[cfg(test)]
 mod tests {
     use serde_json::json;
     use crate::core::{Page, Tid};
-    use crate::core::structs::Item;
+    use crate::core::structs::{Deduplication, Item};
+    use crate::core::test_support::{sample_btree, sample_index_info};
 
     #[test]
     pub fn test_string_to_page_conversion() {
@@ -221,12 +260,29 @@ mod tests {
                     block_number: 1,
                     offset_number: 2,
                 }),
+                tids: None,
+                dead: None,
+                htid: None,
+                key_size: None,
+                key_attributes: None,
+                truncated: None,
+                key: None,
+                row: None,
+                row_visible: None,
+                bounding_box: None,
+                state: None,
+                next_offset: None,
             }],
             prev_page_id: Some(1),
             next_page_id: Some(1),
             high_key: None,
             prev_item: None,
             nb_items: None,
+            flags: vec![],
+            lsn: None,
+            nsn: None,
+            overlapping_pairs: None,
+            deduplication: None,
         };
         let v = vec![original_page];
         let json_str = json!(v).to_string();
@@ -236,109 +292,39 @@ mod tests {
     }
 
     #[test]
-    pub fn test_get_parent_child_mapping() {
-        let leaf_a = Page {
-            id: 0,
-            level: 0,
-            is_leaf: true,
-            is_root: false,
-            items: vec![],
-            prev_page_id: None,
-            next_page_id: None,
-            high_key: None,
-            prev_item: None,
-            nb_items: None,
-        };
-        let leaf_b = Page {
-            id: 1,
-            level: 0,
-            is_leaf: true,
-            is_root: false,
-            items: vec![],
-            prev_page_id: None,
-            next_page_id: None,
-            high_key: None,
-            prev_item: None,
-            nb_items: None,
-        };
-        let leaf_c = Page {
-            id: 2,
-            level: 0,
-            is_leaf: true,
-            is_root: false,
-            items: vec![],
-            prev_page_id: None,
-            next_page_id: None,
-            high_key: None,
-            prev_item: None,
-            nb_items: None,
-        };
-        let par_ab = Page {
-            id: 3,
-            level: 1,
-            is_leaf: false,
-            is_root: false,
-            items: vec![Item {
-                value: "abc".to_string(),
-                child: Some(Box::new(leaf_a.clone())),
-                pointer: None,
-                obj_id: None,
-            }, Item {
-                value: "def".to_string(),
-                child: Some(Box::new(leaf_b.clone())),
-                pointer: None,
-                obj_id: None,
-            }],
-            prev_page_id: None,
-            next_page_id: None,
-            high_key: None,
-            prev_item: None,
-            nb_items: None,
-        };
-        let par_c = Page {
-            id: 4,
-            level: 1,
-            is_leaf: false,
-            is_root: false,
-            items: vec![Item {
-                value: "ghi".to_string(),
-                child: Some(Box::new(leaf_c.clone())),
-                pointer: None,
-                obj_id: None,
-            }],
-            prev_page_id: None,
-            next_page_id: None,
-            high_key: None,
-            prev_item: None,
-            nb_items: None,
-        };
-        let root = Page {
-            id: 5,
-            level: 2,
-            is_leaf: false,
-            is_root: true,
-            items: vec![Item {
-                value: "jkl".to_string(),
-                child: Some(Box::new(par_ab.clone())),
-                pointer: None,
-                obj_id: None,
-            }, Item {
-                value: "mno".to_string(),
-                child: Some(Box::new(par_c.clone())),
-                pointer: None,
-                obj_id: None,
-            }],
-            prev_page_id: None,
-            next_page_id: None,
-            high_key: None,
-            prev_item: None,
-            nb_items: None,
-        };
-        let mut expected_map = std::collections::HashMap::new();
-        expected_map.insert("5".to_string(), vec![par_ab, par_c]);
-        expected_map.insert("3".to_string(), vec![leaf_a, leaf_b]);
-        expected_map.insert("4".to_string(), vec![leaf_c]);
-        let parent_child_map = super::get_parent_child_mapping(vec![root]);
-        assert_eq!(parent_child_map, expected_map);
+    pub fn test_tree_context() {
+        let mut tree = sample_btree(sample_index_info(&["id"], 1));
+        tree.pages.get_mut(1).unwrap().deduplication = Some(Deduplication::new(2, 1, 4));
+
+        let context = super::tree_context(&tree).unwrap();
+        assert_eq!("btree", context["index_type"]);
+        assert_eq!(3, context["root"]["id"]);
+        assert!(context["tree"].get("pages").is_none());
+        let levels: Vec<Vec<i64>> = serde_json::from_value::<Vec<Vec<Page>>>(context["levels"].clone()).unwrap().iter()
+            .map(|level| level.iter().map(|page| page.id).collect())
+            .collect();
+        assert_eq!(vec![vec![1, 2]], levels);
+        assert_eq!(4, context["deduplication"]["heap_tids"]);
+
+        let html = super::render_tree_html(&tree).unwrap();
+        assert!(html.contains("<li><label>Heap TIDs per tuple: </label>2.00</li>"));
+        // allequalimage is unknown before btree version 4
+        assert!(!html.contains("Deduplication safe"));
+    }
+
+    #[test]
+    pub fn test_helper_errors() {
+        let handlebars = super::get_handlebars();
+        let tid = json!({"tid": {"block_number": 1, "offset_number": 2}});
+        assert_eq!("(1, 2)", handlebars.render_template("{{renderTid tid}}", &tid).unwrap());
+        // only TIDs into rendered heap pages link to their tuple
+        assert_eq!("(1,2)", handlebars.render_template("{{renderHeapLink tid}}", &tid).unwrap());
+        let rendered = json!({"tid": {"block_number": 1, "offset_number": 2}, "heap_blocks": [1]});
+        assert_eq!("<a class=\"heap-link\" href=\"#heap-1-2\">(1,2)</a>", handlebars.render_template("{{renderHeapLink tid}}", &rendered).unwrap());
+        // a value that isn't a TID, or none at all, fails the render instead of panicking
+        assert!(handlebars.render_template("{{renderTid tid}}", &json!({"tid": "x"})).is_err());
+        assert_eq!("1.33", handlebars.render_template("{{formatRatio ratio}}", &json!({"ratio": 4.0 / 3.0})).unwrap());
+        assert!(handlebars.render_template("{{renderHeapLink}}", &tid).is_err());
+        assert!(handlebars.render_template("{{#if (contains tid)}}{{/if}}", &tid).is_err());
     }
 }
\ No newline at end of file
@@ -0,0 +1,119 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use log::info;
+use serde::Serialize;
+use tiny_http::{Header, Request, Response, Server};
+use crate::core::btree::BTREE_METAPAGE;
+use crate::core::json::{JsonExport, JsonIndexInfo, JsonPage};
+use crate::core::renderer::get_handlebars;
+use crate::db::{get_index_blocks, get_metadata_page, get_page, Client, IndexInfo};
+use crate::error::{Error, Result};
+
+#[derive(Debug, PartialEq)]
+enum Route {
+    /// The page the tree is expanded in.
+    Shell,
+    /// The JSON export document without pages: index info, metapage and root.
+    Meta,
+    /// A single page as in the `pages` of the JSON export.
+    Page(i64),
+    NotFound,
+}
+
+fn route(url: &str) -> Route {
+    let path = url.split('?').next().unwrap_or_default();
+    match path {
+        "/" => Route::Shell,
+        "/meta" => Route::Meta,
+        _ => match path.strip_prefix("/page/").and_then(|block_number| block_number.parse::<i64>().ok()) {
+            Some(block_number) if block_number >= 0 => Route::Page(block_number),
+            _ => Route::NotFound,
+        },
+    }
+}
+
+/// Listens on `listen`, for `serve` to answer on.
+pub fn bind(listen: &str) -> Result<Server> {
+    Ok(Server::http(listen).map_err(|e| io::Error::other(format!("Could not listen on {}: {}", listen, e)))?)
+}
+
+/// Serves a btree index over HTTP until the process is stopped. Only the shell is sent up front,
+/// the browser then asks for pages one at a time as they are expanded, each fetched from the
+/// database on request so the view stays current. A query failing only fails its request, with a
+/// 500 carrying the error message.
+pub fn serve(server: Server, client: Arc<Mutex<Client>>, index_name: String, index_info: Arc<IndexInfo>) -> Result<()> {
+    let index = JsonIndexInfo {
+        name: index_name.clone(),
+        index_type: index_info.index_type.clone(),
+        table: index_info.table_name.clone(),
+        columns: index_info.columns.clone(),
+    };
+    let shell = get_handlebars().render("render_serve", &serde_json::json!({ "index": index }))?;
+    info!("Serving {} on http://{}", index_name, server.server_addr());
+
+    for request in server.incoming_requests() {
+        info!("{} {}", request.method(), request.url());
+        let response = match route(request.url()) {
+            Route::Shell => respond(request, shell.clone(), "text/html; charset=utf-8", 200),
+            Route::Meta => match meta(Arc::clone(&client), &index_name, &index) {
+                Ok(meta) => respond_json(request, &meta),
+                Err(e) => respond(request, e.to_string(), "text/plain", 500),
+            },
+            Route::Page(block_number) => match page(Arc::clone(&client), block_number, &index_name, index_info.clone()) {
+                Ok(Some(page)) => respond_json(request, &page),
+                Ok(None) => respond(request, "Not found".to_string(), "text/plain", 404),
+                Err(e) => respond(request, e.to_string(), "text/plain", 500),
+            },
+            Route::NotFound => respond(request, "Not found".to_string(), "text/plain", 404),
+        };
+        if let Err(e) = response {
+            info!("Could not send the response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn meta(client: Arc<Mutex<Client>>, index_name: &str, index: &JsonIndexInfo) -> Result<JsonExport> {
+    let metadata_page = get_metadata_page(client, index_name.to_string())?;
+    let mut meta = JsonExport::new(index.clone(), Some(serde_json::to_value(&metadata_page)?), &[]);
+    meta.root = Some(metadata_page.root);
+    Ok(meta)
+}
+
+/// The page at `block_number`, or `None` for the metapage, which `/meta` serves, and past the
+/// end of the index.
+fn page(client: Arc<Mutex<Client>>, block_number: i64, index_name: &String, index_info: Arc<IndexInfo>) -> Result<Option<JsonPage>> {
+    if block_number == BTREE_METAPAGE || block_number >= get_index_blocks(Arc::clone(&client), index_name)? {
+        return Ok(None);
+    }
+    let page = get_page(client, block_number, index_name, index_info)?;
+    Ok(Some(JsonPage::from(&page)))
+}
+
+fn respond_json<T: Serialize>(request: Request, value: &T) -> std::io::Result<()> {
+    match serde_json::to_string(value) {
+        Ok(body) => respond(request, body, "application/json", 200),
+        Err(e) => respond(request, Error::from(e).to_string(), "text/plain", 500),
+    }
+}
+
+fn respond(request: Request, body: String, content_type: &str, status: u16) -> std::io::Result<()> {
+    let header = Header::from_bytes("Content-Type", content_type).unwrap();
+    request.respond(Response::from_string(body).with_header(header).with_status_code(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{route, Route};
+
+    #[test]
+    pub fn test_route() {
+        assert_eq!(Route::Shell, route("/"));
+        assert_eq!(Route::Meta, route("/meta"));
+        assert_eq!(Route::Page(12), route("/page/12"));
+        assert_eq!(Route::Page(3), route("/page/3?_=1700000000"));
+        assert_eq!(Route::NotFound, route("/page/-1"));
+        assert_eq!(Route::NotFound, route("/page/abc"));
+        assert_eq!(Route::NotFound, route("/favicon.ico"));
+    }
+}
@@ -0,0 +1,309 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use log::debug;
+use crate::core::arena::walk_roots;
+use crate::core::raw_page::{format_datum, hex_string, read_item_pointer, read_u16, read_u32, varlena_payload};
+use crate::core::structs::{Tid, Tree};
+use crate::db::{get_spgist_key_type, get_spgist_pages, IndexInfo, Pool};
+use crate::error::Result;
+
+/// Block number of the root of the tree holding non-null keys.
+pub const SPGIST_ROOT_BLKNO: i64 = 1;
+/// Block number of the root of the tree holding NULL keys.
+pub const SPGIST_NULL_BLKNO: i64 = 2;
+
+const SPGIST_META: u16 = 1 << 0;
+const SPGIST_DELETED: u16 = 1 << 1;
+const SPGIST_LEAF: u16 = 1 << 2;
+const SPGIST_NULLS: u16 = 1 << 3;
+
+const SPGIST_LIVE: u32 = 0;
+const SPGIST_REDIRECT: u32 = 1;
+const SPGIST_DEAD: u32 = 2;
+
+/// MAXALIGNed sizes of the inner, node and leaf tuple headers.
+const SGITHDRSZ: usize = 8;
+const SGNTHDRSZ: usize = 8;
+const SGLTHDRSZ: usize = 16;
+const SGNT_SIZE_MASK: u16 = 0x1FFF;
+const SGNT_NULL_MASK: u16 = 0x8000;
+const SGLT_NEXT_OFFSET_MASK: u16 = 0x3FFF;
+
+/// `PGSQL_AF_INET` and `PGSQL_AF_INET6` as stored in `inet_struct`.
+const PGSQL_AF_INET: u8 = 2;
+const PGSQL_AF_INET6: u8 = 3;
+
+pub fn generate_spgist(pool: &Pool, index_name: String, index_info: Arc<IndexInfo>) -> Result<Tree> {
+    let key_type = SpGistKeyType::from_type_name(&get_spgist_key_type(pool.client(), index_name.clone())?);
+    debug!("SP-GiST key type: {:?}", key_type);
+    let mut pages = walk_roots(&[SPGIST_ROOT_BLKNO, SPGIST_NULL_BLKNO], |page_ids, _| {
+        pool.fetch(page_ids, |client, page_ids| get_spgist_pages(client, page_ids, &index_name, &key_type))
+    })?;
+    pages.set_levels_from_children(SPGIST_ROOT_BLKNO);
+    pages.set_levels_from_children(SPGIST_NULL_BLKNO);
+    Ok(Tree::new_spgist(SPGIST_ROOT_BLKNO, SPGIST_NULL_BLKNO, pages, index_name, index_info))
+}
+
+/// The datums stored in prefixes, node labels and leaves depend on the operator class, which
+/// in turn depends on the indexed type. Types without a dedicated decoder are shown as text or hex.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpGistKeyType {
+    /// Radix tree: text prefixes, one character per node label, leaves hold the remaining suffix.
+    Text,
+    /// Radix tree over networks: cidr prefixes, unlabelled nodes and inet leaves.
+    Inet,
+    /// Quad tree (point centroid prefix) or k-d tree (float8 prefix), with point leaves.
+    Point,
+    Other,
+}
+
+impl SpGistKeyType {
+    pub fn from_type_name(type_name: &str) -> Self {
+        match type_name {
+            "text" | "character varying" | "name" => SpGistKeyType::Text,
+            "inet" | "cidr" => SpGistKeyType::Inet,
+            "point" => SpGistKeyType::Point,
+            _ => SpGistKeyType::Other,
+        }
+    }
+
+    fn format_prefix(&self, bytes: &[u8]) -> String {
+        match self {
+            SpGistKeyType::Inet => format_inet(bytes, true),
+            SpGistKeyType::Point if bytes.len() == 8 => format!("{}", read_f64(bytes, 0).unwrap()),
+            SpGistKeyType::Point => format_point(bytes),
+            _ => format_datum(bytes),
+        }
+    }
+
+    fn format_label(&self, bytes: &[u8]) -> String {
+        match self {
+            // labels are int2 datums stored by value, -1 marks strings ending at this node
+            SpGistKeyType::Text => match read_u16(bytes, 0).map(|label| label as i16) {
+                Some(-1) => "end of string".to_string(),
+                Some(label) if (0x20..0x7F).contains(&label) => (label as u8 as char).to_string(),
+                Some(label) => format!("{}", label),
+                None => hex_string(bytes),
+            },
+            _ => hex_string(bytes),
+        }
+    }
+
+    fn format_leaf(&self, bytes: &[u8]) -> String {
+        match self {
+            SpGistKeyType::Inet => format_inet(bytes, false),
+            SpGistKeyType::Point => format_point(bytes),
+            _ => format_datum(bytes),
+        }
+    }
+}
+
+fn read_f64(bytes: &[u8], offset: usize) -> Option<f64> {
+    let b = bytes.get(offset..offset + 8)?;
+    Some(f64::from_le_bytes(b.try_into().ok()?))
+}
+
+fn format_point(bytes: &[u8]) -> String {
+    match (read_f64(bytes, 0), read_f64(bytes, 8)) {
+        (Some(x), Some(y)) => format!("({},{})", x, y),
+        _ => hex_string(bytes),
+    }
+}
+
+/// Formats an `inet_struct` varlena (family, netmask bits, address) like `inet_out` does:
+/// the netmask is omitted for host addresses unless `always_bits` is set, as for cidr.
+fn format_inet(bytes: &[u8], always_bits: bool) -> String {
+    let payload = match varlena_payload(bytes) {
+        Some(payload) if payload.len() >= 2 => payload,
+        _ => return hex_string(bytes),
+    };
+    let (family, bits, address) = (payload[0], payload[1], &payload[2..]);
+    let (text, max_bits) = match (family, address.len()) {
+        (PGSQL_AF_INET, 4) => (Ipv4Addr::new(address[0], address[1], address[2], address[3]).to_string(), 32),
+        (PGSQL_AF_INET6, 16) => (Ipv6Addr::from(<[u8; 16]>::try_from(address).unwrap()).to_string(), 128),
+        _ => return hex_string(bytes),
+    };
+    if always_bits || bits != max_bits {
+        format!("{}/{}", text, bits)
+    } else {
+        text
+    }
+}
+
+/// Names of the flags set in a page's `SpGistPageOpaqueData`, and whether it is a leaf page.
+pub fn spgist_page_flags(special: &[u8]) -> (Vec<String>, bool) {
+    let flags = read_u16(special, 0).unwrap_or(0);
+    let names = [
+        (SPGIST_META, "meta"),
+        (SPGIST_DELETED, "deleted"),
+        (SPGIST_LEAF, "leaf"),
+        (SPGIST_NULLS, "nulls"),
+    ].iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    (names, flags & SPGIST_LEAF != 0)
+}
+
+/// A node of an inner tuple: its label, if the operator class uses labels, and the tuple it
+/// points to, which is missing for nodes that haven't received any value yet.
+#[derive(Debug, PartialEq)]
+pub struct SpGistNode {
+    pub label: Option<String>,
+    pub downlink: Option<Tid>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SpGistTuple {
+    Inner {
+        all_the_same: bool,
+        prefix: Option<String>,
+        nodes: Vec<SpGistNode>,
+    },
+    Leaf {
+        value: String,
+        heap_tid: Tid,
+        /// Offset of the next tuple of the same chain on this page, 0 at the end of the chain.
+        next_offset: u16,
+    },
+    /// A tuple that has been moved (`redirect` to its new location), deleted (`dead`), or that
+    /// only keeps the offsets of its chain stable (`placeholder`).
+    Dead {
+        state: String,
+        redirect: Option<Tid>,
+    },
+}
+
+/// Decodes a tuple of an SP-GiST page. Every tuple type starts with the same 2 bit state, and
+/// live tuples are inner or leaf tuples depending on the page they are on.
+pub fn decode_spgist_tuple(bytes: &[u8], is_leaf: bool, key_type: &SpGistKeyType) -> Option<SpGistTuple> {
+    let header = read_u32(bytes, 0)?;
+    match header & 0x03 {
+        SPGIST_LIVE if is_leaf => decode_leaf_tuple(bytes, header, key_type),
+        SPGIST_LIVE => decode_inner_tuple(bytes, header, key_type),
+        state => {
+            let redirect = if state == SPGIST_REDIRECT { read_item_pointer(bytes, 6) } else { None };
+            let state = match state {
+                SPGIST_REDIRECT => "redirect",
+                SPGIST_DEAD => "dead",
+                _ => "placeholder",
+            };
+            Some(SpGistTuple::Dead { state: state.to_string(), redirect })
+        }
+    }
+}
+
+fn decode_inner_tuple(bytes: &[u8], header: u32, key_type: &SpGistKeyType) -> Option<SpGistTuple> {
+    let all_the_same = header & (1 << 2) != 0;
+    let node_count = ((header >> 3) & 0x1FFF) as usize;
+    let prefix_size = (header >> 16) as usize;
+    let size = (read_u16(bytes, 4)? as usize).min(bytes.len());
+    let prefix = if prefix_size > 0 {
+        Some(key_type.format_prefix(bytes.get(SGITHDRSZ..SGITHDRSZ + prefix_size)?))
+    } else {
+        None
+    };
+
+    let mut nodes = vec![];
+    let mut offset = SGITHDRSZ + prefix_size;
+    for _ in 0..node_count {
+        let downlink = read_item_pointer(bytes, offset)?;
+        let info = read_u16(bytes, offset + 6)?;
+        let node_size = ((info & SGNT_SIZE_MASK) as usize).max(SGNTHDRSZ);
+        let label = if info & SGNT_NULL_MASK == 0 {
+            Some(key_type.format_label(bytes.get(offset + SGNTHDRSZ..(offset + node_size).min(size))?))
+        } else {
+            None
+        };
+        nodes.push(SpGistNode {
+            label,
+            downlink: if downlink.block_number == u32::MAX { None } else { Some(downlink) },
+        });
+        offset += node_size;
+    }
+    Some(SpGistTuple::Inner { all_the_same, prefix, nodes })
+}
+
+fn decode_leaf_tuple(bytes: &[u8], header: u32, key_type: &SpGistKeyType) -> Option<SpGistTuple> {
+    let size = ((header >> 2) as usize).min(bytes.len());
+    let info = read_u16(bytes, 4)?;
+    let heap_tid = read_item_pointer(bytes, 6)?;
+    let datum = bytes.get(SGLTHDRSZ..size).unwrap_or(&[]);
+    let value = if datum.is_empty() { "NULL".to_string() } else { key_type.format_leaf(datum) };
+    Some(SpGistTuple::Leaf {
+        value,
+        heap_tid,
+        next_offset: info & SGLT_NEXT_OFFSET_MASK,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Tid;
+    use super::{decode_spgist_tuple, spgist_page_flags, SpGistKeyType, SpGistNode, SpGistTuple};
+
+    fn node(block: u32, offset: u16, label: Option<i16>) -> Vec<u8> {
+        let mut bytes = ((block >> 16) as u16).to_le_bytes().to_vec();
+        bytes.extend((block as u16).to_le_bytes());
+        bytes.extend(offset.to_le_bytes());
+        match label {
+            Some(label) => {
+                bytes.extend(16u16.to_le_bytes());
+                bytes.extend((label as i64).to_le_bytes());
+            }
+            None => bytes.extend((8u16 | 0x8000).to_le_bytes()),
+        }
+        bytes
+    }
+
+    #[test]
+    pub fn test_decode_text_inner_tuple() {
+        // two nodes under the prefix 'ab', a short varlena padded to 8 bytes
+        let header: u32 = (2 << 3) | (8 << 16);
+        let mut bytes = header.to_le_bytes().to_vec();
+        bytes.extend(48u16.to_le_bytes());
+        bytes.extend([0, 0]);
+        bytes.extend([0x07, b'a', b'b', 0, 0, 0, 0, 0]);
+        bytes.extend(node(3, 5, Some(b'c' as i16)));
+        bytes.extend(node(u32::MAX, 0xFFFF, Some(-1)));
+        let tuple = decode_spgist_tuple(&bytes, false, &SpGistKeyType::Text).unwrap();
+        assert_eq!(SpGistTuple::Inner {
+            all_the_same: false,
+            prefix: Some("ab".to_string()),
+            nodes: vec![
+                SpGistNode { label: Some("c".to_string()), downlink: Some(Tid { block_number: 3, offset_number: 5 }) },
+                SpGistNode { label: Some("end of string".to_string()), downlink: None },
+            ],
+        }, tuple);
+    }
+
+    #[test]
+    pub fn test_decode_inet_leaf_and_redirect_tuples() {
+        let inet = [0x0F, 2, 32, 10, 0, 0, 1, 0];
+        let size = 16 + inet.len() as u32;
+        let mut bytes = (size << 2).to_le_bytes().to_vec();
+        bytes.extend(4u16.to_le_bytes());
+        bytes.extend([0, 0, 7, 0, 2, 0]);
+        bytes.extend([0, 0, 0, 0]);
+        bytes.extend(inet);
+        assert_eq!(Some(SpGistTuple::Leaf {
+            value: "10.0.0.1".to_string(),
+            heap_tid: Tid { block_number: 7, offset_number: 2 },
+            next_offset: 4,
+        }), decode_spgist_tuple(&bytes, true, &SpGistKeyType::Inet));
+
+        let mut redirect = ((16u32 << 2) | 1).to_le_bytes().to_vec();
+        redirect.extend([0, 0, 0, 0, 9, 0, 3, 0, 0, 0, 0, 0]);
+        assert_eq!(Some(SpGistTuple::Dead {
+            state: "redirect".to_string(),
+            redirect: Some(Tid { block_number: 9, offset_number: 3 }),
+        }), decode_spgist_tuple(&redirect, true, &SpGistKeyType::Inet));
+    }
+
+    #[test]
+    pub fn test_spgist_page_flags() {
+        assert_eq!((vec!["leaf".to_string()], true), spgist_page_flags(&[4, 0, 0, 0, 0, 0, 0x82, 0xFF]));
+        assert_eq!((vec!["leaf".to_string(), "nulls".to_string()], true), spgist_page_flags(&[12, 0]));
+        assert_eq!((vec![], false), spgist_page_flags(&[0, 0]));
+    }
+}
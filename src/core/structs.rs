@@ -1,9 +1,11 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::rc::Rc;
+use std::sync::Arc;
 use bytes::{Buf, BufMut, BytesMut};
 use postgres_types::{FromSql, IsNull, to_sql_checked, ToSql, Type};
-use crate::db::IndexInfo;
+use crate::core::arena::PageArena;
+use crate::db::{IndexInfo, SPLIT_NOT_IN_PARENT};
 use serde::{Serialize, Deserialize};
 
 
@@ -14,6 +16,10 @@ pub struct MetadataPage {
     pub level: i64,
     pub fast_root: i64,
     pub fast_level: i64,
+    /// Whether deduplication is safe for the index (`btm_allequalimage`), `None` before btree
+    /// version 4. Without it leaf tuples never get posting lists.
+    #[serde(default)]
+    pub allequalimage: Option<bool>,
 }
 
 impl MetadataPage {
@@ -24,10 +30,25 @@ impl MetadataPage {
             level,
             fast_root,
             fast_level,
+            allequalimage: None,
         }
     }
 }
 
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
+pub struct GinMetadataPage {
+    pub pending_head: i64,
+    pub pending_tail: i64,
+    pub tail_free_size: i32,
+    pub n_pending_pages: i64,
+    pub n_pending_tuples: i64,
+    pub n_total_pages: i64,
+    pub n_entry_pages: i64,
+    pub n_data_pages: i64,
+    pub n_entries: i64,
+    pub version: i32,
+}
+
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Page {
@@ -41,6 +62,17 @@ pub struct Page {
     pub high_key: Option<String>,
     pub prev_item: Option<Box<Item>>,
     pub nb_items: Option<i32>,
+    #[serde(default)]
+    pub flags: Vec<String>,
+    #[serde(default)]
+    pub lsn: Option<String>,
+    #[serde(default)]
+    pub nsn: Option<String>,
+    #[serde(default)]
+    pub overlapping_pairs: Option<i64>,
+    /// Leaf tuples against the heap TIDs they hold, on btree leaf pages.
+    #[serde(default)]
+    pub deduplication: Option<Deduplication>,
 }
 
 impl Page {
@@ -56,76 +88,492 @@ impl Page {
             high_key: None,
             prev_item: None,
             nb_items: None,
+            flags: vec![],
+            lsn: None,
+            nsn: None,
+            overlapping_pairs: None,
+            deduplication: None,
         }
     }
+
+    /// Whether this GiST page was split after the parent it was reached from was written, its
+    /// right sibling then holding keys the parent has no downlink for yet.
+    pub fn split_not_in_parent(&self) -> bool {
+        self.flags.iter().any(|flag| flag == SPLIT_NOT_IN_PARENT)
+    }
+}
+
+/// How many heap TIDs the leaf tuples of a btree page, or of a whole index, hold. Without
+/// deduplication every tuple holds one and the ratio is 1.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct Deduplication {
+    pub tuples: i64,
+    /// Tuples carrying a posting list.
+    pub posting_tuples: i64,
+    pub heap_tids: i64,
+    /// Heap TIDs per tuple.
+    pub ratio: f64,
+}
+
+impl Deduplication {
+    pub fn new(tuples: i64, posting_tuples: i64, heap_tids: i64) -> Self {
+        let ratio = if tuples == 0 { 1.0 } else { heap_tids as f64 / tuples as f64 };
+        Self { tuples, posting_tuples, heap_tids, ratio }
+    }
+
+    /// Counts the heap TIDs of leaf items: the posting list of deduplicated ones, one otherwise.
+    pub fn of_items(items: &[Item]) -> Self {
+        let posting_lists: Vec<usize> = items.iter().filter_map(|item| item.tids.as_ref().map(Vec::len)).collect();
+        let heap_tids = posting_lists.iter().sum::<usize>() + items.len() - posting_lists.len();
+        Self::new(items.len() as i64, posting_lists.len() as i64, heap_tids as i64)
+    }
+}
+
+/// Suffix truncation of the pivot tuples on one level of a btree. Savings are measured against
+/// the first leaf tuple below each pivot, the tuple it was truncated from when the page split
+/// unless it has been deleted since.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct SuffixTruncation {
+    pub level: i64,
+    /// Pivots other than the "minus infinity" first downlink of each page.
+    pub pivots: i64,
+    /// Key attributes cut off, over all pivots.
+    pub truncated_attributes: i64,
+    /// Pivots that kept a heap TID as tiebreaker.
+    pub heap_tids: i64,
+    /// Size of the pivots' keys, heap TIDs included.
+    pub key_bytes: i64,
+    /// How much larger the keys would be untruncated.
+    pub bytes_saved: i64,
+}
+
+impl std::iter::Sum for Deduplication {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        let (tuples, posting_tuples, heap_tids) = iter.fold((0, 0, 0), |(tuples, posting_tuples, heap_tids), dedup| {
+            (tuples + dedup.tuples, posting_tuples + dedup.posting_tuples, heap_tids + dedup.heap_tids)
+        });
+        Self::new(tuples, posting_tuples, heap_tids)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Item {
     pub value: String,
-    pub child: Option<Box<Page>>,
+    /// Block number of the page below this item in the tree, looked up in the `PageArena`.
+    pub child: Option<i64>,
     pub pointer: Option<i64>,
     pub obj_id: Option<Tid>,
+    #[serde(default)]
+    pub tids: Option<Vec<Tid>>,
+    /// Whether a btree leaf tuple was killed (`LP_DEAD`), `None` on internal pages.
+    #[serde(default)]
+    pub dead: Option<bool>,
+    /// The heap TID of a btree tuple: its own on leaf pages, the first of its posting list when
+    /// deduplicated, and the tiebreaker kept by suffix truncation on pivot tuples.
+    #[serde(default)]
+    pub htid: Option<Tid>,
+    /// Size of the key attributes of a btree tuple, padding included but not its posting list or
+    /// pivot heap TID.
+    #[serde(default)]
+    pub key_size: Option<i64>,
+    /// Key attributes a btree pivot kept after suffix truncation, 0 for the "minus infinity"
+    /// first downlink. `None` when the tuple doesn't say, and then it has all of them.
+    #[serde(default)]
+    pub key_attributes: Option<i64>,
+    /// Names of the key columns suffix truncation cut off a pivot, when the columns are known.
+    #[serde(default)]
+    pub truncated: Option<Vec<String>>,
+    /// The key decoded attribute by attribute, when the types of the index's attributes are known.
+    #[serde(default)]
+    pub key: Option<Vec<KeyValue>>,
+    /// The heap row of a btree leaf item, looked up with `--resolve-heap`.
+    #[serde(default)]
+    pub row: Option<RowData>,
+    /// Whether `--resolve-heap` found the heap row, `None` when it wasn't looked up. Dead rows
+    /// are not found.
+    #[serde(default)]
+    pub row_visible: Option<bool>,
+    #[serde(default)]
+    pub bounding_box: Option<BoundingBox>,
+    /// State of SP-GiST tuples that no longer hold a key: redirect, dead or placeholder.
+    #[serde(default)]
+    pub state: Option<String>,
+    /// Offset of the next SP-GiST leaf tuple of the same chain, on the same page.
+    #[serde(default)]
+    pub next_offset: Option<i64>,
 }
 
 impl Item {
-    pub fn new(value: String, child: Option<Box<Page>>, pointer: Option<i64>, obj_id: Option<Tid>) -> Self {
+    pub fn new(value: String, child: Option<i64>, pointer: Option<i64>, obj_id: Option<Tid>) -> Self {
         Self {
             value,
             child,
             pointer,
             obj_id,
+            tids: None,
+            dead: None,
+            htid: None,
+            key_size: None,
+            key_attributes: None,
+            truncated: None,
+            key: None,
+            row: None,
+            row_visible: None,
+            bounding_box: None,
+            state: None,
+            next_offset: None,
         }
     }
 }
 
+/// An attribute of an index key, rendered the way its type's output function would.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeyValue {
+    pub column: String,
+    pub type_name: String,
+    /// `None` for NULL.
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct BoundingBox {
+    pub x_min: f64,
+    pub y_min: f64,
+    pub x_max: f64,
+    pub y_max: f64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Tree {
-    metadata_page: Option<MetadataPage>,
-    pub root: Page,
-    index_name: String,
-    table_name: String,
-    columns: Vec<String>,
+    pub metadata_page: Option<MetadataPage>,
+    pub gin_metadata_page: Option<GinMetadataPage>,
+    /// Block number of the root page.
+    pub root: i64,
+    pub pages: PageArena,
+    pub pending_list: Vec<Page>,
+    /// Block number of the root of the SP-GiST tree holding NULL keys, which `pages` also has.
+    #[serde(default)]
+    pub nulls_root: Option<i64>,
+    #[serde(default)]
+    pub heap_pages: Vec<HeapPage>,
+    pub index_name: String,
+    pub table_name: String,
+    pub columns: Vec<String>,
+    /// Number of key columns at the start of `columns`.
+    #[serde(default)]
+    pub key_columns: usize,
     pub index_type: Option<String>,
 }
 
 impl Tree {
-    pub fn new(metadata_page: MetadataPage, root: Page, index_name: String, index_info: Rc<IndexInfo>) -> Self {
+    pub fn new(metadata_page: MetadataPage, root: i64, pages: PageArena, index_name: String, index_info: Arc<IndexInfo>) -> Self {
         Self {
             metadata_page: Some(metadata_page),
+            gin_metadata_page: None,
             root,
+            pages,
+            pending_list: vec![],
+            nulls_root: None,
+            heap_pages: vec![],
             index_name,
             table_name: index_info.table_name.clone(),
             columns: index_info.columns.clone(),
+            key_columns: index_info.key_columns,
             index_type: Some("btree".to_string()),
         }
     }
+
+    pub fn new_gin(metadata_page: GinMetadataPage, root: i64, pages: PageArena, pending_list: Vec<Page>, index_name: String, index_info: Arc<IndexInfo>) -> Self {
+        Self {
+            metadata_page: None,
+            gin_metadata_page: Some(metadata_page),
+            root,
+            pages,
+            pending_list,
+            nulls_root: None,
+            heap_pages: vec![],
+            index_name,
+            table_name: index_info.table_name.clone(),
+            columns: index_info.columns.clone(),
+            key_columns: index_info.key_columns,
+            index_type: Some("gin".to_string()),
+        }
+    }
+
+    pub fn new_gist(root: i64, pages: PageArena, index_name: String, index_info: Arc<IndexInfo>) -> Self {
+        Self {
+            metadata_page: None,
+            gin_metadata_page: None,
+            root,
+            pages,
+            pending_list: vec![],
+            nulls_root: None,
+            heap_pages: vec![],
+            index_name,
+            table_name: index_info.table_name.clone(),
+            columns: index_info.columns.clone(),
+            key_columns: index_info.key_columns,
+            index_type: Some("gist".to_string()),
+        }
+    }
+
+    pub fn new_spgist(root: i64, nulls_root: i64, pages: PageArena, index_name: String, index_info: Arc<IndexInfo>) -> Self {
+        Self {
+            metadata_page: None,
+            gin_metadata_page: None,
+            root,
+            pages,
+            pending_list: vec![],
+            nulls_root: Some(nulls_root),
+            heap_pages: vec![],
+            index_name,
+            table_name: index_info.table_name.clone(),
+            columns: index_info.columns.clone(),
+            key_columns: index_info.key_columns,
+            index_type: Some("spgist".to_string()),
+        }
+    }
+
+    pub fn root(&self) -> &Page {
+        self.pages.get(self.root).expect("the root page is fetched first")
+    }
+
+    /// Deduplication over all the leaf pages that were fetched, `None` for other access methods.
+    pub fn deduplication(&self) -> Option<Deduplication> {
+        let pages: Vec<Deduplication> = self.pages.iter().filter_map(|page| page.deduplication).collect();
+        if pages.is_empty() {
+            return None;
+        }
+        Some(pages.into_iter().sum())
+    }
+
+    /// Suffix truncation of the pivots of each btree level, from the root down. Pivots whose
+    /// first leaf tuple wasn't fetched count towards `key_bytes` but not `bytes_saved`.
+    pub fn suffix_truncation(&self) -> Vec<SuffixTruncation> {
+        // pivots and leaf tuples of version 4 indexes store the heap TID as a last key attribute,
+        // a MAXALIGNed item pointer
+        let heap_tid_size = 8;
+        let leaf_heap_tid_size = match &self.metadata_page {
+            Some(metadata_page) if metadata_page.version >= 4 => heap_tid_size,
+            _ => 0,
+        };
+        let mut levels: BTreeMap<i64, SuffixTruncation> = BTreeMap::new();
+        for page in self.pages.iter().filter(|page| !page.is_leaf) {
+            for item in page.items.iter() {
+                let key_size = match item.key_size {
+                    Some(key_size) if key_size > 0 => key_size,
+                    _ => continue,
+                };
+                let level = levels.entry(page.level).or_insert(SuffixTruncation { level: page.level, ..Default::default() });
+                let size = key_size + if item.htid.is_some() { heap_tid_size } else { 0 };
+                level.pivots += 1;
+                if let Some(key_attributes) = item.key_attributes {
+                    level.truncated_attributes += (self.key_columns as i64 - key_attributes).max(0);
+                }
+                if item.htid.is_some() {
+                    level.heap_tids += 1;
+                }
+                level.key_bytes += size;
+                if let Some(leaf_key_size) = self.first_leaf_key_size(item.child) {
+                    level.bytes_saved += leaf_key_size + leaf_heap_tid_size - size;
+                }
+            }
+        }
+        levels.into_values().rev().collect()
+    }
+
+    /// Key size of the first tuple of the leftmost leaf page below `page_id`.
+    fn first_leaf_key_size(&self, page_id: Option<i64>) -> Option<i64> {
+        let mut page = self.pages.get(page_id?)?;
+        while !page.is_leaf {
+            page = self.pages.get(page.items.first()?.child?)?;
+        }
+        page.items.first()?.key_size
+    }
 }
 
-#[derive(Debug)]
-pub struct RowData {
-    pub primary_key_data: Option<Vec<String>>,
-    pub column_data: Option<Vec<String>>,
-    pub byte_values: Option<String>,
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
+pub struct BrinMetadataPage {
+    pub magic: String,
+    pub version: i32,
+    pub pages_per_range: i32,
+    pub last_revmap_page: i64,
 }
 
-impl RowData {
-    pub fn new(primary_key_data: Vec<String>, column_data: Vec<String>) -> Self {
+/// Summary of one indexed column over a block range, as stored in a BRIN tuple.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BrinSummary {
+    pub attnum: i32,
+    pub column: Option<String>,
+    pub all_nulls: bool,
+    pub has_nulls: bool,
+    pub value: Option<String>,
+    pub min: Option<String>,
+    pub max: Option<String>,
+    pub overlapping_ranges: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BrinRange {
+    pub first_block: i64,
+    pub last_block: i64,
+    /// Where the revmap points for this range, `None` when the range is not summarized.
+    pub summary_tid: Option<Tid>,
+    pub placeholder: bool,
+    pub summaries: Vec<BrinSummary>,
+}
+
+impl BrinRange {
+    pub fn is_summarized(&self) -> bool {
+        self.summary_tid.is_some() && !self.placeholder
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BrinIndex {
+    pub metadata_page: BrinMetadataPage,
+    pub ranges: Vec<BrinRange>,
+    pub heap_blocks: i64,
+    pub unsummarized_ranges: i64,
+    pub index_name: String,
+    pub table_name: String,
+    pub columns: Vec<String>,
+    pub index_type: String,
+}
+
+impl BrinIndex {
+    pub fn new(metadata_page: BrinMetadataPage, ranges: Vec<BrinRange>, heap_blocks: i64, index_name: String, index_info: Arc<IndexInfo>) -> Self {
+        let unsummarized_ranges = ranges.iter().filter(|range| !range.is_summarized()).count() as i64;
         Self {
-            primary_key_data: Some(primary_key_data),
-            column_data: Some(column_data),
-            byte_values: None,
+            metadata_page,
+            ranges,
+            heap_blocks,
+            unsummarized_ranges,
+            index_name,
+            table_name: index_info.table_name.clone(),
+            columns: index_info.columns.clone(),
+            index_type: "brin".to_string(),
         }
     }
-    pub fn new_bytes(byte_values: String) -> Self {
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
+pub struct HashMetadataPage {
+    pub magic: i64,
+    pub version: i64,
+    pub ntuples: f64,
+    pub ffactor: i32,
+    pub bsize: i32,
+    pub bmsize: i32,
+    pub bmshift: i32,
+    pub maxbucket: i64,
+    pub highmask: i64,
+    pub lowmask: i64,
+    pub ovflpoint: i64,
+    pub firstfree: i64,
+    pub nmaps: i64,
+    pub spares: Vec<i64>,
+    pub mapp: Vec<i64>,
+}
+
+/// Where an overflow page is tracked in the free space bitmap.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HashBitmapBit {
+    pub block_number: i64,
+    pub bitmap_block_number: i64,
+    pub bit: i32,
+    pub in_use: bool,
+}
+
+/// A bucket's primary page followed by its overflow page chain.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HashBucket {
+    pub bucket: i64,
+    pub pages: Vec<Page>,
+    pub live_items: i64,
+    pub dead_items: i64,
+    pub overflow_pages: i64,
+    pub overflow_bitmap: Vec<HashBitmapBit>,
+    /// Live items relative to the average bucket, 1.0 being a perfectly even distribution.
+    pub skew: f64,
+    pub is_skewed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HashIndex {
+    pub metadata_page: HashMetadataPage,
+    pub buckets: Vec<HashBucket>,
+    pub max_overflow_pages: i64,
+    pub max_skew: f64,
+    #[serde(default)]
+    pub heap_pages: Vec<HeapPage>,
+    pub index_name: String,
+    pub table_name: String,
+    pub columns: Vec<String>,
+    pub index_type: String,
+}
+
+impl HashIndex {
+    pub fn new(metadata_page: HashMetadataPage, buckets: Vec<HashBucket>, index_name: String, index_info: Arc<IndexInfo>) -> Self {
+        let max_overflow_pages = buckets.iter().map(|bucket| bucket.overflow_pages).max().unwrap_or(0);
+        let max_skew = buckets.iter().map(|bucket| bucket.skew).fold(0.0, f64::max);
         Self {
-            primary_key_data: None,
-            column_data: None,
-            byte_values: Some(byte_values),
+            metadata_page,
+            buckets,
+            max_overflow_pages,
+            max_skew,
+            heap_pages: vec![],
+            index_name,
+            table_name: index_info.table_name.clone(),
+            columns: index_info.columns.clone(),
+            index_type: "hash".to_string(),
         }
     }
 }
 
+/// A line pointer of a heap page and the tuple it points to, as returned by `heap_page_items`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HeapTuple {
+    pub offset: i16,
+    /// `unused`, `normal`, `redirect` or `dead`.
+    pub lp_state: String,
+    /// Offset of the tuple a redirect line pointer leads to, the start of a pruned HOT chain.
+    pub redirect_to: Option<i16>,
+    pub length: i16,
+    pub xmin: Option<String>,
+    pub xmax: Option<String>,
+    pub ctid: Option<Tid>,
+    /// Next version of a HOT updated tuple, on the same page.
+    pub hot_next: Option<Tid>,
+    pub flags: Vec<String>,
+    pub combined_flags: Vec<String>,
+    /// Whether a leaf item of the index points at this line pointer.
+    pub referenced: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HeapPage {
+    pub block_number: i64,
+    pub tuples: Vec<HeapTuple>,
+}
+
+/// A heap row looked up by TID, values being `None` for NULL.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RowData {
+    /// Values of the table's primary key, empty when it has none.
+    pub primary_key_data: Vec<Option<String>>,
+    /// Values of the indexed columns.
+    pub column_data: Vec<Option<String>>,
+}
+
+impl RowData {
+    pub fn new(primary_key_data: Vec<Option<String>>, column_data: Vec<Option<String>>) -> Self {
+        Self {
+            primary_key_data,
+            column_data,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Tid {
@@ -142,10 +590,8 @@ impl Display for Tid {
 
 impl ToSql for Tid {
     fn to_sql(&self, _: &Type, w: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> where Self: Sized {
-        let mut tid_str = format!("({},{})", self.block_number, self.offset_number);
-
-        w.reserve(tid_str.len());
-        w.put_slice(tid_str.as_bytes());
+        w.put_u32(self.block_number);
+        w.put_u16(self.offset_number);
 
         Ok(IsNull::No)
     }
@@ -172,4 +618,4 @@ impl<'a> FromSql<'a> for Tid {
     fn accepts(ty: &Type) -> bool {
         matches!(*ty,Type::TID)
     }
-}
\ No newline at end of file
+}
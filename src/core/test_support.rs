@@ -0,0 +1,40 @@
+//! Fixtures shared by the tests of the renderers, which all draw the same small tree.
+
+use std::sync::Arc;
+use crate::core::arena::PageArena;
+use crate::core::structs::{Item, MetadataPage, Page, Tree};
+use crate::db::IndexInfo;
+
+/// A btree index on `columns` of table `t`, the first `key_columns` of them being key columns.
+pub fn sample_index_info(columns: &[&str], key_columns: usize) -> IndexInfo {
+    IndexInfo {
+        index_type: "btree".to_string(),
+        columns: columns.iter().map(|column| column.to_string()).collect(),
+        key_columns,
+        attributes: vec![],
+        table_name: "t".to_string(),
+        table_oid: 0,
+        primary_indexed_attributes: vec![],
+    }
+}
+
+/// A two level btree named after the table and key columns of `index_info`: root 3 with a
+/// "minus infinity" downlink to leaf 1 and a `b` pivot to leaf 2, the leaves being siblings
+/// and empty. Tests fill in the pages they need through `pages.get_mut`.
+pub fn sample_btree(index_info: IndexInfo) -> Tree {
+    let mut leaf_a = Page::new(1, 0, true, false, 2, 0);
+    leaf_a.prev_page_id = None;
+    let mut leaf_b = Page::new(2, 0, true, false, 0, 1);
+    leaf_b.next_page_id = None;
+    let mut root = Page::new(3, 1, false, true, 0, 0);
+    root.prev_page_id = None;
+    root.next_page_id = None;
+    root.items.push(Item::new(String::new(), Some(1), Some(1), None));
+    root.items.push(Item::new("b".to_string(), Some(2), Some(2), None));
+    let mut pages = PageArena::new();
+    for page in [leaf_a, leaf_b, root] {
+        pages.insert(page);
+    }
+    let index_name = format!("{}_{}", index_info.table_name, index_info.columns[..index_info.key_columns].join("_"));
+    Tree::new(MetadataPage::new(4, 3, 1, 3, 1), 3, pages, index_name, Arc::new(index_info))
+}
@@ -0,0 +1,165 @@
+use std::path::Path;
+use crate::core::renderer::write_output;
+use crate::core::structs::{Item, Page, Tree};
+use crate::error::Result;
+
+/// Output path that sends the text rendering to stdout.
+pub const STDOUT_PATH: &str = "-";
+
+/// Indented tree of the pages, one line per page with its block number, level, item count,
+/// sibling pointers, high key and deduplication. With `show_items` every item is listed too, and
+/// child pages hang off the item pointing to them rather than off the page. The GIN pending
+/// list and the SP-GiST tree of NULL keys follow the main tree.
+pub fn generate_text(tree: &Tree, show_items: bool) -> String {
+    let mut text = format!(
+        "{} ({} on {} ({}))\n",
+        tree.index_name,
+        tree.index_type.clone().unwrap_or_default(),
+        tree.table_name,
+        tree.columns.join(", "),
+    );
+    push_page(&mut text, tree, tree.root(), "", true, show_items);
+    if !tree.pending_list.is_empty() {
+        text.push_str("Pending list\n");
+        for (i, page) in tree.pending_list.iter().enumerate() {
+            push_page(&mut text, tree, page, "", i == tree.pending_list.len() - 1, show_items);
+        }
+    }
+    if let Some(nulls_root) = tree.nulls_root.and_then(|nulls_root| tree.pages.get(nulls_root)) {
+        text.push_str("Tree of NULL keys\n");
+        push_page(&mut text, tree, nulls_root, "", true, show_items);
+    }
+    text
+}
+
+/// Pages recurse into their children, which only goes as deep as the tree is high.
+fn push_page(text: &mut String, tree: &Tree, page: &Page, prefix: &str, is_last: bool, show_items: bool) {
+    text.push_str(&format!("{}{}{}\n", prefix, connector(is_last), page_summary(page)));
+    let prefix = format!("{}{}", prefix, indent(is_last));
+    if show_items {
+        for (i, item) in page.items.iter().enumerate() {
+            let is_last_item = i == page.items.len() - 1;
+            text.push_str(&format!("{}{}{}\n", prefix, connector(is_last_item), item_summary(i, item)));
+            if let Some(child) = item.child.and_then(|child_id| tree.pages.get(child_id)) {
+                // right halves of splits the page has no downlink for hang off the same item
+                let halves: Vec<&Page> = std::iter::successors(Some(child), |page| tree.pages.right_half(page.id)).collect();
+                for (j, half) in halves.iter().enumerate() {
+                    push_page(text, tree, half, &format!("{}{}", prefix, indent(is_last_item)), j == halves.len() - 1, show_items);
+                }
+            }
+        }
+    } else {
+        let children: Vec<&Page> = tree.pages.children(page).collect();
+        for (i, child) in children.iter().enumerate() {
+            push_page(text, tree, child, &prefix, i == children.len() - 1, show_items);
+        }
+    }
+}
+
+fn connector(is_last: bool) -> &'static str {
+    if is_last { "└── " } else { "├── " }
+}
+
+fn indent(is_last: bool) -> &'static str {
+    if is_last { "    " } else { "│   " }
+}
+
+fn page_summary(page: &Page) -> String {
+    let mut kind = vec![format!("level {}", page.level)];
+    if page.is_root {
+        kind.push("root".to_string());
+    }
+    if page.is_leaf {
+        kind.push("leaf".to_string());
+    }
+    let sibling = |page_id: Option<i64>| page_id.map(|page_id| page_id.to_string()).unwrap_or_else(|| "-".to_string());
+    let mut summary = format!(
+        "Page {} ({}) {} items, prev {}, next {}",
+        page.id,
+        kind.join(", "),
+        page.items.len(),
+        sibling(page.prev_page_id),
+        sibling(page.next_page_id),
+    );
+    if let Some(high_key) = &page.high_key {
+        summary.push_str(&format!(", high key {}", high_key));
+    }
+    if let Some(deduplication) = page.deduplication.filter(|deduplication| deduplication.posting_tuples > 0) {
+        summary.push_str(&format!(", {} posting lists, {:.2} heap TIDs per tuple", deduplication.posting_tuples, deduplication.ratio));
+    }
+    summary
+}
+
+fn item_summary(i: usize, item: &Item) -> String {
+    let value = match &item.key {
+        Some(key) if !key.is_empty() => {
+            let values: Vec<&str> = key.iter().map(|value| value.value.as_deref().unwrap_or("NULL")).collect();
+            format!("({})", values.join(", "))
+        }
+        _ => item.value.clone(),
+    };
+    let mut summary = format!("{}: {}", i, item.state.as_ref().unwrap_or(&value));
+    if let Some(tids) = &item.tids {
+        summary.push_str(&format!(" -> {} tids", tids.len()));
+    } else if let Some(tid) = &item.obj_id {
+        summary.push_str(&format!(" -> {}", tid));
+    }
+    summary
+}
+
+/// Writes the text rendering to `output_path`, or to stdout when it is `-`.
+pub fn render_text(tree: Tree, show_items: bool, output_path: &Path) -> Result<()> {
+    let text = generate_text(&tree, show_items);
+    if output_path == Path::new(STDOUT_PATH) {
+        print!("{}", text);
+        Ok(())
+    } else {
+        write_output(text, output_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::structs::{Item, KeyValue, Tid};
+    use crate::core::test_support::{sample_btree, sample_index_info};
+    use super::generate_text;
+
+    #[test]
+    pub fn test_generate_text() {
+        let mut tree = sample_btree(sample_index_info(&["name"], 1));
+        let leaf_a = tree.pages.get_mut(1).unwrap();
+        leaf_a.high_key = Some("b".to_string());
+        leaf_a.items.push(Item::new("a".to_string(), None, Some(0), Some(Tid { block_number: 0, offset_number: 1 })));
+        let mut item = Item::new("62 00 00 00".to_string(), None, Some(0), Some(Tid { block_number: 0, offset_number: 2 }));
+        item.key = Some(vec![
+            KeyValue { column: "name".to_string(), type_name: "text".to_string(), value: Some("b".to_string()) },
+            KeyValue { column: "note".to_string(), type_name: "text".to_string(), value: None },
+        ]);
+        tree.pages.get_mut(2).unwrap().items.push(item);
+
+        assert_eq!(
+            [
+                "t_name (btree on t (name))",
+                "└── Page 3 (level 1, root) 2 items, prev -, next -",
+                "    ├── Page 1 (level 0, leaf) 1 items, prev -, next 2, high key b",
+                "    └── Page 2 (level 0, leaf) 1 items, prev 1, next -",
+                "",
+            ].join("\n"),
+            generate_text(&tree, false),
+        );
+        assert_eq!(
+            [
+                "t_name (btree on t (name))",
+                "└── Page 3 (level 1, root) 2 items, prev -, next -",
+                "    ├── 0: ",
+                "    │   └── Page 1 (level 0, leaf) 1 items, prev -, next 2, high key b",
+                "    │       └── 0: a -> (0,1)",
+                "    └── 1: b",
+                "        └── Page 2 (level 0, leaf) 1 items, prev 1, next -",
+                "            └── 0: (b, NULL) -> (0,2)",
+                "",
+            ].join("\n"),
+            generate_text(&tree, true),
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::io;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use crate::core::structs::Page;
+use crate::error::Result;
+
+const HELP: &str = "↑↓/jk select  enter/→/l downlink  n/] next page  p/[ prev page  ←/h/backspace back  r root  q quit";
+
+/// Where the browser is: a page and the item selected on it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Position {
+    page_id: i64,
+    selected: usize,
+}
+
+/// Navigation state of the page browser. Pages are fetched through `load_page` the first time
+/// they are shown and cached, so only the pages actually visited are read. A page that can't be
+/// read is shown empty, with the error in place of its details.
+pub struct Browser<F: FnMut(i64) -> Result<Page>> {
+    title: String,
+    root: i64,
+    load_page: F,
+    pages: HashMap<i64, Page>,
+    errors: HashMap<i64, String>,
+    current: Position,
+    history: Vec<Position>,
+}
+
+impl<F: FnMut(i64) -> Result<Page>> Browser<F> {
+    pub fn new(title: String, root: i64, load_page: F) -> Self {
+        Self {
+            title,
+            root,
+            load_page,
+            pages: HashMap::new(),
+            errors: HashMap::new(),
+            current: Position { page_id: root, selected: 0 },
+            history: vec![],
+        }
+    }
+
+    /// The current page, fetched if it wasn't yet.
+    pub fn current_page(&mut self) -> &Page {
+        let page_id = self.current.page_id;
+        let errors = &mut self.errors;
+        let load_page = &mut self.load_page;
+        self.pages.entry(page_id).or_insert_with(|| load_page(page_id).unwrap_or_else(|e| {
+            errors.insert(page_id, e.to_string());
+            Page::new(page_id, 0, false, false, 0, 0)
+        }))
+    }
+
+    pub fn select_next(&mut self) {
+        let items = self.current_page().items.len();
+        if self.current.selected + 1 < items {
+            self.current.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.current.selected = self.current.selected.saturating_sub(1);
+    }
+
+    /// Opens the child page of the selected item of an internal page.
+    pub fn follow_downlink(&mut self) {
+        let selected = self.current.selected;
+        let page = self.current_page();
+        if page.is_leaf {
+            return;
+        }
+        if let Some(child_page_id) = page.items.get(selected).and_then(|item| item.pointer) {
+            self.go_to(child_page_id);
+        }
+    }
+
+    /// Moves to the right sibling, or the left one when `right` is false.
+    pub fn follow_sibling(&mut self, right: bool) {
+        let page = self.current_page();
+        let sibling = if right { page.next_page_id } else { page.prev_page_id };
+        if let Some(page_id) = sibling {
+            self.go_to(page_id);
+        }
+    }
+
+    pub fn go_to_root(&mut self) {
+        if self.current.page_id != self.root {
+            self.go_to(self.root);
+        }
+    }
+
+    /// Returns to the previous position, e.g. the parent after following a downlink.
+    pub fn back(&mut self) {
+        if let Some(position) = self.history.pop() {
+            self.current = position;
+        }
+    }
+
+    fn go_to(&mut self, page_id: i64) {
+        self.history.push(self.current);
+        self.current = Position { page_id, selected: 0 };
+    }
+
+    /// Breadcrumbs of the pages visited to get to the current one.
+    fn path(&self) -> String {
+        self.history.iter()
+            .chain(std::iter::once(&self.current))
+            .map(|position| position.page_id.to_string())
+            .collect::<Vec<String>>()
+            .join(" > ")
+    }
+}
+
+/// Runs the browser in the terminal until the user quits.
+pub fn run_tui<F: FnMut(i64) -> Result<Page>>(mut browser: Browser<F>) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut browser);
+    ratatui::restore();
+    result
+}
+
+fn event_loop<F: FnMut(i64) -> Result<Page>>(terminal: &mut DefaultTerminal, browser: &mut Browser<F>) -> io::Result<()> {
+    loop {
+        browser.current_page();
+        terminal.draw(|frame| draw(frame, browser))?;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => browser.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => browser.select_prev(),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => browser.follow_downlink(),
+            KeyCode::Char('n') | KeyCode::Char(']') => browser.follow_sibling(true),
+            KeyCode::Char('p') | KeyCode::Char('[') => browser.follow_sibling(false),
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') => browser.back(),
+            KeyCode::Char('r') => browser.go_to_root(),
+            _ => {}
+        }
+    }
+}
+
+fn draw<F: FnMut(i64) -> Result<Page>>(frame: &mut Frame, browser: &Browser<F>) {
+    let page = &browser.pages[&browser.current.page_id];
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(0), Constraint::Length(1)])
+        .split(frame.area());
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(rows[1]);
+
+    let header = Paragraph::new(format!("{}  path: {}", browser.title, browser.path()))
+        .block(Block::default().borders(Borders::ALL).title("Index"));
+    frame.render_widget(header, rows[0]);
+
+    let items: Vec<ListItem> = page.items.iter().enumerate()
+        .map(|(i, item)| ListItem::new(format!("{:>4} {}", i, item.value)))
+        .collect();
+    let items = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(page_title(page)))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(browser.current.selected));
+    frame.render_stateful_widget(items, panes[0], &mut state);
+
+    let details = match browser.errors.get(&page.id) {
+        Some(error) => vec![Line::from(format!("Could not read page {}: {}", page.id, error))],
+        None => page_details(page, browser.current.selected),
+    };
+    let details = Paragraph::new(details)
+        .block(Block::default().borders(Borders::ALL).title("Details"))
+        .wrap(Wrap { trim: false });
+    frame.render_widget(details, panes[1]);
+
+    frame.render_widget(Paragraph::new(HELP), rows[2]);
+}
+
+fn page_title(page: &Page) -> String {
+    let kind = if page.is_leaf { "leaf" } else { "internal" };
+    format!("Page {} ({}, level {}, {} items)", page.id, kind, page.level, page.items.len())
+}
+
+fn page_details(page: &Page, selected: usize) -> Vec<Line<'static>> {
+    let sibling = |page_id: Option<i64>| page_id.map(|page_id| page_id.to_string()).unwrap_or_else(|| "-".to_string());
+    let mut lines = vec![
+        Line::from(format!("Block: {}", page.id)),
+        Line::from(format!("Level: {}", page.level)),
+        Line::from(format!("Flags: {}", page.flags.join(", "))),
+        Line::from(format!("Prev page: {}", sibling(page.prev_page_id))),
+        Line::from(format!("Next page: {}", sibling(page.next_page_id))),
+        Line::from(format!("High key: {}", page.high_key.clone().unwrap_or_else(|| "-".to_string()))),
+        Line::from(""),
+    ];
+    if let Some(item) = page.items.get(selected) {
+        lines.push(Line::from(format!("Item {}", selected)));
+        lines.push(Line::from(format!("Value: {}", item.value)));
+        if page.is_leaf {
+            if let Some(tid) = &item.obj_id {
+                lines.push(Line::from(format!("Heap TID: {}", tid)));
+            }
+        } else if let Some(pointer) = item.pointer {
+            lines.push(Line::from(format!("Downlink: block {}", pointer)));
+        }
+        if let Some(tids) = &item.tids {
+            lines.push(Line::from(format!("Posting list: {}", tids.iter().map(|tid| tid.to_string()).collect::<Vec<String>>().join(" "))));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use crate::core::structs::Page;
+    use crate::core::test_support::{sample_btree, sample_index_info};
+    use super::{draw, Browser};
+
+    fn page(page_id: i64) -> Page {
+        sample_btree(sample_index_info(&["id"], 1)).pages.get(page_id).cloned().unwrap()
+    }
+
+    #[test]
+    pub fn test_browser_navigation() {
+        let loaded = RefCell::new(vec![]);
+        let mut browser = Browser::new("t_id".to_string(), 3, |page_id| {
+            loaded.borrow_mut().push(page_id);
+            Ok(page(page_id))
+        });
+        assert_eq!(3, browser.current_page().id);
+
+        browser.select_next();
+        browser.select_next();
+        browser.follow_downlink();
+        assert_eq!(2, browser.current_page().id);
+        // leaves have no downlinks and the rightmost page no right link
+        browser.follow_downlink();
+        browser.follow_sibling(true);
+        assert_eq!(2, browser.current_page().id);
+
+        browser.follow_sibling(false);
+        assert_eq!(1, browser.current_page().id);
+        assert_eq!("3 > 2 > 1", browser.path());
+        browser.back();
+        browser.back();
+        assert_eq!(3, browser.current_page().id);
+        assert_eq!(1, browser.current.selected);
+        browser.go_to_root();
+        assert_eq!("3", browser.path());
+        // every page is read once, however often it is shown
+        assert_eq!(vec![3, 2, 1], *loaded.borrow());
+    }
+
+    #[test]
+    pub fn test_draw() {
+        let mut browser = Browser::new("t_id".to_string(), 3, |page_id| Ok(page(page_id)));
+        browser.select_next();
+        browser.follow_downlink();
+        browser.current_page();
+        let mut terminal = Terminal::new(TestBackend::new(120, 20)).unwrap();
+        terminal.draw(|frame| draw(frame, &browser)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("t_id  path: 3 > 2"));
+        assert!(screen.contains("Page 2 (leaf, level 0, 0 items)"));
+        assert!(screen.contains("Prev page: 1"));
+        assert!(screen.contains("Next page: -"));
+    }
+
+    #[test]
+    pub fn test_draw_unreadable_page() {
+        let mut browser = Browser::new("t_id".to_string(), 3, |page_id| match page_id {
+            3 => Ok(page(3)),
+            _ => Err(io::Error::other("failed to fill whole buffer").into()),
+        });
+        browser.follow_downlink();
+        browser.current_page();
+        let mut terminal = Terminal::new(TestBackend::new(120, 20)).unwrap();
+        terminal.draw(|frame| draw(frame, &browser)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("Could not read page 1: failed to fill whole buffer"));
+    }
+}
@@ -0,0 +1,45 @@
+use std::future::Future;
+use tokio::runtime::{Builder, Runtime};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Error, NoTls, Row};
+
+/// A tokio-postgres connection driven by its own single-threaded runtime, which the blocking
+/// API runs the `_async` functions on, the way the `postgres` crate wraps tokio-postgres.
+pub struct Client {
+    runtime: Runtime,
+    client: tokio_postgres::Client,
+}
+
+impl Client {
+    pub fn connect(params: &str) -> Result<Self, Error> {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let (client, connection) = runtime.block_on(tokio_postgres::connect(params, NoTls))?;
+        // the connection only makes progress while the runtime is blocked on a query
+        runtime.spawn(connection);
+        Ok(Self { runtime, client })
+    }
+
+    /// The underlying async client, to pass to the `_async` functions.
+    pub fn client(&self) -> &tokio_postgres::Client {
+        &self.client
+    }
+
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+}
+
+/// Blocking shorthands for ad hoc queries, like the ones of `postgres::Client`.
+impl Client {
+    pub fn query(&self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, Error> {
+        self.block_on(self.client.query(query, params))
+    }
+
+    pub fn query_one(&self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Row, Error> {
+        self.block_on(self.client.query_one(query, params))
+    }
+
+    pub fn batch_execute(&self, query: &str) -> Result<(), Error> {
+        self.block_on(self.client.batch_execute(query))
+    }
+}
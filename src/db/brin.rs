@@ -0,0 +1,211 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use log::info;
+use crate::error::Result;
+use crate::core::brin::parse_min_max;
+use crate::core::structs::{BrinMetadataPage, BrinRange, BrinSummary, Tid};
+use crate::db::{Client, IndexInfo};
+
+pub fn get_brin_metadata_page(client: Arc<Mutex<Client>>, index_name: String) -> Result<BrinMetadataPage> {
+    let client = client.lock().unwrap();
+    client.block_on(get_brin_metadata_page_async(client.client(), index_name))
+}
+
+pub async fn get_brin_metadata_page_async(client: &tokio_postgres::Client, index_name: String) -> Result<BrinMetadataPage> {
+    let brin_metadata_query = r#"
+        SELECT
+            magic,
+            version,
+            pagesperrange,
+            lastrevmappage
+        FROM brin_metapage_info(get_raw_page($1, 0));
+    "#;
+    info!("Getting BRIN metadata page for index: {}", index_name);
+    let row = client.query_one(brin_metadata_query, &[&index_name]).await?;
+    Ok(BrinMetadataPage {
+        magic: row.get(0),
+        version: row.get(1),
+        pages_per_range: row.get(2),
+        last_revmap_page: row.get(3),
+    })
+}
+
+/// Number of blocks in the table the index summarizes.
+pub fn get_brin_heap_blocks(client: Arc<Mutex<Client>>, index_name: String) -> Result<i64> {
+    let client = client.lock().unwrap();
+    client.block_on(get_brin_heap_blocks_async(client.client(), index_name))
+}
+
+pub async fn get_brin_heap_blocks_async(client: &tokio_postgres::Client, index_name: String) -> Result<i64> {
+    let heap_blocks_query = r#"
+        SELECT pg_relation_size(indrelid) / current_setting('block_size')::bigint
+        FROM pg_index
+        WHERE indexrelid = $1::text::regclass;
+    "#;
+    let row = client.query_one(heap_blocks_query, &[&index_name]).await?;
+    Ok(row.get(0))
+}
+
+/// Reads the revmap entry of every block range in the table. Entries are stored in block range
+/// order across the revmap pages, and an invalid `(0,0)` TID marks a range without summary.
+async fn get_revmap(client: &tokio_postgres::Client, metadata_page: &BrinMetadataPage, range_count: usize, index_name: &String) -> Result<Vec<Option<Tid>>> {
+    let revmap_query = r#"
+        SELECT pages
+        FROM brin_revmap_data(get_raw_page($1, $2));
+    "#;
+    let mut revmap: Vec<Option<Tid>> = vec![];
+    for revmap_page in 1..=metadata_page.last_revmap_page {
+        if revmap.len() >= range_count {
+            break;
+        }
+        info!("getting BRIN revmap page {}", revmap_page);
+        let rows = client.query(revmap_query, &[index_name, &revmap_page]).await?;
+        revmap.extend(rows.iter().map(|row| {
+            let tid: Tid = row.get(0);
+            if tid.block_number == 0 && tid.offset_number == 0 {
+                None
+            } else {
+                Some(tid)
+            }
+        }));
+    }
+    revmap.resize(range_count, None);
+    Ok(revmap)
+}
+
+struct BrinTuple {
+    placeholder: bool,
+    summaries: Vec<BrinSummary>,
+}
+
+async fn get_brin_tuples(client: &tokio_postgres::Client, page_id: i64, index_name: &String, index_info: Arc<IndexInfo>) -> Result<HashMap<u16, BrinTuple>> {
+    info!("getting BRIN items for page {}", page_id);
+    let items_query = r#"
+        SELECT
+        itemoffset,
+        attnum,
+        allnulls,
+        hasnulls,
+        placeholder,
+        value
+        FROM brin_page_items(get_raw_page($1, $2), $1::text::regclass);
+    "#;
+    let rows = client.query(items_query, &[index_name, &page_id]).await?;
+    let mut tuples: HashMap<u16, BrinTuple> = HashMap::new();
+    for row in rows.iter() {
+        let item_offset: i32 = row.get(0);
+        let attnum: i32 = row.get(1);
+        let value: Option<String> = row.get(5);
+        let (min, max) = match value.as_deref().and_then(parse_min_max) {
+            Some((min, max)) => (Some(min), Some(max)),
+            None => (None, None),
+        };
+        let tuple = tuples.entry(item_offset as u16).or_insert(BrinTuple {
+            placeholder: row.get(4),
+            summaries: vec![],
+        });
+        tuple.summaries.push(BrinSummary {
+            attnum,
+            column: index_info.columns.get((attnum - 1) as usize).cloned(),
+            all_nulls: row.get(2),
+            has_nulls: row.get(3),
+            value,
+            min,
+            max,
+            overlapping_ranges: 0,
+        });
+    }
+    Ok(tuples)
+}
+
+/// Builds one entry per block range of the table, attaching the summary tuple the revmap points
+/// to. Every regular page holding summaries is read once.
+pub fn get_brin_ranges(client: Arc<Mutex<Client>>, metadata_page: &BrinMetadataPage, heap_blocks: i64, index_name: String, index_info: Arc<IndexInfo>) -> Result<Vec<BrinRange>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_brin_ranges_async(client.client(), metadata_page, heap_blocks, index_name, index_info))
+}
+
+pub async fn get_brin_ranges_async(client: &tokio_postgres::Client, metadata_page: &BrinMetadataPage, heap_blocks: i64, index_name: String, index_info: Arc<IndexInfo>) -> Result<Vec<BrinRange>> {
+    let pages_per_range = metadata_page.pages_per_range.max(1) as i64;
+    let range_count = ((heap_blocks + pages_per_range - 1) / pages_per_range) as usize;
+    let revmap = get_revmap(client, metadata_page, range_count, &index_name).await?;
+
+    let summary_pages: BTreeSet<u32> = revmap.iter().flatten().map(|tid| tid.block_number).collect();
+    let mut tuples: HashMap<u32, HashMap<u16, BrinTuple>> = HashMap::new();
+    for page_id in summary_pages {
+        tuples.insert(page_id, get_brin_tuples(client, page_id as i64, &index_name, index_info.clone()).await?);
+    }
+
+    Ok(revmap.into_iter().enumerate().map(|(i, summary_tid)| {
+        let first_block = i as i64 * pages_per_range;
+        let tuple = summary_tid.as_ref().and_then(|tid| {
+            tuples.get_mut(&tid.block_number)?.remove(&tid.offset_number)
+        });
+        let (placeholder, summaries) = match tuple {
+            Some(tuple) => (tuple.placeholder, tuple.summaries),
+            None => (false, vec![]),
+        };
+        BrinRange {
+            first_block,
+            last_block: (first_block + pages_per_range).min(heap_blocks) - 1,
+            summary_tid,
+            placeholder,
+            summaries,
+        }
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::core::brin::generate_brin;
+    use crate::db::{get_index_info, init_client, Client};
+
+    fn setup_test_data(client: Arc<Mutex<Client>>) {
+        tear_down_test_data(Arc::clone(&client));
+        client.lock().unwrap().batch_execute(
+            "CREATE TABLE IF NOT EXISTS test_brin_table (
+            id INTEGER NOT NULL,
+            created_at TIMESTAMP NOT NULL
+        ) WITH (autovacuum_enabled = off);
+        INSERT INTO test_brin_table
+            SELECT i, '2024-01-01'::timestamp + i * interval '1 minute' FROM generate_series(1, 20000) i;
+        CREATE INDEX IF NOT EXISTS idx_test_brin ON test_brin_table USING brin (id, created_at) WITH (pages_per_range = 16);
+        INSERT INTO test_brin_table
+            SELECT i, '2024-01-01'::timestamp FROM generate_series(1, 5000) i;"
+        ).unwrap();
+    }
+
+    fn tear_down_test_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_brin_table"
+        ).unwrap();
+    }
+
+    #[test]
+    pub fn test_get_brin_ranges() {
+        let client_ref = Arc::new(init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        setup_test_data(Arc::clone(&client_ref));
+        let index_name = "idx_test_brin".to_string();
+        let index_info = get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        assert_eq!("brin", index_info.index_type);
+
+        let brin = generate_brin(Arc::clone(&client_ref), index_name, Arc::new(index_info)).unwrap();
+        assert_eq!(16, brin.metadata_page.pages_per_range);
+        assert_eq!(((brin.heap_blocks + 15) / 16) as usize, brin.ranges.len());
+        assert!(brin.unsummarized_ranges > 0);
+
+        let first = &brin.ranges[0];
+        assert!(first.is_summarized());
+        assert_eq!(2, first.summaries.len());
+        assert_eq!(Some("1".to_string()), first.summaries[0].min);
+        // the first rows inserted after the index was built landed in the partially filled last
+        // range, whose summary now starts at 1 again and overlaps the first range
+        assert_eq!(1, first.summaries[0].overlapping_ranges);
+        // the rest went to new, unsummarized ranges
+        assert!(!brin.ranges.last().unwrap().is_summarized());
+        tear_down_test_data(Arc::clone(&client_ref));
+    }
+}
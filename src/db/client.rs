@@ -1,42 +1,52 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use log::{debug, info};
-use postgres;
-use postgres::{Client, Row};
-use crate::core::structs::{Item, MetadataPage, RowData, Tid};
+use tokio_postgres::types::Oid;
+use crate::error::{Error, Result};
+use crate::core::structs::{MetadataPage, RowData, Tid};
 use crate::core::Page;
+use crate::core::datum::Attribute;
+use crate::core::btree::{decode_btree_page, decode_metadata_page, link_children, set_truncated_columns, BTREE_METAPAGE};
+use crate::db::Client;
 
-pub fn init_client(host: String, port: String, db: String, user: String, pass: String) -> RefCell<Client> {
+pub fn init_client(host: String, port: String, db: String, user: String, pass: String) -> Result<Mutex<Client>> {
     let mut connection_string = format!("host={} port={} dbname={}", host, port, db);
-    if user != "" {
+    if !user.is_empty() {
         connection_string.push_str(&format!(" user={}", user));
     }
-    if pass != "" {
+    if !pass.is_empty() {
         connection_string.push_str(&format!(" password={}", pass));
     }
 
-    RefCell::new(Client::connect(connection_string.as_str(), postgres::NoTls).unwrap())
+    let client = Client::connect(connection_string.as_str()).map_err(Error::Connection)?;
+    Ok(Mutex::new(client))
 }
 
-pub fn get(client: Arc<RefCell<Client>>, query: String) -> Vec<Row> {
-    client.borrow_mut().query(&query, &[]).unwrap()
-}
-
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct IndexInfo {
     pub index_type: String,
+    /// Indexed columns in index order, key columns first and then `INCLUDE` columns.
     pub columns: Vec<String>,
+    /// Number of key columns at the start of `columns`.
+    pub key_columns: usize,
+    /// The index's own attributes with their types, to decode keys with.
+    pub attributes: Vec<Attribute>,
     pub table_name: String,
-    pub table_oid: postgres::types::Oid,
+    pub table_oid: Oid,
     pub primary_indexed_attributes: Vec<String>,
 }
 
-pub fn get_index_info(client: Arc<RefCell<Client>>, index: String) -> IndexInfo {
+pub fn get_index_info(client: Arc<Mutex<Client>>, index: String) -> Result<IndexInfo> {
+    let client = client.lock().unwrap();
+    client.block_on(get_index_info_async(client.client(), index))
+}
+
+pub async fn get_index_info_async(client: &tokio_postgres::Client, index: String) -> Result<IndexInfo> {
     let mut index_info = IndexInfo {
         index_type: "".to_string(),
         columns: vec![],
+        key_columns: 0,
+        attributes: vec![],
         table_name: "".to_string(),
         table_oid: 0,
         primary_indexed_attributes: vec![],
@@ -48,21 +58,22 @@ pub fn get_index_info(client: Arc<RefCell<Client>>, index: String) -> IndexInfo
             i.relname as index_name,
             am.amname,
             t.oid as table_oid,
-            array_to_string(array_agg(a.attname), ', ') as column_names
+            array_to_string(array_agg(a.attname ORDER BY array_position(ix.indkey::int2[], a.attnum)), ', ') as column_names,
+            ix.indnkeyatts::int4 as key_columns
         FROM pg_index ix
         JOIN pg_class t ON (t.oid = ix.indrelid AND t.relkind = 'r')
         JOIN pg_class i ON (i.oid = ix.indexrelid)
         JOIN pg_am am ON (am.oid = i.relam)
         JOIN pg_attribute a ON (a.attrelid = t.oid AND a.attnum = ANY(ix.indkey))
         WHERE i.relname = $1
-        GROUP BY t.relname, i.relname, am.amname, t.oid;
+        GROUP BY t.relname, i.relname, am.amname, t.oid, ix.indnkeyatts;
     "#;
-    let result = client.borrow_mut().query(index_type_query, &[&index]).unwrap();
+    let result = client.query(index_type_query, &[&index]).await?;
 
-    let (table_name, index_type, columns, table_oid) = match result.get(0) {
+    let (table_name, index_type, columns, key_columns, table_oid) = match result.first() {
         Some(row) => {
             let table_name: String = row.get(0);
-            let table_oid: postgres::types::Oid = row.get(3);
+            let table_oid: Oid = row.get(3);
             let index_type: String = row.get(2);
             let column_str: String = row.get(4);
             let columns = column_str.split(",")
@@ -70,13 +81,15 @@ pub fn get_index_info(client: Arc<RefCell<Client>>, index: String) -> IndexInfo
                     s.to_string().trim().to_string()
                 })
                 .collect();
-            (table_name, index_type, columns, table_oid)
+            let key_columns: i32 = row.get(5);
+            (table_name, index_type, columns, key_columns as usize, table_oid)
         }
-        None => ("".to_string(), "".to_string(), vec![], 0),
+        None => return Err(Error::UnknownIndex(index)),
     };
     index_info.table_oid = table_oid;
     index_info.index_type = index_type;
     index_info.columns = columns;
+    index_info.key_columns = key_columns;
     index_info.table_name = table_name.clone();
     debug!("t: {:?} {:?}", table_name, table_oid);
     let table_indexed_attributes_query = r#"
@@ -86,9 +99,9 @@ pub fn get_index_info(client: Arc<RefCell<Client>>, index: String) -> IndexInfo
         WHERE  i.indrelid = $1
         AND    i.indisprimary;
     "#;
-    let result_indexed_attributes = client.borrow_mut().query(table_indexed_attributes_query, &[&table_oid]).unwrap();
+    let result_indexed_attributes = client.query(table_indexed_attributes_query, &[&table_oid]).await?;
     debug!("{:?}", result_indexed_attributes);
-    let indexed_attributes = match result_indexed_attributes.get(0) {
+    let indexed_attributes = match result_indexed_attributes.first() {
         Some(indexed_attributes) => {
             let indexed_columns: Vec<String> = indexed_attributes.get(0);
             indexed_columns
@@ -96,210 +109,169 @@ pub fn get_index_info(client: Arc<RefCell<Client>>, index: String) -> IndexInfo
         None => vec![],
     };
     index_info.primary_indexed_attributes = indexed_attributes;
-    index_info
+
+    let attributes_query = r#"
+        SELECT a.attname::text, a.atttypid, format_type(a.atttypid, a.atttypmod), a.attlen, a.attalign::text
+        FROM pg_attribute a
+        WHERE a.attrelid = $1::text::regclass AND a.attnum > 0
+        ORDER BY a.attnum;
+    "#;
+    index_info.attributes = client.query(attributes_query, &[&index]).await?.iter()
+        .map(|row| Attribute {
+            name: row.get(0),
+            type_oid: row.get(1),
+            type_name: row.get(2),
+            len: row.get(3),
+            align: row.get::<_, String>(4).chars().next().unwrap_or('c'),
+        })
+        .collect();
+    Ok(index_info)
 }
 
-pub fn get_metadata_page(client: Arc<RefCell<Client>>, index_name: String) -> MetadataPage {
-    let btree_metadata_query = r#"
-        SELECT
-            version,
-            root,
-            level,
-            fastroot,
-            fastlevel
-        FROM bt_metap($1);
+/// Fetches the image of a block of the index, which is decoded locally.
+pub fn get_raw_page(client: Arc<Mutex<Client>>, index_name: &String, page_id: i64) -> Result<Vec<u8>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_raw_page_async(client.client(), index_name, page_id))
+}
+
+pub async fn get_raw_page_async(client: &tokio_postgres::Client, index_name: &String, page_id: i64) -> Result<Vec<u8>> {
+    let raw_page_query = r#"
+        SELECT get_raw_page($1, $2);
     "#;
-    info!("Getting metadata page for index: {}", index_name);
-    let result_metadata = client.borrow_mut().query(btree_metadata_query, &[&index_name]).unwrap();
-    let metadata_page = match result_metadata.get(0) {
-        Some(row) => {
-            let version: i32 = row.get(0);
-            let root: i64 = row.get(1);
-            let level: i64 = row.get(2);
-            let fast_root: i64 = row.get(3);
-            let fast_level: i64 = row.get(4);
-            MetadataPage::new(version, root, level, fast_root, fast_level)
-        }
-        None => MetadataPage::new(0, 0, 0, 0, 0),
-    };
-    metadata_page
+    let row = client.query_one(raw_page_query, &[index_name, &page_id]).await?;
+    Ok(row.get(0))
 }
 
-pub fn get_page(client: Arc<RefCell<Client>>, page_id: i64, index_name: String, index_info: Rc<IndexInfo>) -> Page {
-    println!("getting page {}", page_id);
-    let page_query = r#"
-        SELECT
-        blkno,
-        type::text,
-        live_items,
-        dead_items,
-        avg_item_size,
-        page_size,
-        free_size,
-        btpo_prev,
-        btpo_next,
-        btpo_level,
-        btpo_flags
-        FROM bt_page_stats($1, $2)
-    "#.to_string();
-    let result_page = client.borrow_mut().query(&page_query, &[&index_name, &page_id]).unwrap();
-    let mut page = match result_page.get(0) {
-        Some(row) => {
-            let block_number: i64 = row.get(0);
-            let rtype: String = row.get(1);
-            let level: i64 = row.get(9);
-            let next_page_id: i64 = row.get(8);
-            let prev_page_id: i64 = row.get(7);
-            Page::new(block_number, level, rtype == "l", rtype == "r", next_page_id, prev_page_id)
-        }
-        None => Page::new(page_id, 0, false, false, 0, 0),
-    };
-    let (items, prev_item, next_item) = get_items(client.clone(), Rc::new(page.clone()), index_name.clone(), index_info.clone());
+/// Fetches the images of several blocks of the index in one query, in the order of `page_ids`.
+pub fn get_raw_pages(client: Arc<Mutex<Client>>, index_name: &String, page_ids: &[i64]) -> Result<Vec<Vec<u8>>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_raw_pages_async(client.client(), index_name, page_ids))
+}
 
-    page.items = items;
-    if next_item.is_some() {
-        page.high_key = Some(next_item.unwrap().value)
-    }
-    page.prev_item = match prev_item {
-        None => { None }
-        Some(item) => { Some(Box::new(item)) }
-    };
-    page
+pub async fn get_raw_pages_async(client: &tokio_postgres::Client, index_name: &String, page_ids: &[i64]) -> Result<Vec<Vec<u8>>> {
+    let raw_pages_query = r#"
+        SELECT get_raw_page($1, t.block_number)
+        FROM unnest($2::bigint[]) WITH ORDINALITY AS t(block_number, position)
+        ORDER BY t.position;
+    "#;
+    let rows = client.query(raw_pages_query, &[index_name, &page_ids]).await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
 }
 
-pub fn get_row(client: Arc<RefCell<Client>>, ct_ids: Vec<Tid>, index_info: Rc<IndexInfo>) -> HashMap<Tid, RowData> {
-    info!("getting {} rows", ct_ids.len());
-    let primary_key_columns = index_info.primary_indexed_attributes.iter().map(|pk| format!("{}::text", pk)).collect::<Vec<String>>().join(", ");
-    let columns = index_info.columns.iter().map(|pk| format!("{}::text", pk)).collect::<Vec<String>>().join(", ");
-
-
-    let ct_ids_array = ct_ids.iter()
-        .map(|tid| format!("({},{})", tid.block_number, tid.offset_number))
-        .collect::<Vec<String>>()
-        .join(", ");
-
-    let mut row_query = "".to_string();
-    if primary_key_columns == "" {
-        row_query = format!(r#"
-        SELECT ctid, {}
-        FROM {}
-        WHERE ctid IN (SELECT ('('|| block_num || ',' || offset_num || ')')::tid FROM unnest(ARRAY[{}]) AS t(block_num integer , offset_num integer))
-    "#, columns, index_info.table_name, ct_ids_array);
-    } else {
-        row_query = format!(r#"
-        SELECT ctid, {}, {}
-        FROM {}
-        WHERE ctid IN (SELECT ('('|| block_num || ',' || offset_num || ')')::tid FROM unnest(ARRAY[{}]) AS t(block_num integer , offset_num integer))
-    "#, primary_key_columns, columns, index_info.table_name, ct_ids_array);
-    }
-    let rows = client.borrow_mut().query(&row_query, &[]).unwrap();
+/// Number of blocks of the index, past which `get_raw_page` fails.
+pub fn get_index_blocks(client: Arc<Mutex<Client>>, index_name: &String) -> Result<i64> {
+    let client = client.lock().unwrap();
+    client.block_on(get_index_blocks_async(client.client(), index_name))
+}
 
+pub async fn get_index_blocks_async(client: &tokio_postgres::Client, index_name: &String) -> Result<i64> {
+    let index_blocks_query = r#"
+        SELECT pg_relation_size($1::text::regclass) / current_setting('block_size')::bigint;
+    "#;
+    let row = client.query_one(index_blocks_query, &[index_name]).await?;
+    Ok(row.get(0))
+}
 
-    // Rows data in page should Map<ct_id, RowData>
-    // Row Data -> primary key data, standard_index_data
-    let mut row_data: HashMap<Tid, RowData> = HashMap::new();
-    for row in rows.iter() {
-        let mut i = 0;
-        let ct_id: Tid = row.get(i);
-        i += 1;
-
-        let mut pks_left = index_info.primary_indexed_attributes.len();
-        let mut pk_values: Vec<String> = vec![];
-        while pks_left > 0 {
-            let pk: String = row.get(i);
-            pks_left -= 1;
-            i += 1;
-            &pk_values.push(pk);
-        }
+pub fn get_metadata_page(client: Arc<Mutex<Client>>, index_name: String) -> Result<MetadataPage> {
+    let client = client.lock().unwrap();
+    client.block_on(get_metadata_page_async(client.client(), index_name))
+}
 
-        let mut cols_left = index_info.columns.len();
-        let mut col_vals: Vec<String> = vec![];
-        while cols_left > 0 {
-            let col: String = row.get(i);
-            cols_left -= 1;
-            i += 1;
-            &col_vals.push(col);
-        }
-        row_data.insert(ct_id, RowData::new(pk_values, col_vals));
-    }
+pub async fn get_metadata_page_async(client: &tokio_postgres::Client, index_name: String) -> Result<MetadataPage> {
+    info!("Getting metadata page for index: {}", index_name);
+    let raw = get_raw_page_async(client, &index_name, BTREE_METAPAGE).await?;
+    decode_metadata_page(&raw).ok_or_else(|| Error::UnreadablePage {
+        relation: index_name,
+        block_number: BTREE_METAPAGE,
+        reason: "isn't a btree metapage",
+    })
+}
+
+/// Fetches a single page with its keys decoded from the index tuples, the heap isn't read. The
+/// downlinks of internal pages are set as their items' `child` but not followed, callers walk
+/// the tree one page at a time.
+pub fn get_page(client: Arc<Mutex<Client>>, page_id: i64, index_name: &String, index_info: Arc<IndexInfo>) -> Result<Page> {
+    let client = client.lock().unwrap();
+    client.block_on(get_page_async(client.client(), page_id, index_name, index_info))
+}
+
+pub async fn get_page_async(client: &tokio_postgres::Client, page_id: i64, index_name: &String, index_info: Arc<IndexInfo>) -> Result<Page> {
+    Ok(get_pages_async(client, &[page_id], index_name, index_info).await?.pop().unwrap())
+}
 
-    row_data
+/// Fetches several pages, typically a level of the tree, like `get_page` but with one query for
+/// all of their page images.
+pub fn get_pages(client: Arc<Mutex<Client>>, page_ids: &[i64], index_name: &String, index_info: Arc<IndexInfo>) -> Result<Vec<Page>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_pages_async(client.client(), page_ids, index_name, index_info))
 }
 
-pub fn get_items(client: Arc<RefCell<Client>>, page: Rc<Page>, index_name: String, index_info: Rc<IndexInfo>) -> (Vec<Item>, Option<Item>, Option<Item>) {
-    info!("getting items for page {}", page.id);
-    let btree_item_query = r#"
-        SELECT *
-        FROM bt_page_items($1, $2);
+pub async fn get_pages_async(client: &tokio_postgres::Client, page_ids: &[i64], index_name: &String, index_info: Arc<IndexInfo>) -> Result<Vec<Page>> {
+    info!("getting pages {:?}", page_ids);
+    let raw_pages = get_raw_pages_async(client, index_name, page_ids).await?;
+    page_ids.iter().zip(raw_pages.iter())
+        .map(|(page_id, raw)| {
+            let mut page = decode_btree_page(*page_id, raw, &index_info.attributes).ok_or_else(|| Error::UnreadablePage {
+                relation: index_name.clone(),
+                block_number: *page_id,
+                reason: "isn't a btree page",
+            })?;
+            link_children(&mut page);
+            set_truncated_columns(&mut page, &index_info);
+            Ok(page)
+        })
+        .collect()
+}
+
+/// Looks up the rows at `ct_ids` in the table, with their primary key and indexed columns as
+/// text. Rows the query's snapshot doesn't see, dead ones included, are left out of the map.
+pub fn get_row(client: Arc<Mutex<Client>>, ct_ids: Vec<Tid>, index_info: Arc<IndexInfo>) -> Result<HashMap<Tid, RowData>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_row_async(client.client(), ct_ids, index_info))
+}
+
+pub async fn get_row_async(client: &tokio_postgres::Client, ct_ids: Vec<Tid>, index_info: Arc<IndexInfo>) -> Result<HashMap<Tid, RowData>> {
+    info!("getting {} rows", ct_ids.len());
+    // identifiers are quoted, and the table named after its oid, by the server
+    let row_query_query = r#"
+        SELECT format(
+            'SELECT ctid%s FROM %s WHERE ctid = ANY($1::tid[])',
+            (SELECT string_agg(', ' || quote_ident(c) || '::text', '' ORDER BY position)
+             FROM unnest($2::text[]) WITH ORDINALITY AS t(c, position)),
+            $1::oid::regclass
+        );
     "#;
-    let result_items = client.borrow_mut().query(btree_item_query, &[&index_name, &page.id]).unwrap();
-    let mut items: Vec<Item> = vec![];
-
-    if page.is_leaf {
-        let ct_ids: Vec<Tid> = result_items.iter().map(|item| {
-            let ct_id: Tid = item.get(1);
-            ct_id
-        }).collect();
-        if ct_ids.len() > 0 {
-            let rows = get_row(client.clone(), ct_ids, index_info.clone());
-            for item in result_items.iter() {
-                let row_id: Tid = item.get(1);
-                let row_id_value = rows.get(&row_id);
-                let value: String = match row_id_value {
-                    Some(row_data) => row_data.byte_values.clone().unwrap_or_else(|| item.get(5)),
-                    None => item.get(5),
-                };
-                items.push(Item::new(value, None, Some(row_id.block_number as i64), Some(row_id)));
-            }
-        }
-    } else {
-        for item in result_items.iter() {
-            let next_page_tid: Tid = item.get(1);
-            let next_page_pointer: i64 = next_page_tid.block_number as i64;
-            let child_page = get_page(client.clone(), next_page_pointer, index_name.clone(), index_info.clone());
-
-            let value: String = match item.get(5) {
-                Some(value) => value,
-                None => "".to_string(),
-            };
-            items.push(Item::new(value, Some(Box::new(child_page)), Some(next_page_pointer), None));
-        }
-    }
+    let selected_columns: Vec<&String> = index_info.primary_indexed_attributes.iter().chain(index_info.columns.iter()).collect();
+    let row_query: String = client.query_one(row_query_query, &[&index_info.table_oid, &selected_columns]).await?.get(0);
+    let rows = client.query(&row_query, &[&ct_ids]).await?;
 
-    let mut prev_item: Option<Item> = None;
-    let next_item: Option<Item> = match page.next_page_id {
-        None => None,
-        Some(_) => {
-            if items.len() > 0 {
-                Some(items.remove(0))
-            } else {
-                None
-            }
-        }
-    };
-    if items.len() > 0 {
-        if page.prev_page_id.is_some() || (page.prev_page_id.is_none() && !page.is_root && !page.is_leaf) {
-            prev_item = Some(items.remove(0));
-        }
+    // the primary key columns come right after the ctid, then the indexed columns
+    let primary_key_count = index_info.primary_indexed_attributes.len();
+    let mut row_data: HashMap<Tid, RowData> = HashMap::new();
+    for row in rows.iter() {
+        let ct_id: Tid = row.get(0);
+        let pk_values: Vec<Option<String>> = (1..=primary_key_count).map(|i| row.get(i)).collect();
+        let col_vals: Vec<Option<String>> = (primary_key_count + 1..row.len()).map(|i| row.get(i)).collect();
+        row_data.insert(ct_id, RowData::new(pk_values, col_vals));
     }
 
-    (items, prev_item, next_item)
+    Ok(row_data)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::cell::RefCell;
-    use std::rc::Rc;
-    use std::sync::Arc;
-    use postgres::Client;
+    use std::future::Future;
+    use std::sync::{Arc, Mutex};
     use crate::core::{Tid};
-    use crate::db::client::{get_index_info, get_metadata_page, get_row, IndexInfo};
-    use crate::db::get_page;
+    use crate::core::btree::decode_btree_page;
+    use crate::core::structs::RowData;
+    use crate::db::client::{get_index_blocks, get_index_info, get_index_info_async, get_metadata_page, get_metadata_page_async, get_page_async, get_raw_page, get_row, IndexInfo};
+    use crate::db::{get_page, Client, Pool};
     use crate::core::btree::generate_btree;
 
-    fn setup_test_data(client: Arc<RefCell<Client>>) {
+    fn setup_test_data(client: Arc<Mutex<Client>>) {
         tear_down_test_data(Arc::clone(&client));
-        client.borrow_mut().batch_execute(
+        client.lock().unwrap().batch_execute(
             "CREATE TABLE IF NOT EXISTS test_table (
             id SERIAL PRIMARY KEY,
             name TEXT NOT NULL,
@@ -307,13 +279,13 @@ mod tests {
             created_at TIMESTAMP NOT NULL DEFAULT NOW()
         )").unwrap();
 
-        client.borrow_mut().batch_execute(
+        client.lock().unwrap().batch_execute(
             "CREATE INDEX IF NOT EXISTS idx_users_name_email ON test_table (name, email)"
         ).unwrap();
     }
 
-    fn insert_data(client: Arc<RefCell<Client>>) {
-        client.borrow_mut().batch_execute(
+    fn insert_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
             "INSERT INTO test_table(id, name, email) VALUES \
             (1, 'foo', 'foo@gmail.com'),\
             (2, 'bar', 'bar@gmail.com'),\
@@ -326,8 +298,8 @@ mod tests {
         ).unwrap()
     }
 
-    fn tear_down_test_data(client: Arc<RefCell<Client>>) {
-        client.borrow_mut().batch_execute(
+    fn tear_down_test_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
             "DROP TABLE IF EXISTS test_table"
         ).unwrap();
     }
@@ -335,6 +307,7 @@ mod tests {
     fn assert_index_info(expected_index_info: &IndexInfo, actual_index_info: &IndexInfo) {
         assert_eq!(expected_index_info.index_type, actual_index_info.index_type);
         assert_eq!(expected_index_info.columns, actual_index_info.columns);
+        assert_eq!(expected_index_info.key_columns, actual_index_info.key_columns);
         assert_eq!(expected_index_info.table_name, actual_index_info.table_name);
         assert_eq!(expected_index_info.primary_indexed_attributes, actual_index_info.primary_indexed_attributes);
     }
@@ -343,12 +316,14 @@ mod tests {
     pub fn test_index_info() {
         let client_ref = Arc::new(super::init_client(
             "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
-        ));
+        ).unwrap());
         setup_test_data(Arc::clone(&client_ref));
-        let actual_index_info = get_index_info(Arc::clone(&client_ref), "idx_users_name_email".to_string());
+        let actual_index_info = get_index_info(Arc::clone(&client_ref), "idx_users_name_email".to_string()).unwrap();
         let expected_index_info = IndexInfo {
             index_type: "btree".to_string(),
             columns: vec!["name".to_string(), "email".to_string()],
+            key_columns: 2,
+            attributes: vec![],
             table_name: "test_table".to_string(),
             primary_indexed_attributes: vec!["id".to_string()],
             table_oid: 0,
@@ -362,9 +337,9 @@ mod tests {
     pub fn test_metadata_page_information() {
         let client_ref = Arc::new(super::init_client(
             "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
-        ));
+        ).unwrap());
         setup_test_data(Arc::clone(&client_ref));
-        let actual_metadata_page = get_metadata_page(Arc::clone(&client_ref), "idx_users_name_email".to_string());
+        let actual_metadata_page = get_metadata_page(Arc::clone(&client_ref), "idx_users_name_email".to_string()).unwrap();
         let expected_metadata_page = super::MetadataPage::new(1, 1, 1, 0, 0);
         assert_ne!(expected_metadata_page, actual_metadata_page);
         tear_down_test_data(Arc::clone(&client_ref));
@@ -372,18 +347,40 @@ mod tests {
 
     #[test]
     pub fn test_get_row() {
-        // Todo: update this test with predictable data
         let client_ref = Arc::new(super::init_client(
             "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
-        ));
+        ).unwrap());
         setup_test_data(Arc::clone(&client_ref));
         insert_data(Arc::clone(&client_ref));
-        let actual_index_info = get_index_info(Arc::clone(&client_ref), "idx_users_name_email".to_string());
-        let row_data = get_row(Arc::clone(&client_ref), vec![Tid { block_number: 0, offset_number: 1 }, Tid { block_number: 0, offset_number: 2 }], Rc::new(actual_index_info));
-        for (k, v) in row_data.iter() {
-            println!("Key: {}, Value: {:?}", k, v);
-        }
+        let actual_index_info = get_index_info(Arc::clone(&client_ref), "idx_users_name_email".to_string()).unwrap();
+        let tid = |offset_number: u16| Tid { block_number: 0, offset_number };
+        let row_data = get_row(Arc::clone(&client_ref), vec![tid(1), tid(2), tid(100)], Arc::new(actual_index_info)).unwrap();
         tear_down_test_data(Arc::clone(&client_ref));
+
+        let row = |id: &str, name: &str, email: &str| RowData::new(vec![Some(id.to_string())], vec![Some(name.to_string()), Some(email.to_string())]);
+        assert_eq!(2, row_data.len());
+        assert_eq!(Some(&row("1", "foo", "foo@gmail.com")), row_data.get(&tid(1)));
+        assert_eq!(Some(&row("2", "bar", "bar@gmail.com")), row_data.get(&tid(2)));
+    }
+
+    #[test]
+    pub fn test_get_row_quotes_identifiers() {
+        let client_ref = Arc::new(super::init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        client_ref.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS \"Test_Users\";
+            CREATE TABLE \"Test_Users\" (id INTEGER PRIMARY KEY, \"order\" TEXT NOT NULL);
+            CREATE INDEX idx_test_users_order ON \"Test_Users\" (\"order\");
+            INSERT INTO \"Test_Users\" VALUES (1, 'first'), (2, 'second');"
+        ).unwrap();
+        let index_info = get_index_info(Arc::clone(&client_ref), "idx_test_users_order".to_string()).unwrap();
+        let tid = |offset_number: u16| Tid { block_number: 0, offset_number };
+        let row_data = get_row(Arc::clone(&client_ref), vec![tid(2)], Arc::new(index_info)).unwrap();
+        client_ref.lock().unwrap().batch_execute("DROP TABLE IF EXISTS \"Test_Users\"").unwrap();
+
+        let expected = RowData::new(vec![Some("2".to_string())], vec![Some("second".to_string())]);
+        assert_eq!(Some(&expected), row_data.get(&tid(2)));
     }
 
     #[test]
@@ -391,13 +388,13 @@ mod tests {
         // Todo: update this test with predictable data
         let client_ref = Arc::new(super::init_client(
             "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
-        ));
+        ).unwrap());
         setup_test_data(Arc::clone(&client_ref));
         insert_data(Arc::clone(&client_ref));
         let index_name = "idx_users_name_email".to_string();
-        let actual_index_info = get_index_info(Arc::clone(&client_ref), index_name.clone());
-        let metadata_page = get_metadata_page(Arc::clone(&client_ref), index_name.clone());
-        let page = get_page(Arc::clone(&client_ref), metadata_page.root, index_name, Rc::new(actual_index_info));
+        let actual_index_info = get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        let metadata_page = get_metadata_page(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        let page = get_page(Arc::clone(&client_ref), metadata_page.root, &index_name, Arc::new(actual_index_info)).unwrap();
         println!("{:?}", page);
         tear_down_test_data(Arc::clone(&client_ref));
     }
@@ -406,12 +403,184 @@ mod tests {
     pub fn test_get_tree() {
         let client_ref = Arc::new(super::init_client(
             "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "".to_string(), "".to_string(),
-        ));
+        ).unwrap());
         setup_test_data(Arc::clone(&client_ref));
         insert_data(Arc::clone(&client_ref));
         let index_name = "idx_users_name_email".to_string();
-        let actual_index_info = get_index_info(Arc::clone(&client_ref), index_name.clone());
-        let tree = generate_btree(Arc::clone(&client_ref), index_name.clone(), Rc::new(actual_index_info));
+        let actual_index_info = get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        let tree = generate_btree(&Pool::from(Arc::clone(&client_ref)), index_name.clone(), Arc::new(actual_index_info)).unwrap();
+        tear_down_test_data(Arc::clone(&client_ref));
+        assert!(tree.root().is_root);
+        assert_eq!(8, tree.pages.iter().filter(|page| page.is_leaf).map(|page| page.items.len()).sum::<usize>());
+    }
+
+    #[test]
+    pub fn test_get_page_async() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let (client, connection) = runtime.block_on(
+            tokio_postgres::connect("host=localhost port=5432 dbname=postgres user=postgres", tokio_postgres::NoTls)
+        ).unwrap();
+        runtime.spawn(connection);
+        runtime.block_on(client.batch_execute(
+            "DROP TABLE IF EXISTS test_async_table;
+            CREATE TABLE test_async_table (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+            INSERT INTO test_async_table SELECT i, 'name_' || i FROM generate_series(1, 1000) i;"
+        )).unwrap();
+        let index_name = "test_async_table_pkey".to_string();
+        let index_info = Arc::new(runtime.block_on(get_index_info_async(&client, index_name.clone())).unwrap());
+        assert_eq!("btree", index_info.index_type);
+        let metadata_page = runtime.block_on(get_metadata_page_async(&client, index_name.clone())).unwrap();
+        assert_eq!(1, metadata_page.level);
+
+        // the futures can be handed to a multi-threaded executor
+        fn assert_send<F: Future + Send>(future: F) -> F { future }
+        let root = runtime.block_on(assert_send(get_page_async(&client, metadata_page.root, &index_name, index_info.clone()))).unwrap();
+        assert!(!root.is_leaf);
+        let leaf_id = root.items[0].child.unwrap();
+        let leaf = runtime.block_on(get_page_async(&client, leaf_id, &index_name, index_info)).unwrap();
+        assert!(leaf.is_leaf);
+        assert_eq!(Some(Tid { block_number: 0, offset_number: 1 }), leaf.items[0].obj_id);
+        runtime.block_on(client.batch_execute("DROP TABLE test_async_table")).unwrap();
+    }
+
+    #[test]
+    pub fn test_decode_btree_page_matches_pageinspect() {
+        let client_ref = Arc::new(super::init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        setup_test_data(Arc::clone(&client_ref));
+        client_ref.lock().unwrap().batch_execute(
+            "INSERT INTO test_table(name, email) SELECT 'name_' || i, 'user_' || i || '@gmail.com' FROM generate_series(1, 2000) i"
+        ).unwrap();
+        let index_name = "idx_users_name_email".to_string();
+        let metadata_page = get_metadata_page(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        assert_eq!(1, metadata_page.level);
+        for page_id in [metadata_page.root, 1] {
+            let raw = get_raw_page(Arc::clone(&client_ref), &index_name, page_id).unwrap();
+            let page = decode_btree_page(page_id, &raw, &[]).unwrap();
+            let rows = client_ref.lock().unwrap().query(
+                "SELECT ctid, coalesce(data, '') FROM bt_page_items($1, $2) ORDER BY itemoffset", &[&index_name, &page_id],
+            ).unwrap();
+            let mut expected: Vec<(Tid, String)> = rows.iter().map(|row| (row.get(0), row.get(1))).collect();
+            if page.next_page_id.is_some() {
+                assert_eq!(Some(expected.remove(0).1), page.high_key);
+            }
+            let actual: Vec<(u32, String)> = page.items.iter()
+                .map(|item| (item.pointer.unwrap() as u32, item.value.clone()))
+                .collect();
+            assert_eq!(expected.iter().map(|(tid, data)| (tid.block_number, data.clone())).collect::<Vec<(u32, String)>>(), actual);
+            if page.is_leaf {
+                let heap_tids: Vec<Tid> = page.items.iter().filter_map(|item| item.obj_id.clone()).collect();
+                assert_eq!(expected.into_iter().map(|(tid, _)| tid).collect::<Vec<Tid>>(), heap_tids);
+            }
+        }
         tear_down_test_data(Arc::clone(&client_ref));
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_decode_btree_page_matches_bt_page_items_columns() {
+        // data, dead, htid and tids of an item
+        type ItemColumns = (String, Option<bool>, Option<Tid>, Option<Vec<Tid>>);
+        let client_ref = Arc::new(super::init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        let version: i32 = client_ref.lock().unwrap().query_one("SELECT current_setting('server_version_num')::int", &[]).unwrap().get(0);
+        if version < 130000 {
+            // dead, htid and tids were added to bt_page_items along with deduplication
+            return;
+        }
+        client_ref.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_dedup_table;
+            CREATE TABLE test_dedup_table (id INTEGER NOT NULL, code INTEGER NOT NULL) WITH (autovacuum_enabled = off);
+            INSERT INTO test_dedup_table SELECT i, i % 10 FROM generate_series(1, 5000) i;
+            CREATE INDEX idx_test_dedup_code ON test_dedup_table (code, id);
+            CREATE INDEX idx_test_dedup_posting ON test_dedup_table (code);
+            DELETE FROM test_dedup_table WHERE code = 3;"
+        ).unwrap();
+        // an index scan over the deleted rows marks their index tuples dead
+        client_ref.lock().unwrap().batch_execute(
+            "SET enable_seqscan = off; SET enable_bitmapscan = off;
+            SELECT count(*) FROM test_dedup_table WHERE code = 3;
+            RESET enable_seqscan; RESET enable_bitmapscan;"
+        ).unwrap();
+        let mut postings = 0;
+        for index_name in ["idx_test_dedup_code".to_string(), "idx_test_dedup_posting".to_string()] {
+            let metadata_page = get_metadata_page(Arc::clone(&client_ref), index_name.clone()).unwrap();
+            let row = client_ref.lock().unwrap().query_one("SELECT allequalimage FROM bt_metap($1)", &[&index_name]).unwrap();
+            assert_eq!(Some(row.get::<_, bool>(0)), metadata_page.allequalimage);
+            let blocks = get_index_blocks(Arc::clone(&client_ref), &index_name).unwrap();
+            for page_id in 1..blocks {
+                let raw = get_raw_page(Arc::clone(&client_ref), &index_name, page_id).unwrap();
+                let page = decode_btree_page(page_id, &raw, &[]).unwrap();
+                let rows = client_ref.lock().unwrap().query(
+                    "SELECT coalesce(data, ''), dead, htid, tids FROM bt_page_items($1, $2) ORDER BY itemoffset", &[&index_name, &page_id],
+                ).unwrap();
+                let mut expected: Vec<ItemColumns> = rows.iter()
+                    .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+                    .collect();
+                if page.next_page_id.is_some() {
+                    expected.remove(0);
+                }
+                let actual: Vec<ItemColumns> = page.items.iter()
+                    .map(|item| (item.value.clone(), item.dead, item.htid.clone(), item.tids.clone()))
+                    .collect();
+                assert_eq!(expected, actual);
+                postings += page.items.iter().filter(|item| item.tids.is_some()).count();
+            }
+        }
+        assert!(postings > 0);
+        client_ref.lock().unwrap().batch_execute("DROP TABLE test_dedup_table").unwrap();
+    }
+
+    #[test]
+    pub fn test_decode_btree_page_keys() {
+        let client_ref = Arc::new(super::init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        client_ref.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_key_table;
+            CREATE TABLE test_key_table (name TEXT, id INTEGER, ref UUID, created TIMESTAMP, amount NUMERIC, code SMALLINT);
+            INSERT INTO test_key_table SELECT
+                CASE WHEN i % 7 = 0 THEN NULL WHEN i % 5 = 0 THEN repeat('long', 40) || i ELSE 'name_' || (i % 20) END,
+                i,
+                md5(i::text)::uuid,
+                CASE WHEN i % 11 = 0 THEN NULL ELSE timestamp '2001-02-03 04:05:06.789' + i * interval '1 day 1.5 seconds' END,
+                CASE WHEN i % 13 = 0 THEN 'NaN' ELSE (i - 250) * 123.4567 ^ (i % 4) END,
+                i % 3
+            FROM generate_series(1, 500) i;
+            INSERT INTO test_key_table VALUES ('bc', 0, NULL, '0044-03-15 12:00:00 BC', -0.000001, NULL);
+            CREATE INDEX idx_test_key ON test_key_table (name, id, ref, created, amount) INCLUDE (code);"
+        ).unwrap();
+        let index_name = "idx_test_key".to_string();
+        let index_info = Arc::new(get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap());
+        assert_eq!(5, index_info.key_columns);
+        assert_eq!(vec!["name", "id", "ref", "created", "amount", "code"], index_info.attributes.iter().map(|a| a.name.as_str()).collect::<Vec<&str>>());
+        let blocks = get_index_blocks(Arc::clone(&client_ref), &index_name).unwrap();
+        let mut leaf_tids = vec![];
+        for page_id in 1..blocks {
+            let raw = get_raw_page(Arc::clone(&client_ref), &index_name, page_id).unwrap();
+            let page = decode_btree_page(page_id, &raw, &index_info.attributes).unwrap();
+            for item in page.items.iter() {
+                let key = item.key.as_ref().unwrap();
+                if !page.is_leaf {
+                    assert_eq!(item.key_attributes.unwrap() as usize, key.len());
+                    continue;
+                }
+                let row = client_ref.lock().unwrap().query_one(
+                    "SELECT name, id::text, ref::text, created::text, amount::text, code::text FROM test_key_table WHERE ctid = $1::text::tid",
+                    &[&item.obj_id.as_ref().unwrap().to_string()],
+                ).unwrap();
+                let expected: Vec<Option<String>> = (0..6).map(|i| row.get(i)).collect();
+                let actual: Vec<Option<String>> = key.iter().map(|value| value.value.clone()).collect();
+                assert_eq!(expected, actual);
+                assert_eq!(expected.iter().map(|value| value.as_deref().unwrap_or("NULL")).collect::<Vec<&str>>().join(", "), item.value);
+                leaf_tids.push(item.obj_id.clone().unwrap());
+            }
+        }
+        assert_eq!(501, leaf_tids.len());
+        let rows = get_row(Arc::clone(&client_ref), leaf_tids, index_info).unwrap();
+        assert_eq!(501, rows.len());
+        assert!(rows.values().any(|row| row.column_data.contains(&None)));
+        client_ref.lock().unwrap().batch_execute("DROP TABLE test_key_table").unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::{Arc, Mutex};
+use log::info;
+use crate::db::Client;
+use crate::error::{Error, Result};
+
+const HEAP_FUNCTIONS: [&str; 2] = ["heap_page_items", "heap_tuple_infomask_flags"];
+
+/// The `pageinspect` functions inspecting an index of `access_method` calls, plus the ones
+/// reading the heap pages its items point to when `heap_pages` is set.
+pub fn pageinspect_functions(access_method: &str, heap_pages: bool) -> Vec<&'static str> {
+    let mut functions = vec!["get_raw_page"];
+    functions.extend_from_slice(match access_method {
+        // btree pages are decoded from their raw image alone
+        "btree" => &[][..],
+        "gin" => &["gin_metapage_info", "gin_page_opaque_info", "gin_leafpage_items"],
+        "gist" => &["gist_page_opaque_info", "gist_page_items"],
+        "brin" => &["brin_metapage_info", "brin_revmap_data", "brin_page_items"],
+        "hash" => &["hash_metapage_info", "hash_page_type", "hash_page_stats", "hash_page_items", "hash_bitmap_info"],
+        _ => &[],
+    });
+    if heap_pages {
+        functions.extend_from_slice(&HEAP_FUNCTIONS);
+    }
+    functions
+}
+
+/// Checks that `pageinspect` is installed in the database, has all of `functions`, and that the
+/// user is a superuser. pageinspect grants `EXECUTE` to `PUBLIC` and checks for a superuser inside
+/// its functions, so function privileges say nothing. With `create`, a missing extension is
+/// installed first. The error says what exactly is missing.
+pub fn check_pageinspect(client: Arc<Mutex<Client>>, functions: &[&str], create: bool) -> Result<()> {
+    let client = client.lock().unwrap();
+    client.block_on(check_pageinspect_async(client.client(), functions, create))
+}
+
+pub async fn check_pageinspect_async(client: &tokio_postgres::Client, functions: &[&str], create: bool) -> Result<()> {
+    let status_query = r#"
+        SELECT
+        current_database()::text,
+        current_user::text,
+        (SELECT rolsuper FROM pg_roles WHERE rolname = current_user),
+        (SELECT extversion FROM pg_extension WHERE extname = 'pageinspect'),
+        EXISTS (SELECT FROM pg_available_extensions WHERE name = 'pageinspect')
+    "#;
+    let row = client.query_one(status_query, &[]).await?;
+    let database: String = row.get(0);
+    let user: String = row.get(1);
+    let is_superuser: bool = row.get(2);
+    let version: Option<String> = row.get(3);
+    let available: bool = row.get(4);
+    let version = match version {
+        Some(version) => version,
+        None if create && available => create_pageinspect_async(client).await?,
+        None => return Err(Error::ExtensionNotInstalled { database, available }),
+    };
+    info!("pageinspect {} is installed in {}", version, database);
+
+    // functions of the extension by name, false for the ones its version doesn't have yet
+    let functions_query = r#"
+        SELECT
+        f.name,
+        bool_or(p.oid IS NOT NULL)
+        FROM unnest($1::text[]) WITH ORDINALITY AS f(name, position)
+        LEFT JOIN pg_proc p ON p.proname = f.name AND p.oid IN (
+            SELECT d.objid
+            FROM pg_depend d
+            JOIN pg_extension e ON e.oid = d.refobjid
+            WHERE e.extname = 'pageinspect' AND d.classid = 'pg_proc'::regclass AND d.deptype = 'e'
+        )
+        GROUP BY f.name, f.position
+        ORDER BY f.position
+    "#;
+    let rows = client.query(functions_query, &[&functions]).await?;
+    let missing: Vec<String> = rows.iter()
+        .filter(|row| !row.get::<_, bool>(1))
+        .map(|row| row.get(0))
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::ExtensionOutdated { version, functions: missing });
+    }
+    if !is_superuser {
+        return Err(Error::NotPrivileged { user, functions: functions.iter().map(|function| function.to_string()).collect() });
+    }
+    Ok(())
+}
+
+/// Runs `CREATE EXTENSION pageinspect` in a transaction of its own, rolled back if it fails, and
+/// returns the version installed.
+async fn create_pageinspect_async(client: &tokio_postgres::Client) -> Result<String> {
+    info!("Creating the pageinspect extension");
+    client.batch_execute("BEGIN").await?;
+    let created = async {
+        client.batch_execute("CREATE EXTENSION pageinspect").await?;
+        client.query_one("SELECT extversion FROM pg_extension WHERE extname = 'pageinspect'", &[]).await
+    }.await;
+    match created {
+        Ok(row) => {
+            client.batch_execute("COMMIT").await?;
+            Ok(row.get(0))
+        }
+        Err(e) => {
+            client.batch_execute("ROLLBACK").await?;
+            Err(e.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::db::init_client;
+    use crate::error::Error;
+    use super::{check_pageinspect, pageinspect_functions};
+
+    #[test]
+    pub fn test_check_pageinspect() {
+        let connect = |db: &str, user: &str| Arc::new(init_client(
+            "localhost".to_string(), "5432".to_string(), db.to_string(), user.to_string(), "".to_string(),
+        ).unwrap());
+        let admin = connect("postgres", "postgres");
+        // DROP and CREATE DATABASE can't run in the implicit transaction of a multi-statement batch
+        for statement in [
+            "DROP DATABASE IF EXISTS test_extension_db",
+            "CREATE DATABASE test_extension_db",
+            "DROP ROLE IF EXISTS test_extension_role",
+            "CREATE ROLE test_extension_role LOGIN",
+        ] {
+            admin.lock().unwrap().batch_execute(statement).unwrap();
+        }
+        let functions = pageinspect_functions("btree", true);
+        assert_eq!(vec!["get_raw_page", "heap_page_items", "heap_tuple_infomask_flags"], functions);
+        {
+            let client = connect("test_extension_db", "postgres");
+            match check_pageinspect(Arc::clone(&client), &functions, false) {
+                Err(Error::ExtensionNotInstalled { database, available }) => {
+                    assert_eq!("test_extension_db", database);
+                    assert!(available);
+                }
+                other => panic!("expected ExtensionNotInstalled, got {:?}", other),
+            }
+            check_pageinspect(Arc::clone(&client), &functions, true).unwrap();
+            check_pageinspect(Arc::clone(&client), &functions, false).unwrap();
+
+            // a role with default privileges may execute the functions, which then refuse to run
+            let role = connect("test_extension_db", "test_extension_role");
+            match check_pageinspect(Arc::clone(&role), &functions, false) {
+                Err(Error::NotPrivileged { user, functions: required }) => {
+                    assert_eq!("test_extension_role", user);
+                    assert_eq!(functions, required);
+                }
+                other => panic!("expected NotPrivileged, got {:?}", other),
+            }
+            let raw_page = role.lock().unwrap().query_one("SELECT get_raw_page('pg_class', 0)", &[]).unwrap_err();
+            assert!(matches!(Error::from(raw_page), Error::PermissionDenied(_)));
+            match check_pageinspect(role, &["get_raw_page", "no_such_function"], false) {
+                Err(Error::ExtensionOutdated { functions, .. }) => assert_eq!(vec!["no_such_function".to_string()], functions),
+                other => panic!("expected ExtensionOutdated, got {:?}", other),
+            }
+        }
+        admin.lock().unwrap().batch_execute("DROP DATABASE test_extension_db WITH (FORCE)").unwrap();
+        admin.lock().unwrap().batch_execute("DROP ROLE test_extension_role").unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::{Arc, Mutex};
+use log::info;
+use crate::error::Result;
+use crate::core::gin::{decode_entry_tuple, decode_item_pointers, decode_pending_tuple, decode_posting_items, GinPostings, GIN_ROOT_BLKNO};
+use crate::core::raw_page::{hex_string, max_align, RawPage, INVALID_BLOCK_NUMBER, ITEM_POINTER_SIZE};
+use crate::core::structs::{GinMetadataPage, Item, Page, Tid};
+use crate::db::{Client, IndexInfo};
+
+pub fn get_gin_metadata_page(client: Arc<Mutex<Client>>, index_name: String) -> Result<GinMetadataPage> {
+    let client = client.lock().unwrap();
+    client.block_on(get_gin_metadata_page_async(client.client(), index_name))
+}
+
+pub async fn get_gin_metadata_page_async(client: &tokio_postgres::Client, index_name: String) -> Result<GinMetadataPage> {
+    let gin_metadata_query = r#"
+        SELECT
+            pending_head,
+            pending_tail,
+            tail_free_size,
+            n_pending_pages,
+            n_pending_tuples,
+            n_total_pages,
+            n_entry_pages,
+            n_data_pages,
+            n_entries,
+            version
+        FROM gin_metapage_info(get_raw_page($1, 0));
+    "#;
+    info!("Getting GIN metadata page for index: {}", index_name);
+    let row = client.query_one(gin_metadata_query, &[&index_name]).await?;
+    Ok(GinMetadataPage {
+        pending_head: row.get(0),
+        pending_tail: row.get(1),
+        tail_free_size: row.get(2),
+        n_pending_pages: row.get(3),
+        n_pending_tuples: row.get(4),
+        n_total_pages: row.get(5),
+        n_entry_pages: row.get(6),
+        n_data_pages: row.get(7),
+        n_entries: row.get(8),
+        version: row.get(9),
+    })
+}
+
+struct GinPageInfo {
+    raw: Vec<u8>,
+    rightlink: i64,
+    maxoff: i32,
+    flags: Vec<String>,
+}
+
+impl GinPageInfo {
+    fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|page_flag| page_flag == flag)
+    }
+}
+
+/// The images and opaque data of several pages in one query, in the order of `page_ids`.
+async fn get_gin_page_infos(client: &tokio_postgres::Client, page_ids: &[i64], index_name: &String) -> Result<Vec<GinPageInfo>> {
+    let pages_query = r#"
+        SELECT p.raw, o.rightlink, o.maxoff, o.flags
+        FROM unnest($2::bigint[]) WITH ORDINALITY AS t(block_number, position),
+        LATERAL get_raw_page($1, t.block_number) AS p(raw),
+        LATERAL gin_page_opaque_info(p.raw) AS o
+        ORDER BY t.position;
+    "#;
+    let rows = client.query(pages_query, &[index_name, &page_ids]).await?;
+    Ok(rows.iter().map(|row| GinPageInfo {
+        raw: row.get(0),
+        rightlink: row.get(1),
+        maxoff: row.get(2),
+        flags: row.get(3),
+    }).collect())
+}
+
+fn new_gin_page(page_id: i64, info: &GinPageInfo, is_leaf: bool) -> Page {
+    let mut page = Page::new(page_id, 0, is_leaf, false, info.rightlink, 0);
+    page.prev_page_id = None;
+    if info.rightlink == INVALID_BLOCK_NUMBER {
+        page.next_page_id = None;
+    }
+    page.nb_items = Some(info.maxoff);
+    page.flags = info.flags.clone();
+    page
+}
+
+/// Fetches GIN pages of the entry tree or of posting trees. The items' `child` is set to what's
+/// below them: entry tree downlinks, posting tree roots hanging off entry leaves, and posting tree
+/// downlinks. Besides the page images, only compressed posting tree leaves need a query, one for
+/// all of them.
+pub fn get_gin_pages(client: Arc<Mutex<Client>>, page_ids: &[i64], index_name: &String, index_info: Arc<IndexInfo>) -> Result<Vec<Page>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_gin_pages_async(client.client(), page_ids, index_name, index_info))
+}
+
+pub async fn get_gin_pages_async(client: &tokio_postgres::Client, page_ids: &[i64], index_name: &String, index_info: Arc<IndexInfo>) -> Result<Vec<Page>> {
+    info!("getting {} GIN pages", page_ids.len());
+    let infos = get_gin_page_infos(client, page_ids, index_name).await?;
+    let mut pages: Vec<Page> = page_ids.iter().zip(infos.iter()).map(|(page_id, info)| {
+        let mut page = new_gin_page(*page_id, info, info.has_flag("leaf"));
+        page.is_root = *page_id == GIN_ROOT_BLKNO;
+        page.items = if info.has_flag("data") {
+            get_gin_posting_items(&page, info)
+        } else {
+            get_gin_entry_items(&page, info, index_info.clone())
+        };
+        page
+    }).collect();
+    set_compressed_leaf_items(client, &mut pages, &infos).await?;
+    Ok(pages)
+}
+
+fn get_gin_entry_items(page: &Page, info: &GinPageInfo, index_info: Arc<IndexInfo>) -> Vec<Item> {
+    info!("decoding GIN entry items of page {}", page.id);
+    let raw_page = match RawPage::new(&info.raw) {
+        Some(raw_page) => raw_page,
+        None => return vec![],
+    };
+    let mut items: Vec<Item> = vec![];
+    for (_, tuple) in raw_page.index_tuples() {
+        let entry = decode_entry_tuple(&tuple, page.is_leaf, &index_info.attributes);
+        let item = match (entry.downlink, entry.postings) {
+            (Some(downlink), _) => Item::new(entry.key, Some(downlink as i64), Some(downlink as i64), None),
+            (None, Some(GinPostings::Tree(posting_root))) => Item::new(entry.key, Some(posting_root as i64), Some(posting_root as i64), None),
+            (None, Some(GinPostings::List(tids))) => {
+                let mut item = Item::new(entry.key, None, None, tids.first().cloned());
+                item.tids = Some(tids);
+                item
+            }
+            (None, None) => Item::new(entry.key, None, None, None),
+        };
+        items.push(item);
+    }
+    items
+}
+
+/// Decodes the items of a posting tree page, except for compressed leaves which are left to
+/// `set_compressed_leaf_items`.
+fn get_gin_posting_items(page: &Page, info: &GinPageInfo) -> Vec<Item> {
+    info!("decoding GIN posting tree items of page {}", page.id);
+    let raw_page = match RawPage::new(&info.raw) {
+        Some(raw_page) => raw_page,
+        None => return vec![],
+    };
+    if !page.is_leaf {
+        return decode_posting_items(raw_page.contents(), info.maxoff as usize).into_iter()
+            .map(|(child_block, key)| Item::new(format!("{}", key), Some(child_block as i64), Some(child_block as i64), None))
+            .collect();
+    }
+    if info.has_flag("compressed") {
+        return vec![];
+    }
+    let contents = raw_page.contents();
+    let data = contents.get(max_align(ITEM_POINTER_SIZE)..).unwrap_or(&[]);
+    decode_item_pointers(data, info.maxoff as usize).into_iter()
+        .map(|tid| Item::new(format!("{}", tid), None, Some(tid.block_number as i64), Some(tid)))
+        .collect()
+}
+
+/// Sets the items of the compressed posting tree leaves among `pages`, one per posting list
+/// segment, with a single `gin_leafpage_items` query over all of them.
+async fn set_compressed_leaf_items(client: &tokio_postgres::Client, pages: &mut [Page], infos: &[GinPageInfo]) -> Result<()> {
+    let compressed: Vec<usize> = infos.iter().enumerate()
+        .filter(|(_, info)| info.has_flag("data") && info.has_flag("leaf") && info.has_flag("compressed"))
+        .map(|(i, _)| i)
+        .collect();
+    if compressed.is_empty() {
+        return Ok(());
+    }
+    info!("getting GIN posting lists of {} compressed leaves", compressed.len());
+    let raw_pages: Vec<&[u8]> = compressed.iter().map(|i| infos[*i].raw.as_slice()).collect();
+    let leaf_items_query = r#"
+        SELECT t.position, i.first_tid, i.nbytes, i.tids
+        FROM unnest($1::bytea[]) WITH ORDINALITY AS t(raw, position),
+        LATERAL gin_leafpage_items(t.raw) WITH ORDINALITY AS i(first_tid, nbytes, tids, item)
+        ORDER BY t.position, i.item;
+    "#;
+    let rows = client.query(leaf_items_query, &[&raw_pages]).await?;
+    for row in rows.iter() {
+        let position: i64 = row.get(0);
+        let first_tid: Tid = row.get(1);
+        let nbytes: i16 = row.get(2);
+        let tids: Vec<Tid> = row.get(3);
+        let mut item = Item::new(format!("{} tids in {} bytes", tids.len(), nbytes), None, Some(first_tid.block_number as i64), Some(first_tid));
+        item.tids = Some(tids);
+        pages[compressed[position as usize - 1]].items.push(item);
+    }
+    Ok(())
+}
+
+/// Walks the fast-update pending list from `head` along the right links.
+pub fn get_gin_pending_list(client: Arc<Mutex<Client>>, head: i64, index_name: String, index_info: Arc<IndexInfo>) -> Result<Vec<Page>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_gin_pending_list_async(client.client(), head, index_name, index_info))
+}
+
+pub async fn get_gin_pending_list_async(client: &tokio_postgres::Client, head: i64, index_name: String, index_info: Arc<IndexInfo>) -> Result<Vec<Page>> {
+    let mut pages: Vec<Page> = vec![];
+    let mut page_id = head;
+    while page_id != INVALID_BLOCK_NUMBER {
+        info!("getting GIN pending list page {}", page_id);
+        let info = get_gin_page_infos(client, &[page_id], &index_name).await?.pop().unwrap();
+        let mut page = new_gin_page(page_id, &info, true);
+        if let Some(raw_page) = RawPage::new(&info.raw) {
+            page.items = raw_page.index_tuples().iter().map(|(_, tuple)| {
+                let (key, heap_tid) = decode_pending_tuple(tuple, &index_info.attributes);
+                let value = if key.is_empty() { hex_string(tuple.bytes()) } else { key };
+                Item::new(value, None, Some(heap_tid.block_number as i64), Some(heap_tid))
+            }).collect();
+        }
+        page_id = info.rightlink;
+        pages.push(page);
+    }
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::core::gin::generate_gin;
+    use crate::db::{get_gin_metadata_page, get_index_info, init_client, Client, Pool};
+
+    fn setup_test_data(client: Arc<Mutex<Client>>) {
+        tear_down_test_data(Arc::clone(&client));
+        client.lock().unwrap().batch_execute(
+            "CREATE TABLE IF NOT EXISTS test_gin_table (
+            id SERIAL PRIMARY KEY,
+            body TSVECTOR NOT NULL
+        ) WITH (autovacuum_enabled = off);
+        INSERT INTO test_gin_table(body)
+            SELECT to_tsvector('simple', 'common word' || (i % 20)) FROM generate_series(1, 20000) i;
+        CREATE INDEX IF NOT EXISTS idx_test_gin_body ON test_gin_table USING gin (body);
+        INSERT INTO test_gin_table(body)
+            SELECT to_tsvector('simple', 'pending' || i) FROM generate_series(1, 10) i;"
+        ).unwrap();
+    }
+
+    fn tear_down_test_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_gin_table"
+        ).unwrap();
+    }
+
+
+    #[test]
+    pub fn test_get_gin_tree() {
+        let client_ref = Arc::new(init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        setup_test_data(Arc::clone(&client_ref));
+        let index_name = "idx_test_gin_body".to_string();
+        let index_info = get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        assert_eq!("gin", index_info.index_type);
+
+        let metadata_page = get_gin_metadata_page(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        assert_eq!(10, metadata_page.n_pending_tuples);
+
+        let tree = generate_gin(&Pool::from(Arc::clone(&client_ref)), index_name, Arc::new(index_info)).unwrap();
+        assert!(tree.root().is_root);
+        assert!(tree.pages.iter().any(|page| page.flags.contains(&"data".to_string())));
+        let pending_items: usize = tree.pending_list.iter().map(|page| page.items.len()).sum();
+        assert_eq!(10, pending_items);
+        assert!(tree.pending_list[0].items.iter().any(|item| item.value == "pending1"));
+        tear_down_test_data(Arc::clone(&client_ref));
+    }
+}
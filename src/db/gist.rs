@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use log::info;
+use crate::error::Result;
+use crate::core::gist::{count_overlapping_pairs, parse_bounding_box, parse_lsn, GIST_ROOT_BLKNO};
+use crate::core::raw_page::INVALID_BLOCK_NUMBER;
+use crate::core::structs::{BoundingBox, Item, Page, Tid};
+use crate::db::Client;
+
+/// Added to a page's flags when its NSN is newer than the LSN of the parent it was reached from:
+/// the page was split after the parent was last written, so the downlink to the new right
+/// sibling is still missing and scans have to follow the right link, which `walk` does too.
+pub const SPLIT_NOT_IN_PARENT: &str = "split_not_in_parent";
+
+/// Fetches GiST pages, their downlinks set as the items' `child`, with one query for the page
+/// headers and one for the items of all of them. `parent_lsns` holds, for each page, the LSN of
+/// the page the downlink was read from and is used to detect splits the parent doesn't know about
+/// yet.
+pub fn get_gist_pages(client: Arc<Mutex<Client>>, page_ids: &[i64], index_name: &String, parent_lsns: &[Option<u64>]) -> Result<Vec<Page>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_gist_pages_async(client.client(), page_ids, index_name, parent_lsns))
+}
+
+pub async fn get_gist_pages_async(client: &tokio_postgres::Client, page_ids: &[i64], index_name: &String, parent_lsns: &[Option<u64>]) -> Result<Vec<Page>> {
+    info!("getting {} GiST pages", page_ids.len());
+    let pages_query = r#"
+        SELECT
+        o.lsn::text,
+        o.nsn::text,
+        o.rightlink,
+        o.flags
+        FROM unnest($2::bigint[]) WITH ORDINALITY AS t(block_number, position),
+        LATERAL gist_page_opaque_info(get_raw_page($1, t.block_number)) AS o
+        ORDER BY t.position
+    "#;
+    let rows = client.query(pages_query, &[index_name, &page_ids]).await?;
+    let mut items = get_gist_items(client, page_ids, index_name).await?;
+
+    Ok(page_ids.iter().zip(parent_lsns).zip(rows.iter()).map(|((page_id, parent_lsn), row)| {
+        let lsn: String = row.get(0);
+        let nsn: String = row.get(1);
+        let rightlink: i64 = row.get(2);
+        let flags: Vec<String> = row.get(3);
+
+        let is_leaf = flags.iter().any(|flag| flag == "leaf");
+        let mut page = Page::new(*page_id, 0, is_leaf, *page_id == GIST_ROOT_BLKNO, rightlink, 0);
+        page.prev_page_id = None;
+        if rightlink == INVALID_BLOCK_NUMBER {
+            page.next_page_id = None;
+        }
+        page.flags = flags;
+        if let (Some(parent_lsn), Some(nsn_value)) = (parent_lsn, parse_lsn(&nsn)) {
+            if nsn_value > *parent_lsn {
+                page.flags.push(SPLIT_NOT_IN_PARENT.to_string());
+            }
+        }
+        page.lsn = Some(lsn);
+        page.nsn = Some(nsn);
+
+        page.items = items.remove(page_id).unwrap_or_default().into_iter().map(|(ctid, keys)| {
+            let keys = keys.unwrap_or_default();
+            let bounding_box = parse_bounding_box(&keys);
+            let pointer = ctid.block_number as i64;
+            let mut item = if is_leaf {
+                Item::new(keys, None, Some(pointer), Some(ctid))
+            } else {
+                Item::new(keys, Some(pointer), Some(pointer), None)
+            };
+            item.bounding_box = bounding_box;
+            item
+        }).collect();
+        let boxes: Vec<BoundingBox> = page.items.iter().filter_map(|item| item.bounding_box).collect();
+        if !boxes.is_empty() {
+            page.overlapping_pairs = Some(count_overlapping_pairs(&boxes));
+        }
+        page
+    }).collect())
+}
+
+/// The `(ctid, keys)` of the items of each page, in item order.
+async fn get_gist_items(client: &tokio_postgres::Client, page_ids: &[i64], index_name: &String) -> Result<HashMap<i64, Vec<(Tid, Option<String>)>>> {
+    info!("getting GiST items for pages {:?}", page_ids);
+    let items_query = r#"
+        SELECT
+        t.block_number,
+        i.ctid,
+        i.keys
+        FROM unnest($2::bigint[]) AS t(block_number),
+        LATERAL gist_page_items(get_raw_page($1, t.block_number), $1::regclass) AS i
+        ORDER BY t.block_number, i.itemoffset
+    "#;
+    let rows = client.query(items_query, &[index_name, &page_ids]).await?;
+    let mut items: HashMap<i64, Vec<(Tid, Option<String>)>> = HashMap::new();
+    for row in rows.iter() {
+        items.entry(row.get(0)).or_default().push((row.get(1), row.get(2)));
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::core::gist::generate_gist;
+    use crate::db::{get_index_info, init_client, Client, Pool};
+
+    fn setup_test_data(client: Arc<Mutex<Client>>) {
+        tear_down_test_data(Arc::clone(&client));
+        client.lock().unwrap().batch_execute(
+            "CREATE TABLE IF NOT EXISTS test_gist_table (
+            id SERIAL PRIMARY KEY,
+            area BOX NOT NULL
+        );
+        INSERT INTO test_gist_table(area)
+            SELECT box(point(i % 100, i / 100), point(i % 100 + 3, i / 100 + 2)) FROM generate_series(1, 3000) i;
+        CREATE INDEX IF NOT EXISTS idx_test_gist_area ON test_gist_table USING gist (area);"
+        ).unwrap();
+    }
+
+    fn tear_down_test_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_gist_table"
+        ).unwrap();
+    }
+
+    #[test]
+    pub fn test_get_gist_tree() {
+        let client_ref = Arc::new(init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        setup_test_data(Arc::clone(&client_ref));
+        let index_name = "idx_test_gist_area".to_string();
+        let index_info = get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        assert_eq!("gist", index_info.index_type);
+
+        let tree = generate_gist(&Pool::from(Arc::clone(&client_ref)), index_name, Arc::new(index_info)).unwrap();
+        let root = tree.root();
+        assert!(root.is_root);
+        assert!(!root.is_leaf);
+        assert_eq!(1, root.level);
+        assert!(root.items.iter().all(|item| item.bounding_box.is_some() && item.child.is_some()));
+        assert!(root.overlapping_pairs.is_some());
+        let leaf_items: usize = tree.pages.children(root).map(|child| child.items.len()).sum();
+        assert_eq!(3000, leaf_items);
+        tear_down_test_data(Arc::clone(&client_ref));
+    }
+}
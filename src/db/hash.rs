@@ -0,0 +1,261 @@
+use std::sync::{Arc, Mutex};
+use log::info;
+use crate::error::Result;
+use crate::core::raw_page::INVALID_BLOCK_NUMBER;
+use crate::core::structs::{HashBitmapBit, HashBucket, HashMetadataPage, Item, Page, Tid};
+use crate::db::Client;
+
+const LH_OVERFLOW_PAGE: i32 = 1 << 0;
+const LH_BUCKET_PAGE: i32 = 1 << 1;
+const LH_BITMAP_PAGE: i32 = 1 << 2;
+const LH_META_PAGE: i32 = 1 << 3;
+const LH_BUCKET_BEING_POPULATED: i32 = 1 << 4;
+const LH_BUCKET_BEING_SPLIT: i32 = 1 << 5;
+const LH_BUCKET_NEEDS_SPLIT_CLEANUP: i32 = 1 << 6;
+const LH_PAGE_HAS_DEAD_TUPLES: i32 = 1 << 7;
+
+fn hash_page_flags(hasho_flag: i32) -> Vec<String> {
+    [
+        (LH_OVERFLOW_PAGE, "overflow"),
+        (LH_BUCKET_PAGE, "bucket"),
+        (LH_BITMAP_PAGE, "bitmap"),
+        (LH_META_PAGE, "meta"),
+        (LH_BUCKET_BEING_POPULATED, "being_populated"),
+        (LH_BUCKET_BEING_SPLIT, "being_split"),
+        (LH_BUCKET_NEEDS_SPLIT_CLEANUP, "needs_split_cleanup"),
+        (LH_PAGE_HAS_DEAD_TUPLES, "has_dead_tuples"),
+    ].iter()
+        .filter(|(flag, _)| hasho_flag & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+pub fn get_hash_metadata_page(client: Arc<Mutex<Client>>, index_name: String) -> Result<HashMetadataPage> {
+    let client = client.lock().unwrap();
+    client.block_on(get_hash_metadata_page_async(client.client(), index_name))
+}
+
+pub async fn get_hash_metadata_page_async(client: &tokio_postgres::Client, index_name: String) -> Result<HashMetadataPage> {
+    let hash_metadata_query = r#"
+        SELECT
+            magic,
+            version,
+            ntuples,
+            ffactor,
+            bsize,
+            bmsize,
+            bmshift,
+            maxbucket,
+            highmask,
+            lowmask,
+            ovflpoint,
+            firstfree,
+            nmaps,
+            spares,
+            mapp
+        FROM hash_metapage_info(get_raw_page($1, 0));
+    "#;
+    info!("Getting hash metadata page for index: {}", index_name);
+    let row = client.query_one(hash_metadata_query, &[&index_name]).await?;
+    let nmaps: i64 = row.get(12);
+    let mut mapp: Vec<i64> = row.get(14);
+    // mapp is a fixed size array, only the first nmaps entries are bitmap pages
+    mapp.truncate(nmaps as usize);
+    Ok(HashMetadataPage {
+        magic: row.get(0),
+        version: row.get(1),
+        ntuples: row.get(2),
+        ffactor: row.get(3),
+        bsize: row.get(4),
+        bmsize: row.get(5),
+        bmshift: row.get(6),
+        maxbucket: row.get(7),
+        highmask: row.get(8),
+        lowmask: row.get(9),
+        ovflpoint: row.get(10),
+        firstfree: row.get(11),
+        nmaps,
+        spares: row.get(13),
+        mapp,
+    })
+}
+
+struct HashPageStats {
+    live_items: i32,
+    dead_items: i32,
+    next_block: i64,
+    prev_block: i64,
+    flag: i32,
+}
+
+/// Fetches a bucket or overflow page with its items. `hash_page_stats` rejects the other page
+/// types, so those, like the `unused` pages preallocated for future splits, are returned empty
+/// with their type as only flag and no next page.
+async fn get_hash_page(client: &tokio_postgres::Client, page_id: i64, index_name: &String) -> Result<(Page, HashPageStats)> {
+    info!("getting hash page {}", page_id);
+    let page_type: String = client.query_one("SELECT hash_page_type(get_raw_page($1, $2))", &[index_name, &page_id]).await?.get(0);
+    if page_type != "bucket" && page_type != "overflow" {
+        let stats = HashPageStats { live_items: 0, dead_items: 0, next_block: INVALID_BLOCK_NUMBER, prev_block: INVALID_BLOCK_NUMBER, flag: 0 };
+        let mut page = Page::new(page_id, 0, true, false, 0, 0);
+        page.next_page_id = None;
+        page.prev_page_id = None;
+        page.flags = vec![page_type];
+        page.nb_items = Some(0);
+        return Ok((page, stats));
+    }
+    let page_query = r#"
+        SELECT
+        live_items,
+        dead_items,
+        hasho_prevblkno,
+        hasho_nextblkno,
+        hasho_flag
+        FROM hash_page_stats(get_raw_page($1, $2))
+    "#;
+    let row = client.query_one(page_query, &[index_name, &page_id]).await?;
+    let stats = HashPageStats {
+        live_items: row.get(0),
+        dead_items: row.get(1),
+        prev_block: row.get(2),
+        next_block: row.get(3),
+        flag: row.get(4),
+    };
+    let is_overflow = stats.flag & LH_OVERFLOW_PAGE != 0;
+    let mut page = Page::new(page_id, 0, true, false, stats.next_block, stats.prev_block);
+    if stats.next_block == INVALID_BLOCK_NUMBER {
+        page.next_page_id = None;
+    }
+    // on primary bucket pages hasho_prevblkno holds the maxbucket of the last split instead
+    if !is_overflow || stats.prev_block == INVALID_BLOCK_NUMBER {
+        page.prev_page_id = None;
+    }
+    page.flags = hash_page_flags(stats.flag);
+    page.nb_items = Some(stats.live_items + stats.dead_items);
+
+    info!("getting hash items for page {}", page_id);
+    let items_query = r#"
+        SELECT ctid, data
+        FROM hash_page_items(get_raw_page($1, $2));
+    "#;
+    let rows = client.query(items_query, &[index_name, &page_id]).await?;
+    page.items = rows.iter().map(|row| {
+        let ctid: Tid = row.get(0);
+        let hash_code: i64 = row.get(1);
+        Item::new(format!("0x{:08x}", hash_code), None, Some(ctid.block_number as i64), Some(ctid))
+    }).collect();
+    Ok((page, stats))
+}
+
+async fn get_hash_bitmap_bit(client: &tokio_postgres::Client, page_id: i64, index_name: &String) -> Result<HashBitmapBit> {
+    let bitmap_query = r#"
+        SELECT bitmapblkno, bitmapbit, bitstatus
+        FROM hash_bitmap_info($1::text::regclass, $2);
+    "#;
+    let row = client.query_one(bitmap_query, &[index_name, &page_id]).await?;
+    Ok(HashBitmapBit {
+        block_number: page_id,
+        bitmap_block_number: row.get(0),
+        bit: row.get(1),
+        in_use: row.get(2),
+    })
+}
+
+/// Fetches a bucket's primary page at `page_id` and follows `hasho_nextblkno` through its
+/// overflow pages, looking each of them up in the bitmap.
+pub fn get_hash_bucket(client: Arc<Mutex<Client>>, bucket: i64, page_id: i64, index_name: String) -> Result<HashBucket> {
+    let client = client.lock().unwrap();
+    client.block_on(get_hash_bucket_async(client.client(), bucket, page_id, index_name))
+}
+
+pub async fn get_hash_bucket_async(client: &tokio_postgres::Client, bucket: i64, page_id: i64, index_name: String) -> Result<HashBucket> {
+    info!("getting hash bucket {} at page {}", bucket, page_id);
+    let mut hash_bucket = HashBucket {
+        bucket,
+        pages: vec![],
+        live_items: 0,
+        dead_items: 0,
+        overflow_pages: 0,
+        overflow_bitmap: vec![],
+        skew: 0.0,
+        is_skewed: false,
+    };
+    let mut next_page_id = page_id;
+    while next_page_id != INVALID_BLOCK_NUMBER {
+        let (page, stats) = get_hash_page(client, next_page_id, &index_name).await?;
+        if stats.flag & LH_OVERFLOW_PAGE != 0 {
+            hash_bucket.overflow_pages += 1;
+            hash_bucket.overflow_bitmap.push(get_hash_bitmap_bit(client, next_page_id, &index_name).await?);
+        }
+        hash_bucket.live_items += stats.live_items as i64;
+        hash_bucket.dead_items += stats.dead_items as i64;
+        next_page_id = stats.next_block;
+        hash_bucket.pages.push(page);
+    }
+    Ok(hash_bucket)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::core::hash::generate_hash;
+    use crate::core::raw_page::INVALID_BLOCK_NUMBER;
+    use crate::db::{get_index_info, init_client, Client};
+    use super::get_hash_page;
+
+    fn setup_test_data(client: Arc<Mutex<Client>>) {
+        tear_down_test_data(Arc::clone(&client));
+        client.lock().unwrap().batch_execute(
+            "CREATE TABLE IF NOT EXISTS test_hash_table (
+            id SERIAL PRIMARY KEY,
+            code INTEGER NOT NULL
+        ) WITH (autovacuum_enabled = off);
+        CREATE INDEX IF NOT EXISTS idx_test_hash_code ON test_hash_table USING hash (code);
+        INSERT INTO test_hash_table(code)
+            SELECT CASE WHEN i % 2 = 0 THEN 7 ELSE i END FROM generate_series(1, 4000) i;"
+        ).unwrap();
+    }
+
+    fn tear_down_test_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_hash_table"
+        ).unwrap();
+    }
+
+    #[test]
+    pub fn test_get_hash_buckets() {
+        let client_ref = Arc::new(init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        setup_test_data(Arc::clone(&client_ref));
+        let index_name = "idx_test_hash_code".to_string();
+        let index_info = get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        assert_eq!("hash", index_info.index_type);
+
+        let hash = generate_hash(Arc::clone(&client_ref), index_name, Arc::new(index_info)).unwrap();
+        assert_eq!((hash.metadata_page.maxbucket + 1) as usize, hash.buckets.len());
+        let live_items: i64 = hash.buckets.iter().map(|bucket| bucket.live_items).sum();
+        assert_eq!(4000, live_items);
+        assert!(hash.buckets.iter().all(|bucket| bucket.pages[0].flags.contains(&"bucket".to_string())));
+
+        // every other row has the same key, so one bucket holds half the index in a long chain
+        let skewed = hash.buckets.iter().find(|bucket| bucket.skew == hash.max_skew).unwrap();
+        assert!(skewed.live_items >= 2000);
+        assert!(skewed.overflow_pages >= 4);
+        assert_eq!(hash.max_overflow_pages, skewed.overflow_pages);
+        assert!(skewed.overflow_bitmap.iter().all(|bit| bit.in_use));
+
+        // splits preallocate the bucket pages of a whole splitpoint phase, the ones past
+        // maxbucket stay unused until the buckets they are for are split into
+        let client = client_ref.lock().unwrap();
+        let unused: i64 = client.query_one(
+            "SELECT min(b) FROM generate_series(0, pg_relation_size('idx_test_hash_code') / current_setting('block_size')::int - 1) b \
+            WHERE hash_page_type(get_raw_page('idx_test_hash_code', b::int)) = 'unused'", &[],
+        ).unwrap().get(0);
+        let (page, stats) = client.block_on(get_hash_page(client.client(), unused, &"idx_test_hash_code".to_string())).unwrap();
+        assert_eq!(vec!["unused".to_string()], page.flags);
+        assert!(page.items.is_empty());
+        assert_eq!(INVALID_BLOCK_NUMBER, stats.next_block);
+        drop(client);
+        tear_down_test_data(Arc::clone(&client_ref));
+    }
+}
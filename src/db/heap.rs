@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use log::info;
+use crate::error::Result;
+use crate::core::structs::{HeapPage, HeapTuple, Tid};
+use crate::db::{Client, IndexInfo};
+
+const LP_UNUSED: i16 = 0;
+const LP_NORMAL: i16 = 1;
+const LP_REDIRECT: i16 = 2;
+const HEAP_HOT_UPDATED: &str = "HEAP_HOT_UPDATED";
+
+/// Number of blocks of the table the index is on.
+pub fn get_heap_blocks(client: Arc<Mutex<Client>>, index_info: &IndexInfo) -> Result<i64> {
+    let client = client.lock().unwrap();
+    client.block_on(get_heap_blocks_async(client.client(), index_info))
+}
+
+pub async fn get_heap_blocks_async(client: &tokio_postgres::Client, index_info: &IndexInfo) -> Result<i64> {
+    let heap_blocks_query = r#"
+        SELECT pg_relation_size($1::oid::regclass) / current_setting('block_size')::bigint;
+    "#;
+    let row = client.query_one(heap_blocks_query, &[&index_info.table_oid]).await?;
+    Ok(row.get(0))
+}
+
+/// Fetches a heap page of the table the index is on, with the infomask of every tuple decoded.
+/// `referenced` are the TIDs index items point to, which get flagged on the page.
+pub fn get_heap_page(client: Arc<Mutex<Client>>, block_number: i64, index_info: &IndexInfo, referenced: &BTreeSet<Tid>) -> Result<HeapPage> {
+    let client = client.lock().unwrap();
+    client.block_on(get_heap_page_async(client.client(), block_number, index_info, referenced))
+}
+
+pub async fn get_heap_page_async(client: &tokio_postgres::Client, block_number: i64, index_info: &IndexInfo, referenced: &BTreeSet<Tid>) -> Result<HeapPage> {
+    info!("getting heap page {}", block_number);
+    let heap_items_query = r#"
+        SELECT
+        h.lp,
+        h.lp_flags,
+        h.lp_off,
+        h.lp_len,
+        h.t_xmin::text,
+        h.t_xmax::text,
+        h.t_ctid,
+        f.raw_flags,
+        f.combined_flags
+        FROM heap_page_items(get_raw_page($1::oid::regclass::text, $2)) AS h
+        LEFT JOIN LATERAL heap_tuple_infomask_flags(h.t_infomask, h.t_infomask2) AS f ON true
+        ORDER BY h.lp;
+    "#;
+    let rows = client.query(heap_items_query, &[&index_info.table_oid, &block_number]).await?;
+    let tuples = rows.iter().map(|row| {
+        let offset: i16 = row.get(0);
+        let lp_flags: i16 = row.get(1);
+        let lp_off: i16 = row.get(2);
+        let ctid: Option<Tid> = row.get(6);
+        let flags: Vec<String> = row.get::<_, Option<Vec<String>>>(7).unwrap_or_default();
+        let self_tid = Tid { block_number: block_number as u32, offset_number: offset as u16 };
+        let hot_next = match &ctid {
+            Some(ctid) if flags.iter().any(|flag| flag == HEAP_HOT_UPDATED) && *ctid != self_tid => Some(ctid.clone()),
+            _ => None,
+        };
+        HeapTuple {
+            offset,
+            lp_state: match lp_flags {
+                LP_UNUSED => "unused",
+                LP_NORMAL => "normal",
+                LP_REDIRECT => "redirect",
+                _ => "dead",
+            }.to_string(),
+            redirect_to: if lp_flags == LP_REDIRECT { Some(lp_off) } else { None },
+            length: row.get(3),
+            xmin: row.get(4),
+            xmax: row.get(5),
+            ctid,
+            hot_next,
+            flags,
+            combined_flags: row.get::<_, Option<Vec<String>>>(8).unwrap_or_default(),
+            referenced: referenced.contains(&self_tid),
+        }
+    }).collect();
+    Ok(HeapPage { block_number, tuples })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::sync::{Arc, Mutex};
+    use crate::core::Tid;
+    use crate::db::{get_heap_blocks, get_heap_page, get_index_info, init_client, Client};
+
+    fn setup_test_data(client: Arc<Mutex<Client>>) {
+        tear_down_test_data(Arc::clone(&client));
+        client.lock().unwrap().batch_execute(
+            "CREATE TABLE IF NOT EXISTS test_heap_table (
+            id INTEGER NOT NULL,
+            counter INTEGER NOT NULL
+        ) WITH (autovacuum_enabled = off);
+        CREATE INDEX IF NOT EXISTS idx_test_heap_id ON test_heap_table (id);
+        INSERT INTO test_heap_table SELECT i, 0 FROM generate_series(1, 10) i;
+        UPDATE test_heap_table SET counter = 1 WHERE id = 1;"
+        ).unwrap();
+    }
+
+    fn tear_down_test_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_heap_table"
+        ).unwrap();
+    }
+
+    #[test]
+    pub fn test_get_heap_page() {
+        let client_ref = Arc::new(init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        setup_test_data(Arc::clone(&client_ref));
+        let index_info = get_index_info(Arc::clone(&client_ref), "idx_test_heap_id".to_string()).unwrap();
+        assert_eq!(1, get_heap_blocks(Arc::clone(&client_ref), &index_info).unwrap());
+
+        let referenced = BTreeSet::from([Tid { block_number: 0, offset_number: 1 }]);
+        let page = get_heap_page(Arc::clone(&client_ref), 0, &index_info, &referenced).unwrap();
+        assert_eq!(11, page.tuples.len());
+        let updated = &page.tuples[0];
+        assert!(updated.referenced);
+        assert!(updated.xmax.as_deref() != Some("0"));
+        // the counter isn't indexed, so the update is HOT and the index still points at the old version
+        assert!(updated.flags.contains(&"HEAP_HOT_UPDATED".to_string()));
+        assert_eq!(Some(Tid { block_number: 0, offset_number: 11 }), updated.hot_next);
+        assert!(page.tuples[10].flags.contains(&"HEAP_ONLY_TUPLE".to_string()));
+        assert!(page.tuples.iter().all(|tuple| tuple.lp_state == "normal"));
+        assert!(!page.tuples[1].referenced);
+        tear_down_test_data(Arc::clone(&client_ref));
+    }
+}
@@ -1,4 +1,34 @@
+//! Queries against a live server. Each of them is an `_async` function on a
+//! `tokio_postgres::Client`, for embedding in async code, with a blocking variant of the same
+//! name minus the suffix, which locks a `Client` and runs the query on that client's runtime.
+
+mod blocking;
+mod brin;
 mod client;
+mod extension;
+mod gin;
+mod gist;
+mod hash;
+mod heap;
+mod pool;
+mod relation_file;
+mod spgist;
 
-pub use client::{init_client, get_index_info, get_metadata_page, get_page};
+pub use blocking::Client;
+pub use client::{init_client, get_index_blocks, get_index_info, get_metadata_page, get_page, get_pages, get_raw_page, get_raw_pages, get_row};
+pub use client::{get_index_blocks_async, get_index_info_async, get_metadata_page_async, get_page_async, get_pages_async, get_raw_page_async, get_raw_pages_async, get_row_async};
 pub use client::IndexInfo;
+pub use extension::{check_pageinspect, check_pageinspect_async, pageinspect_functions};
+pub use gin::{get_gin_metadata_page, get_gin_pages, get_gin_pending_list};
+pub use gin::{get_gin_metadata_page_async, get_gin_pages_async, get_gin_pending_list_async};
+pub use gist::{get_gist_pages, get_gist_pages_async, SPLIT_NOT_IN_PARENT};
+pub use brin::{get_brin_heap_blocks, get_brin_metadata_page, get_brin_ranges};
+pub use brin::{get_brin_heap_blocks_async, get_brin_metadata_page_async, get_brin_ranges_async};
+pub use hash::{get_hash_bucket, get_hash_metadata_page};
+pub use hash::{get_hash_bucket_async, get_hash_metadata_page_async};
+pub use heap::{get_heap_blocks, get_heap_page};
+pub use heap::{get_heap_blocks_async, get_heap_page_async};
+pub use pool::Pool;
+pub use relation_file::{get_file_metadata_page, get_file_page, RelationFile};
+pub use spgist::{get_spgist_key_type, get_spgist_pages};
+pub use spgist::{get_spgist_key_type_async, get_spgist_pages_async};
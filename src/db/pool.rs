@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use crate::db::{init_client, Client};
+use crate::error::Result;
+
+/// A fixed number of connections to the same database. Pages of a level are split between them
+/// and fetched concurrently, one thread per connection.
+pub struct Pool {
+    clients: Vec<Arc<Mutex<Client>>>,
+}
+
+impl Pool {
+    pub fn connect(host: String, port: String, db: String, user: String, pass: String, size: usize) -> Result<Self> {
+        let clients = (0..size.max(1))
+            .map(|_| Ok(Arc::new(init_client(host.clone(), port.clone(), db.clone(), user.clone(), pass.clone())?)))
+            .collect::<Result<_>>()?;
+        Ok(Self { clients })
+    }
+
+    /// The first connection, for queries that aren't split.
+    pub fn client(&self) -> Arc<Mutex<Client>> {
+        Arc::clone(&self.clients[0])
+    }
+
+    /// Splits `requests` into one chunk per connection and runs `fetch` on the chunks
+    /// concurrently. The results are concatenated in the order of `requests`, or the first error
+    /// of a chunk is returned.
+    pub fn fetch<R: Sync, T: Send, F: Fn(Arc<Mutex<Client>>, &[R]) -> Result<Vec<T>> + Sync>(&self, requests: &[R], fetch: F) -> Result<Vec<T>> {
+        if self.clients.len() == 1 || requests.len() <= 1 {
+            return fetch(self.client(), requests);
+        }
+        let chunk_size = requests.len().div_ceil(self.clients.len());
+        thread::scope(|scope| {
+            let fetch = &fetch;
+            let handles: Vec<_> = requests.chunks(chunk_size).zip(self.clients.iter())
+                .map(|(chunk, client)| scope.spawn(move || fetch(Arc::clone(client), chunk)))
+                .collect();
+            let mut results = vec![];
+            for handle in handles {
+                results.extend(handle.join().unwrap()?);
+            }
+            Ok(results)
+        })
+    }
+}
+
+impl From<Arc<Mutex<Client>>> for Pool {
+    fn from(client: Arc<Mutex<Client>>) -> Self {
+        Self { clients: vec![client] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pool;
+
+    #[test]
+    pub fn test_fetch_keeps_request_order() {
+        let pool = Pool::connect(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(), 3,
+        ).unwrap();
+        assert_eq!(3, pool.clients.len());
+        let requests: Vec<i64> = (0..10).collect();
+        let results = pool.fetch(&requests, |client, chunk| {
+            let pid: i32 = client.lock().unwrap().query_one("SELECT pg_backend_pid()", &[]).unwrap().get(0);
+            Ok(chunk.iter().map(|request| (*request, pid)).collect())
+        }).unwrap();
+        assert_eq!(requests, results.iter().map(|(request, _)| *request).collect::<Vec<i64>>());
+        // chunks of 4, 4 and 2, each on its own connection
+        let mut pids: Vec<i32> = results.iter().map(|(_, pid)| *pid).collect();
+        pids.dedup();
+        assert_eq!(3, pids.len());
+    }
+}
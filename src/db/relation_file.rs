@@ -0,0 +1,214 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use log::info;
+use crate::core::btree::{decode_btree_page, decode_metadata_page, link_children, BTREE_METAPAGE};
+use crate::core::raw_page::{read_u16, PAGE_HEADER_SIZE};
+use crate::core::structs::{MetadataPage, Page};
+use crate::error::{Error, Result};
+
+/// Size of a segment file in a default build (`--with-segsize=1`).
+const DEFAULT_SEGMENT_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// The files of an index relation in a data directory: `<relfilenode>`, then `<relfilenode>.1`,
+/// `<relfilenode>.2`... once the relation outgrows a segment.
+#[derive(Debug)]
+pub struct RelationFile {
+    path: PathBuf,
+    block_size: u64,
+    blocks_per_segment: u64,
+}
+
+impl RelationFile {
+    /// Opens the relation whose first segment is at `path`. The block size is read from the
+    /// header of block 0, and the segment size from the first segment when there is a second one,
+    /// so clusters built with non default sizes are read correctly too.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut header = [0u8; PAGE_HEADER_SIZE];
+        File::open(path)?.read_exact(&mut header)?;
+        let block_size = (read_u16(&header, 18).unwrap() & 0xFF00) as u64;
+        if block_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} doesn't start with a valid page", path.display())));
+        }
+        let segment_size = if segment_path(path, 1).exists() {
+            fs::metadata(path)?.len()
+        } else {
+            DEFAULT_SEGMENT_SIZE
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            block_size,
+            blocks_per_segment: (segment_size / block_size).max(1),
+        })
+    }
+
+    /// Finds `base/<database oid>/<relfilenode>` in a data directory. Relations in other
+    /// tablespaces have to be opened by path.
+    pub fn find(data_dir: &Path, relfilenode: u32) -> io::Result<Self> {
+        let mut candidates: Vec<PathBuf> = fs::read_dir(data_dir.join("base"))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().join(relfilenode.to_string()))
+            .filter(|path| path.is_file())
+            .collect();
+        match candidates.len() {
+            1 => Self::open(&candidates.remove(0)),
+            0 => Err(io::Error::new(io::ErrorKind::NotFound, format!("no file for relfilenode {} under {}", relfilenode, data_dir.join("base").display()))),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "relfilenode {} exists in several databases, pass the file path instead: {}",
+                relfilenode,
+                candidates.iter().map(|path| path.display().to_string()).collect::<Vec<String>>().join(", ")
+            ))),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads block `block_number`, from whichever segment holds it.
+    pub fn read_block(&self, block_number: i64) -> io::Result<Vec<u8>> {
+        let block_number = block_number as u64;
+        let segment = block_number / self.blocks_per_segment;
+        let offset = (block_number % self.blocks_per_segment) * self.block_size;
+        let mut file = File::open(segment_path(&self.path, segment))?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut block = vec![0u8; self.block_size as usize];
+        file.read_exact(&mut block)?;
+        Ok(block)
+    }
+}
+
+fn segment_path(path: &Path, segment: u64) -> PathBuf {
+    if segment == 0 {
+        return path.to_path_buf();
+    }
+    let mut segment_path = path.as_os_str().to_os_string();
+    segment_path.push(format!(".{}", segment));
+    PathBuf::from(segment_path)
+}
+
+/// Reads block `block_number`, saying which block of which file couldn't be read on failure.
+fn read_block(relation: &RelationFile, block_number: i64) -> Result<Vec<u8>> {
+    relation.read_block(block_number).map_err(|e| {
+        Error::Io(io::Error::new(e.kind(), format!("Could not read block {} of {}: {}", block_number, relation.path().display(), e)))
+    })
+}
+
+/// The btree metapage of the relation, failing when block 0 isn't one.
+pub fn get_file_metadata_page(relation: &RelationFile) -> Result<MetadataPage> {
+    info!("Reading metadata page from: {}", relation.path().display());
+    decode_metadata_page(&read_block(relation, BTREE_METAPAGE)?).ok_or_else(|| Error::UnsupportedAccessMethod {
+        index: relation.path().display().to_string(),
+        access_method: "non-btree".to_string(),
+        reason: "block 0 isn't a btree metapage, only btree indexes can be read from files",
+    })
+}
+
+/// Reads a single btree page from the relation files, marking its downlinks as children to
+/// walk into. Without a server the heap can't be looked up, so item values stay the raw key bytes.
+pub fn get_file_page(relation: &RelationFile, page_id: i64) -> Result<Page> {
+    info!("reading page {}", page_id);
+    let raw = read_block(relation, page_id)?;
+    let mut page = decode_btree_page(page_id, &raw, &[]).ok_or_else(|| Error::UnreadablePage {
+        relation: relation.path().display().to_string(),
+        block_number: page_id,
+        reason: "isn't a btree page",
+    })?;
+    link_children(&mut page);
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use crate::db::Client;
+    use crate::core::btree::generate_btree_from_file;
+    use crate::db::init_client;
+    use crate::error::Error;
+    use super::RelationFile;
+
+    fn block(block_size: usize, marker: u8) -> Vec<u8> {
+        let mut block = vec![marker; block_size];
+        block[18..20].copy_from_slice(&(block_size as u16 | 4).to_le_bytes());
+        block
+    }
+
+    #[test]
+    pub fn test_read_block_across_segments() {
+        let dir = std::env::temp_dir().join(format!("relation_file_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("16384");
+        // two blocks per segment, block 2 is the first block of 16384.1
+        fs::write(&path, [block(4096, 0), block(4096, 1)].concat()).unwrap();
+        fs::write(dir.join("16384.1"), [block(4096, 2), block(4096, 3)].concat()).unwrap();
+
+        let relation = RelationFile::open(&path).unwrap();
+        assert_eq!(4096, relation.block_size);
+        assert_eq!(2, relation.blocks_per_segment);
+        assert_eq!(2, relation.read_block(2).unwrap()[100]);
+        assert_eq!(3, relation.read_block(3).unwrap()[100]);
+        assert!(relation.read_block(4).is_err());
+
+        fs::create_dir_all(dir.join("base/5")).unwrap();
+        fs::copy(&path, dir.join("base/5/16384")).unwrap();
+        assert_eq!(dir.join("base/5/16384"), RelationFile::find(&dir, 16384).unwrap().path());
+        assert!(RelationFile::find(&dir, 16385).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn test_generate_btree_from_file() {
+        let client_ref = Arc::new(init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        tear_down_test_data(Arc::clone(&client_ref));
+        client_ref.lock().unwrap().batch_execute(
+            "CREATE TABLE test_file_table (id INTEGER NOT NULL);
+            INSERT INTO test_file_table SELECT i FROM generate_series(1, 10000) i;
+            CREATE INDEX idx_test_file_id ON test_file_table (id);
+            CHECKPOINT;"
+        ).unwrap();
+        let row = client_ref.lock().unwrap().query_one(
+            "SELECT current_setting('data_directory') || '/' || pg_relation_filepath('idx_test_file_id')", &[],
+        ).unwrap();
+        let path: String = row.get(0);
+
+        let relation = RelationFile::open(Path::new(&path)).unwrap();
+        let tree = generate_btree_from_file(&relation, "idx_test_file_id".to_string()).unwrap();
+        assert!(tree.root().is_root);
+        assert_eq!(1, tree.root().level);
+        let leaf_items: usize = tree.pages.children(tree.root()).map(|child| child.items.len()).sum();
+        assert_eq!(10000, leaf_items);
+
+        // a segment cut short after the metapage, as left by an interrupted copy
+        let dir = std::env::temp_dir().join(format!("relation_file_truncated_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let truncated = dir.join("16384");
+        fs::write(&truncated, &fs::read(&path).unwrap()[..relation.block_size as usize]).unwrap();
+        tear_down_test_data(Arc::clone(&client_ref));
+        let error = generate_btree_from_file(&RelationFile::open(&truncated).unwrap(), "idx_test_file_id".to_string()).unwrap_err();
+        assert!(matches!(error, Error::Io(_)));
+        assert!(error.to_string().starts_with(&format!("Could not read block {} of {}", tree.root, truncated.display())));
+
+        // a block zeroed by a torn write, past an intact metapage
+        let block_size = relation.block_size as usize;
+        let mut zeroed = fs::read(&truncated).unwrap();
+        zeroed.resize(block_size * (tree.root as usize + 1), 0);
+        fs::write(&truncated, zeroed).unwrap();
+        let error = generate_btree_from_file(&RelationFile::open(&truncated).unwrap(), "idx_test_file_id".to_string()).unwrap_err();
+        assert!(matches!(error, Error::UnreadablePage { block_number, .. } if block_number == tree.root));
+
+        fs::write(&truncated, block(4096, 0)).unwrap();
+        let error = generate_btree_from_file(&RelationFile::open(&truncated).unwrap(), "idx_test_file_id".to_string()).unwrap_err();
+        assert!(matches!(error, Error::UnsupportedAccessMethod { .. }));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn tear_down_test_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_file_table"
+        ).unwrap();
+    }
+}
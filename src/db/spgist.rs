@@ -0,0 +1,156 @@
+use std::sync::{Arc, Mutex};
+use log::info;
+use crate::error::Result;
+use crate::core::raw_page::RawPage;
+use crate::core::spgist::{decode_spgist_tuple, spgist_page_flags, SpGistKeyType, SpGistTuple, SPGIST_ROOT_BLKNO};
+use crate::core::structs::{Item, Page};
+use crate::db::{get_raw_pages_async, Client};
+
+/// Name of the type stored in the index, which decides how prefixes, labels and leaf datums
+/// are decoded.
+pub fn get_spgist_key_type(client: Arc<Mutex<Client>>, index_name: String) -> Result<String> {
+    let client = client.lock().unwrap();
+    client.block_on(get_spgist_key_type_async(client.client(), index_name))
+}
+
+pub async fn get_spgist_key_type_async(client: &tokio_postgres::Client, index_name: String) -> Result<String> {
+    let key_type_query = r#"
+        SELECT format_type(atttypid, NULL)
+        FROM pg_attribute
+        WHERE attrelid = $1::text::regclass AND attnum = 1;
+    "#;
+    let row = client.query_one(key_type_query, &[&index_name]).await?;
+    Ok(row.get(0))
+}
+
+/// Fetches SP-GiST pages in one query, the blocks their node downlinks and redirects point to
+/// set as the items' `child`. SP-GiST links tuples rather than pages, so several nodes (or tuples
+/// on the same page) can point to the same block, which the walk only descends into once.
+pub fn get_spgist_pages(client: Arc<Mutex<Client>>, page_ids: &[i64], index_name: &String, key_type: &SpGistKeyType) -> Result<Vec<Page>> {
+    let client = client.lock().unwrap();
+    client.block_on(get_spgist_pages_async(client.client(), page_ids, index_name, key_type))
+}
+
+pub async fn get_spgist_pages_async(client: &tokio_postgres::Client, page_ids: &[i64], index_name: &String, key_type: &SpGistKeyType) -> Result<Vec<Page>> {
+    info!("getting {} SP-GiST pages", page_ids.len());
+    let raw_pages = get_raw_pages_async(client, index_name, page_ids).await?;
+    Ok(page_ids.iter().zip(raw_pages.iter())
+        .map(|(page_id, raw)| spgist_page(*page_id, raw, key_type))
+        .collect())
+}
+
+fn spgist_page(page_id: i64, raw: &[u8], key_type: &SpGistKeyType) -> Page {
+    let raw_page = match RawPage::new(raw) {
+        Some(raw_page) => raw_page,
+        None => return Page::new(page_id, 0, true, page_id == SPGIST_ROOT_BLKNO, 0, 0),
+    };
+    let (flags, is_leaf) = spgist_page_flags(raw_page.special());
+    let mut page = Page::new(page_id, 0, is_leaf, page_id == SPGIST_ROOT_BLKNO, 0, 0);
+    page.prev_page_id = None;
+    page.next_page_id = None;
+    page.flags = flags;
+
+    info!("decoding SP-GiST tuples of page {}", page_id);
+    let line_pointers = raw_page.line_pointers();
+    page.nb_items = Some(line_pointers.len() as i32);
+    for pointer in line_pointers.iter() {
+        let tuple = match raw_page.item(pointer).and_then(|bytes| decode_spgist_tuple(bytes, is_leaf, key_type)) {
+            Some(tuple) => tuple,
+            None => continue,
+        };
+        match tuple {
+            SpGistTuple::Inner { prefix, nodes, .. } => {
+                for (i, node) in nodes.into_iter().enumerate() {
+                    let label = node.label.unwrap_or_else(|| format!("node {}", i));
+                    let value = match &prefix {
+                        Some(prefix) => format!("{} [{}]", prefix, label),
+                        None => label,
+                    };
+                    let pointer = node.downlink.as_ref().map(|downlink| downlink.block_number as i64);
+                    page.items.push(Item::new(value, pointer, pointer, node.downlink));
+                }
+            }
+            SpGistTuple::Leaf { value, heap_tid, next_offset } => {
+                let mut item = Item::new(value, None, Some(heap_tid.block_number as i64), Some(heap_tid));
+                item.next_offset = if next_offset == 0 { None } else { Some(next_offset as i64) };
+                page.items.push(item);
+            }
+            SpGistTuple::Dead { state, redirect } => {
+                let pointer = redirect.as_ref().map(|target| target.block_number as i64);
+                let mut item = Item::new(String::new(), pointer, pointer, redirect);
+                item.state = Some(state);
+                page.items.push(item);
+            }
+        }
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::core::spgist::generate_spgist;
+    use crate::core::Tree;
+    use crate::db::{get_index_info, init_client, Client, Pool};
+
+    fn setup_test_data(client: Arc<Mutex<Client>>) {
+        tear_down_test_data(Arc::clone(&client));
+        client.lock().unwrap().batch_execute(
+            "CREATE TABLE IF NOT EXISTS test_spgist_table (
+            id SERIAL PRIMARY KEY,
+            name TEXT NOT NULL,
+            address INET
+        ) WITH (autovacuum_enabled = off);
+        INSERT INTO test_spgist_table(name, address)
+            SELECT 'customer_' || i, ('10.' || i / 256 % 256 || '.' || i % 256 || '.1')::inet FROM generate_series(1, 5000) i;
+        INSERT INTO test_spgist_table(name, address)
+            SELECT 'customer_' || i, NULL FROM generate_series(5001, 5010) i;
+        CREATE INDEX IF NOT EXISTS idx_test_spgist_name ON test_spgist_table USING spgist (name);
+        CREATE INDEX IF NOT EXISTS idx_test_spgist_address ON test_spgist_table USING spgist (address);"
+        ).unwrap();
+    }
+
+    fn tear_down_test_data(client: Arc<Mutex<Client>>) {
+        client.lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_spgist_table"
+        ).unwrap();
+    }
+
+    fn leaf_values(tree: &Tree) -> Vec<String> {
+        tree.pages.iter()
+            .filter(|page| page.is_leaf)
+            .flat_map(|page| page.items.iter().filter(|item| item.state.is_none()).map(|item| item.value.clone()))
+            .collect()
+    }
+
+    #[test]
+    pub fn test_get_spgist_trees() {
+        let client_ref = Arc::new(init_client(
+            "localhost".to_string(), "5432".to_string(), "postgres".to_string(), "postgres".to_string(), "".to_string(),
+        ).unwrap());
+        setup_test_data(Arc::clone(&client_ref));
+
+        let index_name = "idx_test_spgist_name".to_string();
+        let index_info = get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        assert_eq!("spgist", index_info.index_type);
+        let tree = generate_spgist(&Pool::from(Arc::clone(&client_ref)), index_name, Arc::new(index_info)).unwrap();
+        assert!(tree.root().is_root);
+        assert!(!tree.root().is_leaf);
+        // every name shares the same start, which ends up in the root prefix
+        assert!(tree.root().items[0].value.starts_with("customer_"));
+        assert_eq!(5010, leaf_values(&tree).len());
+
+        let index_name = "idx_test_spgist_address".to_string();
+        let index_info = get_index_info(Arc::clone(&client_ref), index_name.clone()).unwrap();
+        let tree = generate_spgist(&Pool::from(Arc::clone(&client_ref)), index_name, Arc::new(index_info)).unwrap();
+        assert!(!tree.root().is_leaf);
+        let addresses = leaf_values(&tree);
+        assert_eq!(5010, addresses.len());
+        assert!(addresses.contains(&"10.0.1.1".to_string()));
+        // NULL keys are kept in their own tree
+        let nulls_root = tree.pages.get(tree.nulls_root.unwrap()).unwrap();
+        assert!(nulls_root.flags.contains(&"nulls".to_string()));
+        assert_eq!(10, addresses.iter().filter(|address| *address == "NULL").count());
+        tear_down_test_data(Arc::clone(&client_ref));
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::io;
+use tokio_postgres::error::SqlState;
+
+/// Everything that can stop an inspection, each with the exit code the command line ends with.
+#[derive(Debug)]
+pub enum Error {
+    /// The server couldn't be reached or didn't accept the credentials.
+    Connection(tokio_postgres::Error),
+    /// A function the inspection relies on is missing, because `pageinspect` isn't installed in
+    /// the database or is too old for the server.
+    MissingExtension(tokio_postgres::Error),
+    /// `pageinspect` isn't installed in `database`, `available` telling whether the server has it
+    /// to install.
+    ExtensionNotInstalled { database: String, available: bool },
+    /// The installed `pageinspect` is too old to have `functions`.
+    ExtensionOutdated { version: String, functions: Vec<String> },
+    /// The user isn't allowed to run `pageinspect` functions or to read the table.
+    PermissionDenied(tokio_postgres::Error),
+    /// `user` isn't a superuser, which every one of the pageinspect `functions` checks for itself
+    /// whatever `EXECUTE` grants say.
+    NotPrivileged { user: String, functions: Vec<String> },
+    UnknownIndex(String),
+    /// The index is of an access method the requested output doesn't handle, `reason` saying
+    /// which ones it does.
+    UnsupportedAccessMethod { index: String, access_method: String, reason: &'static str },
+    /// A btree index without any page besides its metapage, which has no tree to show yet.
+    EmptyIndex(String),
+    /// Block `block_number` of `relation` doesn't decode as the page it should be, `reason`
+    /// saying what was expected. Zeroed, torn or otherwise corrupted blocks end up here.
+    UnreadablePage { relation: String, block_number: i64, reason: &'static str },
+    /// Any other error reported by the server.
+    Query(tokio_postgres::Error),
+    Template(handlebars::RenderError),
+    Json(serde_json::Error),
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Distinct for every kind of error so scripts can tell them apart. 1 is left to panics and 2
+    /// to invalid arguments, which clap exits with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Connection(_) => 3,
+            Error::MissingExtension(_) | Error::ExtensionNotInstalled { .. } | Error::ExtensionOutdated { .. } => 4,
+            Error::PermissionDenied(_) | Error::NotPrivileged { .. } => 5,
+            Error::UnknownIndex(_) => 6,
+            Error::UnsupportedAccessMethod { .. } => 7,
+            Error::EmptyIndex(_) => 8,
+            Error::Query(_) => 9,
+            Error::Template(_) => 10,
+            Error::Json(_) => 11,
+            Error::Io(_) => 12,
+            Error::UnreadablePage { .. } => 13,
+        }
+    }
+}
+
+/// The server's own message when there is one, rather than the generic `db error: ...`.
+fn server_message(error: &tokio_postgres::Error) -> String {
+    match error.as_db_error() {
+        Some(db_error) => db_error.message().to_string(),
+        None => error.to_string(),
+    }
+}
+
+/// Connection errors only say `error connecting to server`, the reason is in their source.
+fn connection_message(error: &tokio_postgres::Error) -> String {
+    match std::error::Error::source(error) {
+        Some(source) => format!("{}: {}", error, source),
+        None => error.to_string(),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connection(e) => write!(f, "Could not connect to the database, {}. Check --host, --port, --db, --user and --password", connection_message(e)),
+            Error::MissingExtension(e) => write!(f, "{}. Is pageinspect installed? Run CREATE EXTENSION pageinspect in the database, \
+                or ALTER EXTENSION pageinspect UPDATE if it is outdated", server_message(e)),
+            Error::ExtensionNotInstalled { database, available: true } => write!(f, "The pageinspect extension isn't installed in {}. \
+                Run CREATE EXTENSION pageinspect in it, or pass --create-extension", database),
+            Error::ExtensionNotInstalled { available: false, .. } => write!(f, "The pageinspect extension isn't available on the server. \
+                Install the contrib package of your PostgreSQL distribution, then run CREATE EXTENSION pageinspect"),
+            Error::ExtensionOutdated { version, functions } => write!(f, "pageinspect {} has no {}. Run ALTER EXTENSION pageinspect UPDATE",
+                version, functions.join(", ")),
+            Error::PermissionDenied(e) => write!(f, "{}. pageinspect functions can only be run by superusers, connect as one with --user", server_message(e)),
+            Error::NotPrivileged { user, functions } => write!(f, "{} is not a superuser, which pageinspect requires to run {}. \
+                Connect as a superuser with --user", user, functions.join(", ")),
+            Error::UnknownIndex(index) => write!(f, "There is no index named {} in the database, check --index and --db", index),
+            Error::UnsupportedAccessMethod { index, access_method, reason } => write!(f, "{} is a {} index, {}", index, access_method, reason),
+            Error::EmptyIndex(index) => write!(f, "{} is empty, its root page isn't set", index),
+            Error::Query(e) => write!(f, "Query failed: {}", server_message(e)),
+            Error::Template(e) => write!(f, "Could not render the HTML output: {}", e),
+            Error::Json(e) => write!(f, "Could not serialize the output: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::UnreadablePage { relation, block_number, reason } => write!(f, "Block {} of {} {}, it may be zeroed or corrupted",
+                block_number, relation, reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connection(e) | Error::MissingExtension(e) | Error::PermissionDenied(e) | Error::Query(e) => Some(e),
+            Error::Template(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::ExtensionNotInstalled { .. } | Error::ExtensionOutdated { .. } | Error::NotPrivileged { .. } => None,
+            Error::UnknownIndex(_) | Error::UnsupportedAccessMethod { .. } | Error::EmptyIndex(_) => None,
+            Error::UnreadablePage { .. } => None,
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for Error {
+    fn from(error: tokio_postgres::Error) -> Self {
+        match error.code() {
+            Some(&SqlState::UNDEFINED_FUNCTION) => Error::MissingExtension(error),
+            Some(&SqlState::INSUFFICIENT_PRIVILEGE) => Error::PermissionDenied(error),
+            _ => Error::Query(error),
+        }
+    }
+}
+
+impl From<handlebars::RenderError> for Error {
+    fn from(error: handlebars::RenderError) -> Self {
+        Error::Template(error)
+    }
+}
+
+impl From<handlebars::TemplateError> for Error {
+    fn from(error: handlebars::TemplateError) -> Self {
+        Error::Template(error.into())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+
+    #[test]
+    pub fn test_exit_codes_are_distinct() {
+        let errors = [
+            Error::UnknownIndex("i".to_string()),
+            Error::UnsupportedAccessMethod { index: "i".to_string(), access_method: "bloom".to_string(), reason: "" },
+            Error::EmptyIndex("i".to_string()),
+            Error::Json(serde_json::from_str::<i32>("").unwrap_err()),
+            Error::Io(std::io::Error::other("")),
+            Error::UnreadablePage { relation: "i".to_string(), block_number: 1, reason: "" },
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(Error::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(errors.len(), codes.len());
+        assert!(codes.iter().all(|code| *code > 2));
+    }
+}
@@ -0,0 +1,115 @@
+//! Inspects the pages of Postgres indexes through the `pageinspect` extension.
+//!
+//! ```no_run
+//! # fn main() -> pg_index_inspector::Result<()> {
+//! let inspector = pg_index_inspector::connect("localhost", "5432", "postgres", "postgres", "")?;
+//! let tree = inspector.inspect_btree("users_pkey")?;
+//! let file = std::fs::File::create("users_pkey.html")?;
+//! pg_index_inspector::render_html(&tree, file)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The per-page queries behind it, blocking and async, are in [`db`].
+
+mod core;
+mod error;
+pub mod db;
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use crate::core::btree::generate_btree;
+use crate::core::heap::with_heap_rows;
+use crate::core::renderer::render_tree_html;
+use crate::db::{check_pageinspect, get_index_info, init_client, pageinspect_functions, Pool};
+
+pub use crate::core::arena::PageArena;
+/// The command line glue of the `pg_index_inspector` binary, not part of the library's API.
+#[doc(hidden)]
+pub use crate::core::handle_command_call;
+pub use crate::core::spgist::SpGistKeyType;
+pub use crate::core::structs::{BoundingBox, GinMetadataPage, HeapPage, HeapTuple, Item, MetadataPage, Page, RowData, Tid, Tree};
+pub use crate::core::structs::{BrinMetadataPage, BrinRange, BrinSummary, HashBitmapBit, HashBucket, HashMetadataPage};
+pub use crate::db::{Client, IndexInfo};
+pub use crate::error::{Error, Result};
+
+/// A connection to the database holding the indexes to inspect.
+pub struct Inspector {
+    pool: Pool,
+}
+
+/// Connects to the database, with an empty `user` or `password` leaving it to libpq defaults.
+pub fn connect(host: &str, port: &str, db: &str, user: &str, password: &str) -> Result<Inspector> {
+    let client = init_client(host.to_string(), port.to_string(), db.to_string(), user.to_string(), password.to_string())?;
+    Ok(Inspector { pool: Pool::from(Arc::new(client)) })
+}
+
+impl Inspector {
+    /// The underlying connection, for the functions of [`db`].
+    pub fn client(&self) -> Arc<Mutex<Client>> {
+        self.pool.client()
+    }
+
+    /// Reads every page of a btree index, with keys decoded from the index tuples only: neither
+    /// heap pages nor the rows leaf items point to are read, see [`Inspector::resolve_heap_rows`].
+    /// Fails with [`Error::UnsupportedAccessMethod`] if `index` isn't a
+    /// btree index, and with one of the extension or privilege errors if `pageinspect` can't be
+    /// used.
+    pub fn inspect_btree(&self, index: &str) -> Result<Tree> {
+        let index_info = get_index_info(self.client(), index.to_string())?;
+        if index_info.index_type != "btree" {
+            return Err(Error::UnsupportedAccessMethod {
+                index: index.to_string(),
+                access_method: index_info.index_type,
+                reason: "only btree indexes can be inspected",
+            });
+        }
+        check_pageinspect(self.client(), &pageinspect_functions("btree", false), false)?;
+        generate_btree(&self.pool, index.to_string(), Arc::new(index_info))
+    }
+
+    /// Looks up the table rows the leaf items of a btree `tree` point to, as `--resolve-heap`
+    /// does, `batch_size` TIDs per query and at most `max_rows` of them. Items whose row the
+    /// query's snapshot doesn't see get `row_visible` false.
+    pub fn resolve_heap_rows(&self, tree: Tree, batch_size: usize, max_rows: usize) -> Result<Tree> {
+        let index_info = get_index_info(self.client(), tree.index_name.clone())?;
+        with_heap_rows(self.client(), tree, Arc::new(index_info), batch_size, max_rows)
+    }
+}
+
+/// Writes the HTML page the command line renders for `tree`.
+pub fn render_html<W: Write>(tree: &Tree, mut writer: W) -> Result<()> {
+    writer.write_all(render_tree_html(tree)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{connect, render_html};
+
+    #[test]
+    pub fn test_inspect_btree() {
+        let inspector = connect("localhost", "5432", "postgres", "postgres", "").unwrap();
+        inspector.client().lock().unwrap().batch_execute(
+            "DROP TABLE IF EXISTS test_lib_table;
+            CREATE TABLE test_lib_table (id INTEGER PRIMARY KEY);
+            INSERT INTO test_lib_table SELECT generate_series(1, 1000);"
+        ).unwrap();
+        let tree = inspector.inspect_btree("test_lib_table_pkey").unwrap();
+        assert_eq!(1, tree.metadata_page.as_ref().unwrap().level);
+        let leaves = tree.pages.iter().filter(|page| page.is_leaf).count();
+        assert_eq!(tree.root().items.len(), leaves);
+        assert!(tree.pages.iter().flat_map(|page| page.items.iter()).all(|item| item.row.is_none()));
+
+        let mut html: Vec<u8> = vec![];
+        render_html(&tree, &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("test_lib_table_pkey"));
+        assert_eq!(leaves + 1, html.matches("id=\"page-").count());
+
+        let tree = inspector.resolve_heap_rows(tree, 100, 10).unwrap();
+        let rows = tree.pages.iter().flat_map(|page| page.items.iter()).filter(|item| item.row.is_some()).count();
+        assert_eq!(10, rows);
+        inspector.client().lock().unwrap().batch_execute("DROP TABLE test_lib_table").unwrap();
+    }
+}
@@ -1,8 +1,8 @@
-mod core;
-mod db;
-
-use crate::core::handle_command_call;
+use pg_index_inspector::handle_command_call;
 
 fn main() {
-    handle_command_call();
-}
\ No newline at end of file
+    if let Err(e) = handle_command_call() {
+        eprintln!("{}", e);
+        std::process::exit(e.exit_code());
+    }
+}